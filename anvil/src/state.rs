@@ -112,7 +112,7 @@ impl AnvilState {
                 DataDeviceEvent::DnDStarted { icon, .. } => {
                     *dnd_icon2.lock().unwrap() = icon;
                 }
-                DataDeviceEvent::DnDDropped => {
+                DataDeviceEvent::DnDDropped | DataDeviceEvent::DnDCancelled => {
                     *dnd_icon2.lock().unwrap() = None;
                 }
                 _ => {}
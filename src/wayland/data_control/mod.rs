@@ -0,0 +1,568 @@
+//! Utilities for giving clipboard managers access to the selections
+//!
+//! This module handles the `zwlr_data_control_manager_v1` protocol extension. It lets privileged
+//! clients, like clipboard managers, be notified of every change of the clipboard and of the
+//! primary selection of a seat, read them, and set them, without having the keyboard focus as
+//! the [`data_device`](::wayland::data_device) and
+//! [`primary_selection`](::wayland::primary_selection) protocols require.
+//!
+//! The selections are shared with these two modules: a selection set by a clipboard manager is
+//! advertized to all other clients, and any change of the selections, including the ones made by
+//! your compositor with [`set_data_device_selection`](::wayland::data_device::set_data_device_selection)
+//! or [`set_primary_selection`](::wayland::primary_selection::set_primary_selection), is sent to
+//! the clipboard managers.
+//!
+//! As this gives access to everything the user copies, you should only expose this global to
+//! clients you trust, for example using a filter on the globals of your display.
+//!
+//! ## Initialization
+//!
+//! ```
+//! # extern crate wayland_server;
+//! # extern crate smithay;
+//! use smithay::wayland::data_control::{init_data_control, DataControlEvent};
+//!
+//! # let mut display = wayland_server::Display::new();
+//! // init the data control global:
+//! init_data_control(
+//!     &mut display, // the display
+//!     |event| match event {
+//!         // a clipboard manager reads a selection set by the compositor, write its
+//!         // contents into the fd, as for the `SendSelection` events of the data device
+//!         DataControlEvent::SendSelection { mime_type, fd } => { /* ... */ }
+//!         DataControlEvent::SendPrimarySelection { mime_type, fd } => { /* ... */ }
+//!     },
+//!     None          // insert a logger here
+//! );
+//! ```
+
+use std::{cell::RefCell, ops::Deref as _, os::unix::io::RawFd, rc::Rc};
+
+use wayland_protocols::wlr::unstable::data_control::v1::server::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::{self, ZwlrDataControlManagerV1},
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+    zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+use wayland_server::{Client, Display, Filter, Global, Main};
+
+use crate::wayland::data_device::{
+    add_selection_listener, clear_data_device_selection, install_remote_selection, selection_snapshot,
+    selections_equal, SelectionSnapshot,
+};
+use crate::wayland::primary_selection::{
+    add_primary_selection_listener, clear_primary_selection, primary_selection_snapshot,
+    set_forwarded_primary_selection, PrimaryForward, PrimarySnapshot,
+};
+use crate::wayland::seat::Seat;
+
+/// Events generated by clipboard managers reading the selections set by the compositor
+pub enum DataControlEvent {
+    /// A clipboard manager requested to read the compositor-set selection
+    ///
+    /// The selection was set with
+    /// [`set_data_device_selection`](::wayland::data_device::set_data_device_selection).
+    SendSelection {
+        /// the requested mime type
+        mime_type: String,
+        /// the fd to write into
+        fd: RawFd,
+    },
+    /// A clipboard manager requested to read the compositor-set primary selection
+    ///
+    /// The primary selection was set with
+    /// [`set_primary_selection`](::wayland::primary_selection::set_primary_selection).
+    SendPrimarySelection {
+        /// the requested mime type
+        mime_type: String,
+        /// the fd to write into
+        fd: RawFd,
+    },
+}
+
+// A selection advertised to a clipboard manager, that its offer reads even once it is replaced
+#[derive(Clone)]
+enum OfferedSelection {
+    Clipboard(SelectionSnapshot),
+    Primary(PrimarySnapshot),
+}
+
+impl OfferedSelection {
+    // the mime types of the selection, `None` if it is empty
+    fn mime_types(&self) -> Option<Vec<String>> {
+        match *self {
+            OfferedSelection::Clipboard(ref selection) => {
+                Some(selection.mime_types().to_vec()).filter(|_| !selection.is_empty())
+            }
+            OfferedSelection::Primary(ref selection) => selection.mime_types(),
+        }
+    }
+}
+
+// The data control state of a seat
+struct SeatData {
+    known_devices: Vec<ZwlrDataControlDeviceV1>,
+    // the source set as the clipboard by a clipboard manager, along with the selection it
+    // became, to cancel it once it is replaced
+    clipboard_source: Option<(ZwlrDataControlSourceV1, SelectionSnapshot)>,
+    // the source set as the primary selection by a clipboard manager, along with its forward
+    primary_source: Option<(ZwlrDataControlSourceV1, Rc<PrimaryForward>)>,
+    log: ::slog::Logger,
+}
+
+impl SeatData {
+    fn clipboard_changed(&mut self, selection: &SelectionSnapshot) {
+        self.known_devices.retain(|device| device.as_ref().is_alive());
+        let replaced = match self.clipboard_source {
+            Some((_, ref snapshot)) => !selections_equal(snapshot, selection),
+            None => false,
+        };
+        if replaced {
+            if let Some((source, _)) = self.clipboard_source.take() {
+                source.cancelled();
+            }
+        }
+        let selection = OfferedSelection::Clipboard(selection.clone());
+        for device in &self.known_devices {
+            send_selection_to(device, &selection, &self.log);
+        }
+    }
+
+    fn primary_changed(&mut self, selection: &PrimarySnapshot) {
+        self.known_devices.retain(|device| device.as_ref().is_alive());
+        let replaced = match (&self.primary_source, selection.forward()) {
+            (&Some((_, ref own)), Some(forward)) => !Rc::ptr_eq(own, forward),
+            (&Some(_), None) => true,
+            (&None, _) => false,
+        };
+        if replaced {
+            if let Some((source, _)) = self.primary_source.take() {
+                source.cancelled();
+            }
+        }
+        let selection = OfferedSelection::Primary(selection.clone());
+        for device in &self.known_devices {
+            send_selection_to(device, &selection, &self.log);
+        }
+    }
+}
+
+struct DeviceData {
+    callback: Rc<RefCell<dyn FnMut(DataControlEvent) + 'static>>,
+}
+
+// Access the data control state of a seat, initializing it if needed
+fn seat_data(seat: &Seat) -> Rc<RefCell<SeatData>> {
+    if let Some(seat_data) = seat.user_data().get::<Rc<RefCell<SeatData>>>() {
+        return seat_data.clone();
+    }
+    let seat_data = Rc::new(RefCell::new(SeatData {
+        known_devices: Vec::new(),
+        clipboard_source: None,
+        primary_source: None,
+        log: seat.arc.log.new(o!("smithay_module" => "data_control_mgr")),
+    }));
+    seat.user_data().set(|| seat_data.clone());
+    // follow the changes of the selections
+    let clipboard_data = seat_data.clone();
+    add_selection_listener(
+        seat,
+        Rc::new(move |selection: &SelectionSnapshot| {
+            clipboard_data.borrow_mut().clipboard_changed(selection)
+        }),
+    );
+    let primary_data = seat_data.clone();
+    add_primary_selection_listener(
+        seat,
+        Rc::new(move |selection: &PrimarySnapshot| primary_data.borrow_mut().primary_changed(selection)),
+    );
+    seat_data
+}
+
+/// Initialize the data control global
+///
+/// You need to provide a callback closure, which will be requested to provide the contents
+/// of the selections set by your compositor when a clipboard manager reads them. See the
+/// [`DataControlEvent`] type for details.
+pub fn init_data_control<C, L>(
+    display: &mut Display,
+    callback: C,
+    logger: L,
+) -> Global<ZwlrDataControlManagerV1>
+where
+    C: FnMut(DataControlEvent) + 'static,
+    L: Into<Option<::slog::Logger>>,
+{
+    let log = crate::slog_or_fallback(logger).new(o!("smithay_module" => "data_control_mgr"));
+    let callback = Rc::new(RefCell::new(callback));
+    display.create_global(
+        2,
+        Filter::new(move |(manager, _version), _, _| {
+            implement_manager(manager, callback.clone(), log.clone());
+        }),
+    )
+}
+
+fn implement_manager<C>(
+    manager: Main<ZwlrDataControlManagerV1>,
+    callback: Rc<RefCell<C>>,
+    log: ::slog::Logger,
+) -> ZwlrDataControlManagerV1
+where
+    C: FnMut(DataControlEvent) + 'static,
+{
+    use self::zwlr_data_control_manager_v1::Request;
+    manager.quick_assign(move |_manager, req, _| match req {
+        Request::CreateDataSource { id } => {
+            implement_source(id);
+        }
+        Request::GetDataDevice { id, seat } => match Seat::from_resource(&seat) {
+            Some(seat) => {
+                let device = implement_device(id, seat.clone(), callback.clone());
+                let seat_data = seat_data(&seat);
+                seat_data.borrow_mut().known_devices.push(device.clone());
+                // the current selections are sent to new devices right away
+                let selection = OfferedSelection::Clipboard(selection_snapshot(&seat));
+                send_selection_to(&device, &selection, &log);
+                let primary = OfferedSelection::Primary(primary_selection_snapshot(&seat));
+                send_selection_to(&device, &primary, &log);
+            }
+            None => {
+                error!(log, "Unmanaged seat given to a data control device.");
+            }
+        },
+        Request::Destroy => {}
+        _ => unreachable!(),
+    });
+
+    manager.deref().clone()
+}
+
+fn implement_device<C>(
+    device: Main<ZwlrDataControlDeviceV1>,
+    seat: Seat,
+    callback: Rc<RefCell<C>>,
+) -> ZwlrDataControlDeviceV1
+where
+    C: FnMut(DataControlEvent) + 'static,
+{
+    use self::zwlr_data_control_device_v1::Request;
+    let device_data = DeviceData { callback };
+    device.quick_assign(move |device, req, _| match req {
+        Request::SetSelection { source } => {
+            if let Some(ref source) = source {
+                if !use_source(source, &device) {
+                    return;
+                }
+            }
+            // no keyboard focus is required, clipboard managers are trusted clients
+            match source {
+                Some(source) => {
+                    let mime_types = source_mime_types(&source);
+                    let forwarded = source.clone();
+                    install_remote_selection(&seat, mime_types, move |mime_type, fd| {
+                        if forwarded.as_ref().is_alive() {
+                            forwarded.send(mime_type, fd);
+                        }
+                        let _ = ::nix::unistd::close(fd);
+                    });
+                    // recorded once it is the selection, not to be cancelled right away
+                    seat_data(&seat).borrow_mut().clipboard_source =
+                        Some((source, selection_snapshot(&seat)));
+                }
+                None => clear_data_device_selection(&seat),
+            }
+        }
+        Request::SetPrimarySelection { source } => {
+            if let Some(ref source) = source {
+                if !use_source(source, &device) {
+                    return;
+                }
+            }
+            match source {
+                Some(source) => {
+                    let mime_types = source_mime_types(&source);
+                    let forwarded = source.clone();
+                    let forward: Rc<PrimaryForward> =
+                        Rc::new(RefCell::new(move |mime_type: String, fd: RawFd| {
+                            if forwarded.as_ref().is_alive() {
+                                forwarded.send(mime_type, fd);
+                            }
+                            let _ = ::nix::unistd::close(fd);
+                        }));
+                    set_forwarded_primary_selection(&seat, mime_types, forward.clone());
+                    seat_data(&seat).borrow_mut().primary_source = Some((source, forward));
+                }
+                None => clear_primary_selection(&seat),
+            }
+        }
+        Request::Destroy => {
+            // Clean up the known devices
+            seat_data(&seat)
+                .borrow_mut()
+                .known_devices
+                .retain(|ndd| ndd.as_ref().is_alive() && (!ndd.as_ref().equals(&device.as_ref())))
+        }
+        _ => unreachable!(),
+    });
+    device.as_ref().user_data().set(|| device_data);
+
+    device.deref().clone()
+}
+
+// A source can only be set as a selection once
+fn use_source(source: &ZwlrDataControlSourceV1, device: &ZwlrDataControlDeviceV1) -> bool {
+    let source_data = source.as_ref().user_data().get::<SourceData>().unwrap();
+    if source_data.used.replace(true) {
+        device.as_ref().post_error(
+            zwlr_data_control_device_v1::Error::UsedSource as u32,
+            "The source has already been used.".into(),
+        );
+        return false;
+    }
+    true
+}
+
+// Send a selection to a data control device
+fn send_selection_to(device: &ZwlrDataControlDeviceV1, selection: &OfferedSelection, log: &::slog::Logger) {
+    // the primary selection only exists since version 2
+    if let OfferedSelection::Primary(_) = *selection {
+        if device.as_ref().version() < 2 {
+            return;
+        }
+    }
+    let mime_types = match selection.mime_types() {
+        Some(mime_types) => mime_types,
+        None => {
+            match *selection {
+                OfferedSelection::Clipboard(_) => device.selection(None),
+                OfferedSelection::Primary(_) => device.primary_selection(None),
+            }
+            return;
+        }
+    };
+    let client: Client = match device.as_ref().client() {
+        Some(client) => client,
+        None => return,
+    };
+    let device_data = device.as_ref().user_data().get::<DeviceData>().unwrap();
+    let callback = device_data.callback.clone();
+    // create a corresponding offer
+    let offer = match client.create_resource::<ZwlrDataControlOfferV1>(device.as_ref().version()) {
+        Some(offer) => offer,
+        None => {
+            // the client is disconnecting, nobody is left to send the selection to
+            debug!(log, "Not sending the selection to a disconnected client.");
+            return;
+        }
+    };
+    let log = log.clone();
+    let offered = selection.clone();
+    offer.quick_assign(move |_offer, req, _| {
+        // offers only care about the `receive` request
+        if let zwlr_data_control_offer_v1::Request::Receive { mime_type, fd } = req {
+            // the offer reads the selection it advertised, even if it was replaced since
+            let pending = match offered {
+                OfferedSelection::Clipboard(ref selection) => selection.serve(&mime_type, fd, &log),
+                OfferedSelection::Primary(ref selection) => selection.serve(&mime_type, fd),
+            };
+            if let Some(fd) = pending {
+                debug!(
+                    log,
+                    "Forwarding a selection read by a clipboard manager to the compositor."
+                );
+                let event = match offered {
+                    OfferedSelection::Clipboard(_) => DataControlEvent::SendSelection { mime_type, fd },
+                    OfferedSelection::Primary(_) => DataControlEvent::SendPrimarySelection { mime_type, fd },
+                };
+                (&mut *callback.borrow_mut())(event);
+            }
+        }
+    });
+    // advertize the offer to the client
+    device.data_offer(&offer);
+    for mime_type in mime_types {
+        offer.offer(mime_type);
+    }
+    match *selection {
+        OfferedSelection::Clipboard(_) => device.selection(Some(&offer)),
+        OfferedSelection::Primary(_) => device.primary_selection(Some(&offer)),
+    }
+}
+
+struct SourceData {
+    mime_types: RefCell<Vec<String>>,
+    used: ::std::cell::Cell<bool>,
+}
+
+fn implement_source(source: Main<ZwlrDataControlSourceV1>) -> ZwlrDataControlSourceV1 {
+    use self::zwlr_data_control_source_v1::Request;
+    source.quick_assign(|me, req, _| {
+        let source_data = me.as_ref().user_data().get::<SourceData>().unwrap();
+        match req {
+            Request::Offer { mime_type } => {
+                if source_data.used.get() {
+                    me.as_ref().post_error(
+                        zwlr_data_control_source_v1::Error::InvalidOffer as u32,
+                        "Cannot offer a mime type once the source is used.".into(),
+                    );
+                    return;
+                }
+                source_data.mime_types.borrow_mut().push(mime_type)
+            }
+            Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    });
+    source.as_ref().user_data().set(|| SourceData {
+        mime_types: RefCell::new(Vec::new()),
+        used: ::std::cell::Cell::new(false),
+    });
+
+    source.deref().clone()
+}
+
+fn source_mime_types(source: &ZwlrDataControlSourceV1) -> Vec<String> {
+    source
+        .as_ref()
+        .user_data()
+        .get::<SourceData>()
+        .map(|data| data.mime_types.borrow().clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs::File, io::Read, os::unix::io::FromRawFd};
+
+    use crate::wayland::data_device::test_harness::{
+        create_client, create_seat, offers_of, read_messages, received_events, received_offers,
+        send_int_request, send_request,
+    };
+    use crate::wayland::data_device::{copy_bytes, selection_mime_types, set_data_device_selection};
+
+    // Create a data control device for the seat on behalf of the client
+    fn create_device<C>(seat: &Seat, client: &Client, callback: C) -> ZwlrDataControlDeviceV1
+    where
+        C: FnMut(DataControlEvent) + 'static,
+    {
+        let device = client.create_resource::<ZwlrDataControlDeviceV1>(2).unwrap();
+        let device = implement_device(device, seat.clone(), Rc::new(RefCell::new(callback)));
+        seat_data(seat).borrow_mut().known_devices.push(device.clone());
+        device
+    }
+
+    #[test]
+    fn read_compositor_selection_unfocused() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let requested = Rc::new(RefCell::new(Vec::new()));
+        let requested2 = requested.clone();
+        let device = create_device(&seat, &client, move |event| {
+            if let DataControlEvent::SendSelection { mime_type, fd } = event {
+                requested2.borrow_mut().push(mime_type);
+                let _ = ::nix::unistd::close(fd);
+            }
+        });
+
+        // the clipboard manager is notified of the new selection without having the focus
+        // (data_offer is the first event of zwlr_data_control_device_v1, selection the second)
+        set_data_device_selection(&seat, vec!["text/plain".into()]);
+        let (messages, _) = read_messages(&mut display, &mut socket);
+        let events = messages
+            .iter()
+            .map(|message| (message.sender, message.opcode))
+            .collect::<Vec<_>>();
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 1)));
+
+        // receive is the first request of zwlr_data_control_offer_v1
+        let offer = offers_of(&messages, &[device.as_ref().id()])[0];
+        let (read, write) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer, 0, "text/plain", write);
+        let _ = ::nix::unistd::close(write);
+        let _ = ::nix::unistd::close(read);
+        assert_eq!(*requested.borrow(), vec!["text/plain".to_string()]);
+    }
+
+    #[test]
+    fn set_selection_unfocused() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_device(&seat, &client, |_| {});
+        let source = implement_source(client.create_resource::<ZwlrDataControlSourceV1>(2).unwrap());
+        source
+            .as_ref()
+            .user_data()
+            .get::<SourceData>()
+            .unwrap()
+            .mime_types
+            .borrow_mut()
+            .push("text/plain".into());
+
+        // set_selection is the first request of zwlr_data_control_device_v1
+        send_int_request(
+            &mut display,
+            &mut socket,
+            device.as_ref().id(),
+            0,
+            &[source.as_ref().id()],
+        );
+        assert_eq!(selection_mime_types(&seat), vec!["text/plain".to_string()]);
+        let _ = received_events(&mut display, &mut socket);
+
+        // the source is cancelled once replaced, cancelled being its second event
+        set_data_device_selection(&seat, vec!["image/png".into()]);
+        assert!(received_events(&mut display, &mut socket).contains(&(source.as_ref().id(), 1)));
+    }
+
+    #[test]
+    fn offer_reads_its_selection() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_device(&seat, &client, |_| {});
+        copy_bytes(&seat, vec!["text/plain".into()], b"Hello".to_vec());
+        let offer = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+
+        // the selection is replaced before the clipboard manager reads its offer
+        set_data_device_selection(&seat, vec!["image/png".into()]);
+
+        // receive is the first request of zwlr_data_control_offer_v1
+        let (read, write) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer, 0, "text/plain", write);
+        let _ = ::nix::unistd::close(write);
+        let mut contents = Vec::new();
+        unsafe { File::from_raw_fd(read) }
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"Hello");
+    }
+
+    #[test]
+    fn dead_devices_pruned() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, _socket) = create_client(&mut display);
+        let (other, _other_socket) = create_client(&mut display);
+        create_device(&seat, &client, |_| {});
+        let other_device = create_device(&seat, &other, |_| {});
+
+        // the client disconnects without destroying its device
+        client.kill();
+        display
+            .dispatch(::std::time::Duration::from_millis(0), &mut ())
+            .unwrap();
+        display.flush_clients(&mut ());
+        set_data_device_selection(&seat, vec!["text/plain".into()]);
+
+        let seat_data = seat_data(&seat);
+        let seat_data = seat_data.borrow();
+        assert_eq!(seat_data.known_devices.len(), 1);
+        assert!(seat_data.known_devices[0].as_ref().equals(other_device.as_ref()));
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    cell::Cell,
+    io::{self, Write},
+    os::unix::io::RawFd,
+    rc::Rc,
+};
+
+use calloop::{
+    generic::{Fd, Generic},
+    Interest, LoopHandle, Mode, Source,
+};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+use super::compositor_selection::{log_transfer_error, ClientFd};
+
+/// Write some selection contents into an fd without blocking the event loop
+///
+/// This is meant to be used when handling
+/// [`DataDeviceEvent::SendSelection`](super::DataDeviceEvent::SendSelection): rather than writing
+/// the contents synchronously, which blocks your compositor until the client reads them if
+/// they do not fit in the pipe, the fd is registered as a writable source in your event loop
+/// and the contents are written as the client reads them.
+///
+/// This takes ownership of the fd, which is closed once all the contents were written or if the
+/// client stops reading them. An error is only returned if the fd could not be registered, in
+/// which case it is closed immediately.
+///
+/// See [`write_selection_async_with_completion`] to be notified once the transfer is over.
+pub fn write_selection_async<Data, L>(
+    handle: &LoopHandle<Data>,
+    fd: RawFd,
+    data: Vec<u8>,
+    logger: L,
+) -> io::Result<()>
+where
+    Data: 'static,
+    L: Into<Option<::slog::Logger>>,
+{
+    write_selection_async_with_completion(handle, fd, data, logger, |_| {})
+}
+
+/// Write some selection contents into an fd without blocking the event loop, and get notified
+/// once the transfer is over
+///
+/// This behaves like [`write_selection_async`], and additionally invokes the completion closure
+/// from your event loop once the fd was removed from it and closed, with whether all the
+/// contents were written. It receives `false` if the client stopped reading them before the end,
+/// for example because it closed its end of the fd. This allows you to release the resources
+/// backing the contents as soon as they are not needed anymore.
+///
+/// The closure is not invoked if an error is returned, as the fd could not be registered.
+pub fn write_selection_async_with_completion<Data, L, F>(
+    handle: &LoopHandle<Data>,
+    fd: RawFd,
+    data: Vec<u8>,
+    logger: L,
+    on_complete: F,
+) -> io::Result<()>
+where
+    Data: 'static,
+    L: Into<Option<::slog::Logger>>,
+    F: FnOnce(bool) + 'static,
+{
+    let log = crate::slog_or_fallback(logger).new(o!("smithay_module" => "data_device_mgr"));
+    // the fd is owned by the transfer from now on
+    let target = ClientFd::new(fd);
+    set_nonblocking(fd)?;
+
+    let token = Rc::new(Cell::new(None::<Source<Generic<Fd>>>));
+    let mut transfer = Some(Transfer {
+        target,
+        data,
+        written: 0,
+    });
+    let mut on_complete = Some(on_complete);
+    let loop_handle = handle.clone();
+    let callback_token = token.clone();
+    let source = handle
+        .insert_source(
+            Generic::from_fd(fd, Interest::Writable, Mode::Level),
+            move |_, _, _: &mut Data| {
+                let done = match transfer {
+                    Some(ref mut transfer) => transfer.write_available(&log),
+                    // the source will be removed shortly
+                    None => return Ok(()),
+                };
+                if done {
+                    // the fd must stay open until it is removed from the event loop, so
+                    // the removal and the closing are deferred to an idle callback
+                    let transfer = transfer.take();
+                    let on_complete = on_complete.take();
+                    let loop_handle_ = loop_handle.clone();
+                    let token = callback_token.clone();
+                    loop_handle.insert_idle(move |_| {
+                        if let Some(source) = token.take() {
+                            loop_handle_.remove(source);
+                        }
+                        let complete = transfer.map(|transfer| transfer.is_complete()).unwrap_or(false);
+                        if let Some(on_complete) = on_complete {
+                            on_complete(complete);
+                        }
+                    });
+                }
+                Ok(())
+            },
+        )
+        .map_err(|err| err.error)?;
+    token.set(Some(source));
+    Ok(())
+}
+
+// The state of a transfer into a client fd
+struct Transfer {
+    target: ClientFd,
+    data: Vec<u8>,
+    written: usize,
+}
+
+impl Transfer {
+    // Write as much of the contents as the fd accepts, returns whether the transfer is over
+    fn write_available(&mut self, log: &::slog::Logger) -> bool {
+        while self.written < self.data.len() {
+            match self.target.write(&self.data[self.written..]) {
+                Ok(0) => return true,
+                Ok(n) => self.written += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return false,
+                Err(err) => {
+                    log_transfer_error(log, &err);
+                    return true;
+                }
+            }
+        }
+        true
+    }
+
+    // Whether all the contents were written, closing the fd
+    fn is_complete(self) -> bool {
+        self.written == self.data.len()
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = fcntl(fd, FcntlArg::F_GETFL).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let flags = OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK;
+    fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs::File, io::Read, os::unix::io::FromRawFd, sync::mpsc, time::Duration};
+
+    #[test]
+    fn write_larger_than_pipe() {
+        let mut event_loop = ::calloop::EventLoop::<()>::new().unwrap();
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        // larger than the capacity of a pipe, so that it takes several writes
+        let data = (0..256 * 1024).map(|i| i as u8).collect::<Vec<u8>>();
+        write_selection_async(&event_loop.handle(), write_fd, data.clone(), None).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        ::std::thread::spawn(move || {
+            let mut received = Vec::new();
+            unsafe { File::from_raw_fd(read_fd) }
+                .read_to_end(&mut received)
+                .unwrap();
+            sender.send(received).unwrap();
+        });
+
+        // the reader only gets the end of file once the transfer is over
+        let mut received = None;
+        for _ in 0..1000 {
+            event_loop
+                .dispatch(Some(Duration::from_millis(10)), &mut ())
+                .unwrap();
+            if let Ok(data) = receiver.try_recv() {
+                received = Some(data);
+                break;
+            }
+        }
+        assert_eq!(received.unwrap(), data);
+    }
+
+    #[test]
+    fn write_into_closed_pipe() {
+        let mut event_loop = ::calloop::EventLoop::<()>::new().unwrap();
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        ::nix::unistd::close(read_fd).unwrap();
+        write_selection_async(
+            &event_loop.handle(),
+            write_fd,
+            b"Nobody is listening".to_vec(),
+            None,
+        )
+        .unwrap();
+        // the transfer is aborted without failing the dispatch
+        for _ in 0..2 {
+            event_loop
+                .dispatch(Some(Duration::from_millis(0)), &mut ())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn transfer_completion_reported() {
+        let mut event_loop = ::calloop::EventLoop::<()>::new().unwrap();
+        let outcomes = Rc::new(::std::cell::RefCell::new(Vec::new()));
+
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        let sink = outcomes.clone();
+        write_selection_async_with_completion(
+            &event_loop.handle(),
+            write_fd,
+            b"Hello world!".to_vec(),
+            None,
+            move |complete| sink.borrow_mut().push(complete),
+        )
+        .unwrap();
+        let (closed_read_fd, closed_write_fd) = ::nix::unistd::pipe().unwrap();
+        ::nix::unistd::close(closed_read_fd).unwrap();
+        let sink = outcomes.clone();
+        write_selection_async_with_completion(
+            &event_loop.handle(),
+            closed_write_fd,
+            b"Nobody is listening".to_vec(),
+            None,
+            move |complete| sink.borrow_mut().push(complete),
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            event_loop
+                .dispatch(Some(Duration::from_millis(0)), &mut ())
+                .unwrap();
+        }
+        let mut outcomes = outcomes.borrow().clone();
+        outcomes.sort();
+        assert_eq!(outcomes, vec![false, true]);
+
+        // the fd is closed before the completion is reported
+        let mut received = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut received)
+            .unwrap();
+        assert_eq!(received, b"Hello world!");
+    }
+}
@@ -0,0 +1,113 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wayland_server::{protocol::wl_data_device_manager::DndAction, Display};
+
+use crate::wayland::compositor::{roles::Role, CompositorToken};
+
+use super::{
+    default_action_chooser, init_data_device_handle, DataDeviceConfig, DataDeviceEvent, DataDeviceHandle,
+    DnDIconRole,
+};
+
+/// A builder for the data device global
+///
+/// This is an alternative to the `init_data_device*` functions, where every argument is
+/// optional and set by name:
+///
+/// ```
+/// # extern crate wayland_server;
+/// # #[macro_use] extern crate smithay;
+/// use smithay::wayland::data_device::{DataDeviceBuilder, DataDeviceConfig, DnDIconRole};
+/// # use smithay::wayland::compositor::compositor_init;
+///
+/// # define_roles!(Roles => [DnDIcon, DnDIconRole]);
+/// # let mut display = wayland_server::Display::new();
+/// # let (compositor_token, _, _) = compositor_init::<Roles, _, _>(&mut display, |_, _, _| {}, None);
+/// DataDeviceBuilder::new()
+///     .callback(|dnd_event| { /* react to client DnD/selection actions */ })
+///     .config(DataDeviceConfig {
+///         strict_selection_serial: true,
+///         ..Default::default()
+///     })
+///     .init(&mut display, compositor_token);
+/// ```
+///
+/// Without a callback the events are discarded, and without an action chooser the
+/// [`default_action_chooser`] is used.
+pub struct DataDeviceBuilder {
+    callback: Box<dyn FnMut(DataDeviceEvent)>,
+    action_choice: Box<dyn FnMut(DndAction, DndAction) -> DndAction>,
+    config: DataDeviceConfig,
+    logger: Option<::slog::Logger>,
+}
+
+impl DataDeviceBuilder {
+    /// Start building a data device global with the default settings
+    pub fn new() -> DataDeviceBuilder {
+        DataDeviceBuilder {
+            callback: Box::new(|_| {}),
+            action_choice: Box::new(default_action_chooser),
+            config: DataDeviceConfig::default(),
+            logger: None,
+        }
+    }
+
+    /// Set the callback receiving the [`DataDeviceEvent`]s
+    pub fn callback<C>(mut self, callback: C) -> DataDeviceBuilder
+    where
+        C: FnMut(DataDeviceEvent) + 'static,
+    {
+        self.callback = Box::new(callback);
+        self
+    }
+
+    /// Set the closure choosing the action of the drag'n'drops
+    ///
+    /// See [`init_data_device`](super::init_data_device) for its arguments.
+    pub fn action_chooser<F>(mut self, action_choice: F) -> DataDeviceBuilder
+    where
+        F: FnMut(DndAction, DndAction) -> DndAction + 'static,
+    {
+        self.action_choice = Box::new(action_choice);
+        self
+    }
+
+    /// Set the configuration of the data device logic
+    pub fn config(mut self, config: DataDeviceConfig) -> DataDeviceBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Set the logger of the data device logic
+    pub fn logger<L>(mut self, logger: L) -> DataDeviceBuilder
+    where
+        L: Into<Option<::slog::Logger>>,
+    {
+        self.logger = logger.into();
+        self
+    }
+
+    /// Initialize the data device global
+    ///
+    /// The returned handle gives access to the global, and allows to tear the data device
+    /// functionality down.
+    pub fn init<R>(self, display: &mut Display, token: CompositorToken<R>) -> DataDeviceHandle
+    where
+        R: Role<DnDIconRole> + 'static,
+    {
+        init_data_device_handle(
+            display,
+            Rc::new(RefCell::new(self.callback)),
+            Rc::new(RefCell::new(self.action_choice)),
+            token,
+            self.config,
+            self.logger,
+        )
+    }
+}
+
+impl Default for DataDeviceBuilder {
+    fn default() -> DataDeviceBuilder {
+        DataDeviceBuilder::new()
+    }
+}
@@ -0,0 +1,770 @@
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{self, Read, Write},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    sys::{
+        socket::{send, MsgFlags},
+        stat::{fstat, SFlag},
+    },
+};
+use wayland_server::Client;
+
+/// Content of a compositor-provided selection
+pub(crate) enum SelectionContent {
+    /// The data is provided by the compositor through the `SendSelection` event
+    Callback,
+    /// The same bytes are served for all the advertised mime types
+    Bytes(Arc<Vec<u8>>),
+    /// Different bytes are served for each of the advertised mime types
+    Buffers(HashMap<String, Arc<Vec<u8>>>),
+    /// The contents of this file are served for all the advertised mime types
+    ///
+    /// The file is only opened when a client requests the data.
+    File(PathBuf),
+    /// A new reader is created for each client request, and its contents are served
+    Reader(RefCell<Box<ReaderFactory>>),
+    /// Each client request is forwarded to a closure, that takes care of writing the data
+    Remote(RefCell<Box<RemoteForward>>),
+    /// The contents are generated by a compositor-provided source for each client request
+    Source(Box<dyn SelectionSource>),
+    /// A copy of the contents of a selection from a client that is gone, for each mime type
+    ///
+    /// Nothing is served anymore once it expired.
+    Cached {
+        contents: HashMap<String, Arc<Vec<u8>>>,
+        expires: Instant,
+    },
+}
+
+/// A closure creating readers for the contents of a selection
+pub(crate) type ReaderFactory = dyn FnMut() -> Box<dyn Read + Send>;
+
+/// A closure forwarding the requests for a selection, as mime type and fd to write into
+pub(crate) type RemoteForward = dyn FnMut(String, RawFd);
+
+/// A generator of selection contents provided by the compositor
+///
+/// This allows you to provide contents that are only produced when a client requests them,
+/// like rendering a screenshot as PNG, see
+/// [`set_data_device_selection_source`](super::set_data_device_selection_source).
+pub trait SelectionSource {
+    /// The mime types this source can provide, in order of preference
+    fn mime_types(&self) -> Vec<String>;
+
+    /// Write the contents of this source in the given mime type
+    ///
+    /// This is called from your event loop each time a client requests the selection, with one
+    /// of the mime types returned by [`mime_types`](SelectionSource::mime_types). The contents
+    /// are buffered and then served to the client without blocking your compositor. If an
+    /// error is returned, the client receives nothing.
+    ///
+    /// It may be called while the selection state of the seat is borrowed, so it must not
+    /// access the data device functions of this seat.
+    fn write(&self, mime_type: &str, writer: &mut dyn Write) -> io::Result<()>;
+}
+
+/// A limit on the size of the compositor selection contents served to clients
+///
+/// This only applies to the contents smithay serves by itself: bytes, files, readers,
+/// [sources](SelectionSource) and cached client selections. Contents you write yourself on
+/// [`DataDeviceEvent::SendSelection`](super::DataDeviceEvent::SendSelection) or forward from
+/// a [remote selection](super::install_remote_selection), as well as client selections, which
+/// the source client writes directly, are never limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectionSizeLimit {
+    /// Maximum number of bytes served for a single request
+    pub max_size: usize,
+    /// What to do with contents larger than `max_size`
+    pub policy: OversizedSelection,
+}
+
+/// How to serve selection contents exceeding a [`SelectionSizeLimit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizedSelection {
+    /// Only serve the first `max_size` bytes of the contents
+    Truncate,
+    /// Serve nothing, the client reads an empty selection
+    ///
+    /// As the size of files and readers is not known in advance, up to `max_size` bytes
+    /// of their contents are buffered before being served.
+    Deny,
+}
+
+impl SelectionContent {
+    /// Serve this content into the fd provided by a client
+    ///
+    /// Takes ownership of the fd, which will be closed once the transfer is over.
+    ///
+    /// Each call starts an independent transfer with its own state, so several requests
+    /// for the same selection can be served concurrently.
+    pub(crate) fn serve(
+        &self,
+        mime_type: &str,
+        fd: RawFd,
+        limit: Option<SelectionSizeLimit>,
+        log: &::slog::Logger,
+    ) {
+        self.serve_in_slot(mime_type, fd, limit, None, log)
+    }
+
+    /// Serve this content into the fd provided by a client, on behalf of this client
+    ///
+    /// This behaves like [`serve`](SelectionContent::serve), except that only
+    /// `MAX_PENDING_TRANSFERS` transfers can be pending for a client: its next requests are
+    /// denied, their fd being closed right away, until some of its transfers are over.
+    pub(crate) fn serve_to(
+        &self,
+        client: &Client,
+        mime_type: &str,
+        fd: RawFd,
+        limit: Option<SelectionSizeLimit>,
+        log: &::slog::Logger,
+    ) {
+        match reserve_transfer(client) {
+            Some(slot) => self.serve_in_slot(mime_type, fd, limit, Some(slot), log),
+            None => {
+                debug!(
+                    log,
+                    "Too many pending selection transfers for this client, denying this one."
+                );
+                super::close_fd(fd);
+            }
+        }
+    }
+
+    fn serve_in_slot(
+        &self,
+        mime_type: &str,
+        fd: RawFd,
+        limit: Option<SelectionSizeLimit>,
+        slot: Option<TransferSlot>,
+        log: &::slog::Logger,
+    ) {
+        match *self {
+            SelectionContent::Callback => unreachable!(),
+            SelectionContent::Bytes(ref data) => {
+                let data = data.clone();
+                spawn_transfer(fd, slot, log.clone(), move |target| {
+                    copy_limited(&data[..], target, limit)
+                });
+            }
+            SelectionContent::Buffers(ref buffers) => match buffers.get(mime_type) {
+                Some(data) => {
+                    let data = data.clone();
+                    spawn_transfer(fd, slot, log.clone(), move |target| {
+                        copy_limited(&data[..], target, limit)
+                    });
+                }
+                None => {
+                    super::close_fd(fd);
+                }
+            },
+            SelectionContent::File(ref path) => {
+                let path = path.clone();
+                spawn_transfer(fd, slot, log.clone(), move |target| {
+                    // the file may have been removed since the selection was set,
+                    // in which case the client will just read nothing
+                    let file = File::open(&path)?;
+                    copy_limited(file, target, limit)
+                });
+            }
+            SelectionContent::Reader(ref factory) => {
+                let reader = (&mut *factory.borrow_mut())();
+                // if the reader fails, the transfer is aborted and the client will only
+                // receive the data read so far
+                spawn_transfer(fd, slot, log.clone(), move |target| {
+                    copy_limited(reader, target, limit)
+                });
+            }
+            SelectionContent::Remote(ref forward) => (&mut *forward.borrow_mut())(mime_type.into(), fd),
+            SelectionContent::Source(ref source) => {
+                let mut data = Vec::new();
+                match source.write(mime_type, &mut data) {
+                    Ok(()) => spawn_transfer(fd, slot, log.clone(), move |target| {
+                        copy_limited(&data[..], target, limit)
+                    }),
+                    Err(err) => {
+                        debug!(log, "The selection source failed to write its contents"; "error" => format!("{:?}", err));
+                        super::close_fd(fd);
+                    }
+                }
+            }
+            SelectionContent::Cached { ref contents, .. } => match contents.get(mime_type) {
+                Some(data) if !self.is_expired() => {
+                    let data = data.clone();
+                    spawn_transfer(fd, slot, log.clone(), move |target| {
+                        copy_limited(&data[..], target, limit)
+                    });
+                }
+                _ => {
+                    super::close_fd(fd);
+                }
+            },
+        }
+    }
+
+    /// A hash of this content, if it is known in advance
+    pub(crate) fn content_hash(&self) -> Option<u64> {
+        match *self {
+            SelectionContent::Bytes(ref data) => {
+                let mut hasher = DefaultHasher::new();
+                data.hash(&mut hasher);
+                Some(hasher.finish())
+            }
+            SelectionContent::Buffers(ref buffers) => {
+                // hash the buffers in a stable order, whatever the order of the map
+                let mut buffers = buffers.iter().collect::<Vec<_>>();
+                buffers.sort_by(|a, b| a.0.cmp(b.0));
+                let mut hasher = DefaultHasher::new();
+                buffers.hash(&mut hasher);
+                Some(hasher.finish())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether this content cannot be served anymore
+    pub(crate) fn is_expired(&self) -> bool {
+        match *self {
+            SelectionContent::Cached { expires, .. } => Instant::now() >= expires,
+            _ => false,
+        }
+    }
+}
+
+// Number of transfers that can be pending for a single client, its next requests are denied
+const MAX_PENDING_TRANSFERS: usize = 16;
+
+// The number of transfers still running into the fds of a client, stored in its data map
+#[derive(Default)]
+struct PendingTransfers(Arc<AtomicUsize>);
+
+// A transfer counted in the pending transfers of a client, until it is dropped
+struct TransferSlot(Arc<AtomicUsize>);
+
+impl Drop for TransferSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Count a new transfer into the fds of this client, unless too many are already pending
+fn reserve_transfer(client: &Client) -> Option<TransferSlot> {
+    client.data_map().insert_if_missing(PendingTransfers::default);
+    let pending = client.data_map().get::<PendingTransfers>().unwrap();
+    let slot = TransferSlot(pending.0.clone());
+    // the slot is released right away if the transfer is denied
+    if slot.0.fetch_add(1, Ordering::SeqCst) < MAX_PENDING_TRANSFERS {
+        Some(slot)
+    } else {
+        None
+    }
+}
+
+// Run a transfer into the fd provided by a client
+//
+// The client will only read the pipe after its request has been processed, so the
+// transfer runs in a worker thread to never block the event loop. The fd is closed
+// once the transfer is over, and the slot of the transfer released.
+fn spawn_transfer<F>(fd: RawFd, slot: Option<TransferSlot>, log: ::slog::Logger, transfer: F)
+where
+    F: FnOnce(&mut ClientFd) -> io::Result<()> + Send + 'static,
+{
+    ::std::thread::spawn(move || {
+        let _slot = slot;
+        // the fd is owned by the transfer from now on
+        let mut target = ClientFd::new(fd);
+        if let Err(err) = set_blocking(fd).and_then(|()| transfer(&mut target)) {
+            log_transfer_error(&log, &err);
+        }
+    });
+}
+
+// Log the error that aborted a transfer into the fd of a client
+//
+// The client closing its end before reading everything is expected, for example if it
+// only wanted the beginning of the contents, so this is never propagated.
+pub(crate) fn log_transfer_error(log: &::slog::Logger, err: &io::Error) {
+    match err.raw_os_error().map(Errno::from_i32) {
+        Some(Errno::EPIPE) => debug!(log, "The client stopped reading the selection contents"),
+        Some(Errno::EBADF) => debug!(log, "The fd provided by the client is not valid anymore"),
+        _ => debug!(log, "Failed to write the selection contents"; "error" => format!("{:?}", err)),
+    }
+}
+
+// Copy the contents of a selection into the fd of a client, enforcing the size limit
+//
+// Reads and writes interrupted by a signal are retried.
+fn copy_limited<R: Read, W: Write>(
+    mut reader: R,
+    target: &mut W,
+    limit: Option<SelectionSizeLimit>,
+) -> io::Result<()> {
+    match limit {
+        None => io::copy(&mut reader, target).map(|_| ()),
+        Some(SelectionSizeLimit {
+            max_size,
+            policy: OversizedSelection::Truncate,
+        }) => io::copy(&mut reader.take(max_size as u64), target).map(|_| ()),
+        Some(SelectionSizeLimit {
+            max_size,
+            policy: OversizedSelection::Deny,
+        }) => {
+            // nothing must be sent before we know the whole contents fit
+            let mut contents = Vec::new();
+            reader.take(max_size as u64 + 1).read_to_end(&mut contents)?;
+            if contents.len() > max_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the selection contents exceed the size limit",
+                ));
+            }
+            target.write_all(&contents)
+        }
+    }
+}
+
+// The fd provided by a client to write the selection contents into
+//
+// It owns the fd, which is closed when it is dropped, and never before, so that no path
+// aborting a transfer can close the fd a second time.
+//
+// The protocol does not mandate it to be a pipe, clients may provide a socket as well. If
+// the client closes its end early, writing into a socket would raise a SIGPIPE, so we
+// use `send` with `MSG_NOSIGNAL` for them to only get an `EPIPE` error. Either way, the
+// transfer is then aborted.
+pub(crate) struct ClientFd {
+    file: File,
+    socket: bool,
+}
+
+impl ClientFd {
+    pub(crate) fn new(fd: RawFd) -> ClientFd {
+        let socket = fstat(fd)
+            .map(|stat| SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFSOCK)
+            .unwrap_or(false);
+        ClientFd {
+            file: unsafe { File::from_raw_fd(fd) },
+            socket,
+        }
+    }
+}
+
+impl Write for ClientFd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // an `EINTR` is reported as `ErrorKind::Interrupted`, for the write to be retried
+        if self.socket {
+            send(self.file.as_raw_fd(), buf, MsgFlags::MSG_NOSIGNAL).map_err(|err| match err.as_errno() {
+                Some(errno) => io::Error::from_raw_os_error(errno as i32),
+                None => io::Error::new(io::ErrorKind::Other, err),
+            })
+        } else {
+            self.file.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn set_blocking(fd: RawFd) -> io::Result<()> {
+    let flags = fcntl(fd, FcntlArg::F_GETFL).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let flags = OFlag::from_bits_truncate(flags) & !OFlag::O_NONBLOCK;
+    fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    Ok(())
+}
+
+/// Guess the mime types to advertise for some raw bytes
+///
+/// The detection is voluntarily simple:
+///
+/// - PNG and JPEG images are detected using their magic numbers
+/// - data that is valid UTF-8 and does not contain any NUL byte is considered to be text,
+///   and advertised as `text/plain;charset=utf-8`, `text/plain` and `UTF8_STRING`
+/// - anything else is advertised as `application/octet-stream`
+pub fn sniff_mime_types(data: &[u8]) -> Vec<String> {
+    const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+    const JPEG_MAGIC: &[u8] = b"\xff\xd8\xff";
+
+    if data.starts_with(PNG_MAGIC) {
+        vec!["image/png".into()]
+    } else if data.starts_with(JPEG_MAGIC) {
+        vec!["image/jpeg".into()]
+    } else if !data.contains(&0) && ::std::str::from_utf8(data).is_ok() {
+        vec![
+            "text/plain;charset=utf-8".into(),
+            "text/plain".into(),
+            "UTF8_STRING".into(),
+        ]
+    } else {
+        vec!["application/octet-stream".into()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        os::unix::{io::IntoRawFd, net::UnixStream},
+        time::Duration,
+    };
+
+    // Connect a client to a display, the other end of its socket is returned as well to keep
+    // it connected
+    fn create_client(display: &mut ::wayland_server::Display) -> (Client, UnixStream) {
+        let (server, socket) = UnixStream::pair().unwrap();
+        let client = unsafe { display.create_client(server.into_raw_fd(), &mut ()) };
+        (client, socket)
+    }
+
+    // Serve the content into a pipe and read everything back
+    fn read_served(content: &SelectionContent) -> Vec<u8> {
+        read_served_limited(content, None)
+    }
+
+    fn read_served_limited(content: &SelectionContent, limit: Option<SelectionSizeLimit>) -> Vec<u8> {
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        let mut display = ::wayland_server::Display::new();
+        let (client, _socket) = create_client(&mut display);
+        content.serve_to(
+            &client,
+            "text/plain",
+            write_fd,
+            limit,
+            &::slog::Logger::root(::slog::Discard, o!()),
+        );
+        let mut data = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn serve_bytes() {
+        let content = SelectionContent::Bytes(Arc::new(b"Hello".to_vec()));
+        assert_eq!(read_served(&content), b"Hello");
+    }
+
+    #[test]
+    fn serve_bytes_concurrently() {
+        // larger than the capacity of a pipe, so that the transfers really overlap
+        let data = (0..1024 * 1024).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        let content = SelectionContent::Bytes(Arc::new(data.clone()));
+        let log = ::slog::Logger::root(::slog::Discard, o!());
+        let readers = (0..4)
+            .map(|_| {
+                let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+                let mut display = ::wayland_server::Display::new();
+                let (client, _socket) = create_client(&mut display);
+                content.serve_to(&client, "text/plain", write_fd, None, &log);
+                unsafe { File::from_raw_fd(read_fd) }
+            })
+            .collect::<Vec<_>>();
+        for mut reader in readers {
+            let mut received = Vec::new();
+            reader.read_to_end(&mut received).unwrap();
+            assert!(received == data);
+        }
+    }
+
+    #[test]
+    fn serve_into_socket() {
+        let (mut reader, writer) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        let content = SelectionContent::Bytes(Arc::new(b"Through a socket".to_vec()));
+        let mut display = ::wayland_server::Display::new();
+        let (client, _socket) = create_client(&mut display);
+        content.serve_to(
+            &client,
+            "text/plain",
+            ::std::os::unix::io::IntoRawFd::into_raw_fd(writer),
+            None,
+            &::slog::Logger::root(::slog::Discard, o!()),
+        );
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"Through a socket");
+    }
+
+    #[test]
+    fn serve_into_closed_socket() {
+        let (reader, writer) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        ::std::mem::drop(reader);
+        // writing into a socket whose peer is gone fails without raising a SIGPIPE
+        let mut target = ClientFd::new(::std::os::unix::io::IntoRawFd::into_raw_fd(writer));
+        assert!(target.socket);
+        let err = target.write_all(b"Nobody is listening").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn serve_into_closed_pipe() {
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        ::nix::unistd::close(read_fd).unwrap();
+        let mut target = ClientFd::new(write_fd);
+        assert!(!target.socket);
+        let err = target.write_all(b"Nobody is listening").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        log_transfer_error(&::slog::Logger::root(::slog::Discard, o!()), &err);
+    }
+
+    #[test]
+    fn serve_cached() {
+        let mut contents = HashMap::new();
+        contents.insert("text/plain".to_string(), Arc::new(b"Plain".to_vec()));
+        contents.insert("text/html".to_string(), Arc::new(b"<b>Html</b>".to_vec()));
+        let content = SelectionContent::Cached {
+            contents: contents.clone(),
+            expires: Instant::now() + Duration::from_secs(60),
+        };
+        assert!(!content.is_expired());
+        // the contents of the requested mime type are served
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        let mut display = ::wayland_server::Display::new();
+        let (client, _socket) = create_client(&mut display);
+        content.serve_to(
+            &client,
+            "text/html",
+            write_fd,
+            None,
+            &::slog::Logger::root(::slog::Discard, o!()),
+        );
+        let mut data = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"<b>Html</b>");
+        assert_eq!(read_served(&content), b"Plain");
+
+        let expired = SelectionContent::Cached {
+            contents,
+            expires: Instant::now(),
+        };
+        assert!(expired.is_expired());
+        assert!(read_served(&expired).is_empty());
+    }
+
+    #[test]
+    fn serve_buffers() {
+        let mut buffers = HashMap::new();
+        buffers.insert("text/plain".to_string(), Arc::new(b"Plain".to_vec()));
+        buffers.insert("text/html".to_string(), Arc::new(b"<b>Html</b>".to_vec()));
+        let content = SelectionContent::Buffers(buffers);
+        assert_eq!(read_served(&content), b"Plain");
+        assert!(content.content_hash().is_some());
+
+        // nothing is served for other mime types
+        let content = SelectionContent::Buffers(HashMap::new());
+        assert!(read_served(&content).is_empty());
+    }
+
+    #[test]
+    fn bytes_content_hash() {
+        let hello = SelectionContent::Bytes(Arc::new(b"Hello".to_vec()));
+        let hello_again = SelectionContent::Bytes(Arc::new(b"Hello".to_vec()));
+        let world = SelectionContent::Bytes(Arc::new(b"World".to_vec()));
+        assert!(hello.content_hash().is_some());
+        assert_eq!(hello.content_hash(), hello_again.content_hash());
+        assert_ne!(hello.content_hash(), world.content_hash());
+        assert_eq!(SelectionContent::Callback.content_hash(), None);
+    }
+
+    #[test]
+    fn serve_file() {
+        let mut file = ::tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"Contents of the file").unwrap();
+        let content = SelectionContent::File(file.path().to_owned());
+        assert_eq!(read_served(&content), b"Contents of the file");
+    }
+
+    #[test]
+    fn serve_missing_file() {
+        let path = {
+            let file = ::tempfile::NamedTempFile::new().unwrap();
+            file.path().to_owned()
+        };
+        let content = SelectionContent::File(path);
+        assert!(read_served(&content).is_empty());
+    }
+
+    #[test]
+    fn serve_reader() {
+        let content = SelectionContent::Reader(RefCell::new(Box::new(|| {
+            Box::new(io::Cursor::new(b"Streamed data".to_vec())) as Box<dyn Read + Send>
+        })));
+        // each request gets a fresh reader
+        assert_eq!(read_served(&content), b"Streamed data");
+        assert_eq!(read_served(&content), b"Streamed data");
+    }
+
+    #[test]
+    fn serve_failing_reader() {
+        struct FailingReader(bool);
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0 {
+                    Err(io::Error::new(io::ErrorKind::Other, "stream broke"))
+                } else {
+                    self.0 = true;
+                    buf[..4].copy_from_slice(b"Part");
+                    Ok(4)
+                }
+            }
+        }
+        let content = SelectionContent::Reader(RefCell::new(Box::new(|| {
+            Box::new(FailingReader(false)) as Box<dyn Read + Send>
+        })));
+        assert_eq!(read_served(&content), b"Part");
+    }
+
+    #[test]
+    fn serve_oversized_truncated() {
+        let content = SelectionContent::Bytes(Arc::new(b"Hello world".to_vec()));
+        let limit = SelectionSizeLimit {
+            max_size: 5,
+            policy: OversizedSelection::Truncate,
+        };
+        assert_eq!(read_served_limited(&content, Some(limit)), b"Hello");
+    }
+
+    #[test]
+    fn serve_oversized_denied() {
+        let limit = SelectionSizeLimit {
+            max_size: 5,
+            policy: OversizedSelection::Deny,
+        };
+        let content = SelectionContent::Bytes(Arc::new(b"Hello world".to_vec()));
+        assert!(read_served_limited(&content, Some(limit)).is_empty());
+        // contents within the limit are served as usual
+        let content = SelectionContent::Reader(RefCell::new(Box::new(|| {
+            Box::new(io::Cursor::new(b"Hello".to_vec())) as Box<dyn Read + Send>
+        })));
+        assert_eq!(read_served_limited(&content, Some(limit)), b"Hello");
+    }
+
+    // Reader and writer interrupted by a signal every other call
+    struct Interrupting<T> {
+        inner: T,
+        interrupt: bool,
+    }
+
+    impl<T> Interrupting<T> {
+        fn interrupt(&mut self) -> io::Result<()> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                Err(io::Error::from_raw_os_error(::nix::errno::Errno::EINTR as i32))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl<T: Read> Read for Interrupting<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt()?;
+            // read little by little, for the interruptions to happen mid-transfer
+            let len = buf.len().min(3);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    impl<T: Write> Write for Interrupting<T> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.interrupt()?;
+            let len = buf.len().min(3);
+            self.inner.write(&buf[..len])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn copy_interrupted() {
+        let data = b"Hello world".to_vec();
+        let limits = [
+            None,
+            Some(SelectionSizeLimit {
+                max_size: 64,
+                policy: OversizedSelection::Truncate,
+            }),
+            Some(SelectionSizeLimit {
+                max_size: 64,
+                policy: OversizedSelection::Deny,
+            }),
+        ];
+        for &limit in &limits {
+            let reader = Interrupting {
+                inner: &data[..],
+                interrupt: false,
+            };
+            let mut writer = Interrupting {
+                inner: Vec::new(),
+                interrupt: false,
+            };
+            copy_limited(reader, &mut writer, limit).unwrap();
+            assert_eq!(writer.inner, data);
+        }
+    }
+
+    #[test]
+    fn pending_transfers_bounded_per_client() {
+        let mut display = ::wayland_server::Display::new();
+        let (client, _socket) = create_client(&mut display);
+        let (other, _other_socket) = create_client(&mut display);
+        let mut slots = (0..MAX_PENDING_TRANSFERS)
+            .map(|_| reserve_transfer(&client).unwrap())
+            .collect::<Vec<_>>();
+        assert!(reserve_transfer(&client).is_none());
+        // the other clients are not affected
+        assert!(reserve_transfer(&other).is_some());
+        // a new transfer can start once a pending one is over
+        slots.pop();
+        assert!(reserve_transfer(&client).is_some());
+    }
+
+    #[test]
+    fn sniff_png() {
+        let data = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        assert_eq!(sniff_mime_types(data), vec!["image/png".to_string()]);
+    }
+
+    #[test]
+    fn sniff_utf8_text() {
+        let data = "Hello, wörld!".as_bytes();
+        assert_eq!(
+            sniff_mime_types(data),
+            vec![
+                "text/plain;charset=utf-8".to_string(),
+                "text/plain".to_string(),
+                "UTF8_STRING".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn sniff_unknown_binary() {
+        let data = [0x00, 0xfe, 0x12, 0x80, 0x00];
+        assert_eq!(
+            sniff_mime_types(&data),
+            vec!["application/octet-stream".to_string()]
+        );
+    }
+}
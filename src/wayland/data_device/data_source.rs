@@ -1,4 +1,4 @@
-use std::{cell::RefCell, ops::Deref as _};
+use std::{cell::RefCell, ops::Deref as _, rc::Weak};
 
 use wayland_server::{
     protocol::{
@@ -8,21 +8,64 @@ use wayland_server::{
     Main,
 };
 
+use crate::wayland::seat::SeatRc;
+
 /// The metadata describing a data source
 #[derive(Debug, Clone)]
 pub struct SourceMetadata {
     /// The MIME types supported by this source
+    ///
+    /// They are advertised to clients in this order, unless your compositor configured
+    /// some preferred mime types, see [`DataDeviceConfig`](super::DataDeviceConfig).
     pub mime_types: Vec<String>,
     /// The Drag'n'Drop actions supported by this source
     pub dnd_action: DndAction,
 }
 
-pub(crate) fn implement_data_source(src: Main<WlDataSource>) -> WlDataSource {
-    src.quick_assign(|me, req, _| {
-        let data: &RefCell<SourceMetadata> = me.as_ref().user_data().get().unwrap();
-        let mut guard = data.borrow_mut();
+impl SourceMetadata {
+    /// Create the metadata of a source offering these mime types, without any drag'n'drop action
+    ///
+    /// The mime types are normalized: empty ones are dropped, and only the first occurrence of
+    /// a duplicated mime type is kept.
+    pub fn new(mut mime_types: Vec<String>) -> SourceMetadata {
+        normalize_mime_types(&mut mime_types);
+        SourceMetadata {
+            mime_types,
+            dnd_action: DndAction::empty(),
+        }
+    }
+}
+
+// The user data of the data sources created by smithay
+pub(crate) struct DataSourceData {
+    pub(crate) metadata: RefCell<SourceMetadata>,
+    // the limit of mime types of the data device manager that created the source
+    pub(crate) max_mime_types: usize,
+    // the seats this source was the selection of, updated when its metadata is modified
+    pub(crate) seats: RefCell<Vec<Weak<SeatRc>>>,
+}
+
+// The `on_offer` callback is invoked every time a mime type is added to the source
+pub(crate) fn implement_data_source<F>(
+    src: Main<WlDataSource>,
+    max_mime_types: usize,
+    on_offer: F,
+    log: ::slog::Logger,
+) -> WlDataSource
+where
+    F: Fn(&WlDataSource) + 'static,
+{
+    src.quick_assign(move |me, req, _| {
+        let data: &DataSourceData = me.as_ref().user_data().get().unwrap();
+        let mut guard = data.metadata.borrow_mut();
         match req {
-            Request::Offer { mime_type } => guard.mime_types.push(mime_type),
+            Request::Offer { mime_type } => {
+                if accept_offered_mime_type(&guard.mime_types, &mime_type, max_mime_types, &log) {
+                    guard.mime_types.push(mime_type);
+                    ::std::mem::drop(guard);
+                    on_offer(&me);
+                }
+            }
             Request::SetActions { dnd_actions } => {
                 guard.dnd_action = dnd_actions;
             }
@@ -30,23 +73,193 @@ pub(crate) fn implement_data_source(src: Main<WlDataSource>) -> WlDataSource {
             _ => unreachable!(),
         }
     });
-    src.as_ref().user_data().set(|| {
-        RefCell::new(SourceMetadata {
+    src.as_ref().user_data().set(|| DataSourceData {
+        metadata: RefCell::new(SourceMetadata {
             mime_types: Vec::new(),
             dnd_action: DndAction::None,
-        })
+        }),
+        max_mime_types,
+        seats: RefCell::new(Vec::new()),
     });
 
     src.deref().clone()
 }
 
+// Whether a mime type offered by a client source must be added to the ones it already offers
+//
+// Empty and duplicated mime types are ignored, as well as the ones beyond the limit. This is
+// the validation used by the sources of both the clipboard and the primary selection.
+pub(crate) fn accept_offered_mime_type(
+    offered: &[String],
+    mime_type: &str,
+    max_mime_types: usize,
+    log: &::slog::Logger,
+) -> bool {
+    if mime_type.is_empty() {
+        debug!(log, "Ignoring an empty mime type");
+        false
+    } else if offered.iter().any(|offered| offered == mime_type) {
+        // advertising it again would only duplicate the `offer` events
+        debug!(log, "Ignoring a mime type offered twice"; "mime_type" => mime_type);
+        false
+    } else if offered.len() < max_mime_types {
+        true
+    } else {
+        debug!(log, "Ignoring a mime type offered beyond the limit"; "mime_type" => mime_type);
+        false
+    }
+}
+
+// Remove the empty and duplicated mime types, keeping the first occurrence of each
+pub(crate) fn normalize_mime_types(mime_types: &mut Vec<String>) {
+    let mut seen = Vec::with_capacity(mime_types.len());
+    mime_types.retain(|mime_type| {
+        if mime_type.is_empty() || seen.contains(mime_type) {
+            false
+        } else {
+            seen.push(mime_type.clone());
+            true
+        }
+    });
+}
+
+// Order mime types before advertising them: the preferred ones come first, in order
+// of preference, followed by the others in their original order
+pub(crate) fn ordered_mime_types(mime_types: &[String], preferred: &[String]) -> Vec<String> {
+    let mut ordered = preferred
+        .iter()
+        .filter(|mime_type| mime_types.contains(mime_type))
+        .cloned()
+        .collect::<Vec<_>>();
+    ordered.extend(
+        mime_types
+            .iter()
+            .filter(|mime_type| !preferred.contains(mime_type))
+            .cloned(),
+    );
+    ordered
+}
+
+// Find the mime type to serve from the offered ones for a mime type requested by a client
+//
+// With loose text matching, `text/plain` and its charset-qualified variants are considered
+// compatible with each other.
+pub(crate) fn matching_mime_type(offered: &[String], requested: &str, loose_text: bool) -> Option<String> {
+    if offered.iter().any(|mime_type| mime_type == requested) {
+        return Some(requested.to_owned());
+    }
+    if !loose_text || !is_plain_text(requested) {
+        return None;
+    }
+    let requested_bare = requested == "text/plain";
+    offered
+        .iter()
+        .find(|mime_type| is_plain_text(mime_type) && (requested_bare || *mime_type == "text/plain"))
+        .cloned()
+}
+
+// Find the mime type to serve for a `wl_data_offer.receive` request on an offer of this
+// source, if the source is still alive and offers a matching mime type
+//
+// This is the validation used by both selection and drag'n'drop offers.
+pub(crate) fn source_mime_type(source: &WlDataSource, requested: &str, loose_text: bool) -> Option<String> {
+    if !source.as_ref().is_alive() {
+        return None;
+    }
+    with_source_metadata(source, |meta| {
+        matching_mime_type(&meta.mime_types, requested, loose_text)
+    })
+    .ok()
+    .and_then(|mime_type| mime_type)
+}
+
+fn is_plain_text(mime_type: &str) -> bool {
+    mime_type == "text/plain" || mime_type.starts_with("text/plain;")
+}
+
 /// Access the metadata of a data source
 pub fn with_source_metadata<T, F: FnOnce(&SourceMetadata) -> T>(
     source: &WlDataSource,
     f: F,
 ) -> Result<T, ()> {
-    match source.as_ref().user_data().get::<RefCell<SourceMetadata>>() {
-        Some(data) => Ok(f(&data.borrow())),
+    match source.as_ref().user_data().get::<DataSourceData>() {
+        Some(data) => Ok(f(&data.metadata.borrow())),
         None => Err(()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn mime_order_is_preserved() {
+        let mime_types = strings(&["text/html", "text/plain", "UTF8_STRING"]);
+        assert_eq!(ordered_mime_types(&mime_types, &[]), mime_types);
+    }
+
+    #[test]
+    fn preferred_mime_types_come_first() {
+        let mime_types = strings(&["text/html", "UTF8_STRING", "text/plain", "image/png"]);
+        let preferred = strings(&["image/png", "application/pdf", "text/plain"]);
+        assert_eq!(
+            ordered_mime_types(&mime_types, &preferred),
+            strings(&["image/png", "text/plain", "text/html", "UTF8_STRING"])
+        );
+    }
+
+    #[test]
+    fn normalized_metadata() {
+        let metadata = SourceMetadata::new(strings(&["text/plain", "", "text/html", "text/plain"]));
+        assert_eq!(metadata.mime_types, strings(&["text/plain", "text/html"]));
+        assert!(metadata.dnd_action.is_empty());
+    }
+
+    #[test]
+    fn strict_mime_matching() {
+        let offered = strings(&["text/plain;charset=utf-8", "text/html"]);
+        assert_eq!(
+            matching_mime_type(&offered, "text/html", false),
+            Some("text/html".to_string())
+        );
+        assert_eq!(matching_mime_type(&offered, "text/plain", false), None);
+    }
+
+    #[test]
+    fn loose_text_matching_to_charset_variant() {
+        let offered = strings(&["text/html", "text/plain;charset=utf-8"]);
+        assert_eq!(
+            matching_mime_type(&offered, "text/plain", true),
+            Some("text/plain;charset=utf-8".to_string())
+        );
+        assert_eq!(matching_mime_type(&offered, "text/rtf", true), None);
+    }
+
+    #[test]
+    fn loose_text_matching_to_bare_text() {
+        let offered = strings(&["text/plain"]);
+        assert_eq!(
+            matching_mime_type(&offered, "text/plain;charset=utf-8", true),
+            Some("text/plain".to_string())
+        );
+        // two different charsets are not compatible
+        let offered = strings(&["text/plain;charset=iso-8859-1"]);
+        assert_eq!(
+            matching_mime_type(&offered, "text/plain;charset=utf-8", true),
+            None
+        );
+    }
+
+    #[test]
+    fn loose_text_matching_prefers_exact_match() {
+        let offered = strings(&["text/plain", "text/plain;charset=utf-8"]);
+        assert_eq!(
+            matching_mime_type(&offered, "text/plain;charset=utf-8", true),
+            Some("text/plain;charset=utf-8".to_string())
+        );
+    }
+}
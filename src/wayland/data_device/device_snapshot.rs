@@ -0,0 +1,126 @@
+use std::fmt;
+
+use wayland_server::Client;
+
+use crate::wayland::seat::Seat;
+
+use super::{SeatDataCell, Selection};
+
+/// Who is currently providing the selection of a seat
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SelectionOwner {
+    /// There is no selection
+    Empty,
+    /// The selection is provided by a client
+    Client,
+    /// The selection is provided by the compositor
+    Compositor,
+}
+
+/// A snapshot of the whole data device state of a seat
+///
+/// This is meant for debugging and test assertions, its `Debug` implementation gives a
+/// summary of the state suitable for logging. See [`data_device_snapshot`].
+#[derive(Clone)]
+pub struct DataDeviceSnapshot {
+    /// Who provides the current selection
+    pub selection: SelectionOwner,
+    /// The mime types offered by the current selection
+    pub selection_mime_types: Vec<String>,
+    /// The clients having data devices for this seat, with the number of data devices of each
+    pub devices: Vec<(Client, usize)>,
+    /// The client currently having the data device focus
+    pub focus: Option<Client>,
+    /// Whether a client-initiated drag'n'drop is currently active
+    pub dnd_active: bool,
+}
+
+impl fmt::Debug for DataDeviceSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataDeviceSnapshot")
+            .field("selection", &self.selection)
+            .field("selection_mime_types", &self.selection_mime_types)
+            .field(
+                "devices_per_client",
+                &self.devices.iter().map(|&(_, count)| count).collect::<Vec<_>>(),
+            )
+            .field("has_focus", &self.focus.is_some())
+            .field("dnd_active", &self.dnd_active)
+            .finish()
+    }
+}
+
+/// Take a snapshot of the data device state of this seat
+///
+/// If the data device logic was never used with this seat, the snapshot describes an
+/// empty state.
+pub fn data_device_snapshot(seat: &Seat) -> DataDeviceSnapshot {
+    let seat_data = match seat.user_data().get::<SeatDataCell>() {
+        Some(seat_data) => seat_data,
+        None => {
+            return DataDeviceSnapshot {
+                selection: SelectionOwner::Empty,
+                selection_mime_types: Vec::new(),
+                devices: Vec::new(),
+                focus: None,
+                dnd_active: false,
+            }
+        }
+    };
+    let mut seat_data = seat_data.borrow_mut();
+    let selection_mime_types = seat_data.mime_types();
+    let selection = match seat_data.selection {
+        Selection::Empty => SelectionOwner::Empty,
+        Selection::Client(_) => SelectionOwner::Client,
+        Selection::Compositor { .. } => SelectionOwner::Compositor,
+    };
+    let devices = seat_data
+        .known_devices
+        .per_client()
+        .filter_map(|devices| {
+            let alive = devices
+                .iter()
+                .filter(|dd| dd.as_ref().is_alive())
+                .collect::<Vec<_>>();
+            alive
+                .first()
+                .and_then(|dd| dd.as_ref().client())
+                .map(|client| (client, alive.len()))
+        })
+        .collect();
+    DataDeviceSnapshot {
+        selection,
+        selection_mime_types,
+        devices,
+        focus: seat_data.current_focus.clone(),
+        dnd_active: seat_data.current_dnd.is_some(),
+    }
+}
+
+/// Retrieve the client currently having the data device focus of this seat
+///
+/// Returns `None` if no client has the focus, or if the data device logic was never used
+/// with this seat.
+pub fn data_device_focus(seat: &Seat) -> Option<Client> {
+    seat.user_data()
+        .get::<SeatDataCell>()
+        .and_then(|seat_data| seat_data.borrow().current_focus.clone())
+}
+
+/// Retrieve who is currently providing the selection of this seat
+///
+/// A selection whose client is gone is reported as empty. Returns [`SelectionOwner::Empty`]
+/// if the data device logic was never used with this seat.
+pub fn current_selection(seat: &Seat) -> SelectionOwner {
+    let seat_data = match seat.user_data().get::<SeatDataCell>() {
+        Some(seat_data) => seat_data.borrow(),
+        None => return SelectionOwner::Empty,
+    };
+    match seat_data.selection {
+        Selection::Empty => SelectionOwner::Empty,
+        Selection::Client(ref source) if source.as_ref().is_alive() => SelectionOwner::Client,
+        Selection::Client(_) => SelectionOwner::Empty,
+        Selection::Compositor { ref content, .. } if !content.is_expired() => SelectionOwner::Compositor,
+        Selection::Compositor { .. } => SelectionOwner::Empty,
+    }
+}
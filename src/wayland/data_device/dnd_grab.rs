@@ -1,81 +1,163 @@
-use std::{cell::RefCell, ops::Deref as _, rc::Rc};
+use std::{
+    cell::RefCell,
+    ops::Deref as _,
+    rc::{Rc, Weak},
+};
 
 use wayland_server::{
-    protocol::{wl_data_device_manager::DndAction, wl_data_offer, wl_data_source, wl_pointer, wl_surface},
-    Main,
+    protocol::{
+        wl_data_device, wl_data_device_manager::DndAction, wl_data_offer, wl_data_source, wl_pointer,
+        wl_surface,
+    },
+    Filter, Main,
 };
 
 use crate::wayland::{
     compositor::{roles::Role, CompositorToken},
-    seat::{AxisFrame, GrabStartData, PointerGrab, PointerInnerHandle, Seat},
+    seat::{
+        AxisFrame, GrabStartData, PointerGrab, PointerInnerHandle, Seat, TouchGrab, TouchGrabStartData,
+        TouchInnerHandle,
+    },
     Serial,
 };
 
-use super::{with_source_metadata, DataDeviceData, DnDIconRole, SeatData};
+use super::{
+    data_source::source_mime_type, forget_dead_target, with_source_metadata, DataDeviceData, DnDIconRole,
+    DndActionContext, DndAuditRecord, Notification, SeatDataCell,
+};
 
-pub(crate) struct DnDGrab<R> {
-    start_data: GrabStartData,
+// The drag'n'drop of a client, following the pointer or a touch point depending on the start data
+pub(crate) struct DnDGrab<R: Role<DnDIconRole> + 'static, S = GrabStartData> {
+    start_data: S,
     data_source: Option<wl_data_source::WlDataSource>,
     current_focus: Option<wl_surface::WlSurface>,
+    // the last location of the pointer or touch point, relative to the current target
+    target_location: (f64, f64),
     pending_offers: Vec<wl_data_offer::WlDataOffer>,
+    // the data devices that were sent an `enter` for the current target, devices
+    // created afterwards must not receive the events of this drag'n'drop
+    entered_devices: Vec<wl_data_device::WlDataDevice>,
     offer_data: Option<Rc<RefCell<OfferData>>>,
     icon: Option<wl_surface::WlSurface>,
     origin: wl_surface::WlSurface,
     callback: Rc<RefCell<dyn FnMut(super::DataDeviceEvent)>>,
+    // the events for the compositor, delivered once the device released the grab
+    pending_events: Vec<super::DataDeviceEvent>,
     token: CompositorToken<R>,
     seat: Seat,
+    finished: bool,
 }
 
-impl<R: Role<DnDIconRole> + 'static> DnDGrab<R> {
+impl<R: Role<DnDIconRole> + 'static, S> DnDGrab<R, S> {
     pub(crate) fn new(
-        start_data: GrabStartData,
+        start_data: S,
         source: Option<wl_data_source::WlDataSource>,
         origin: wl_surface::WlSurface,
         seat: Seat,
         icon: Option<wl_surface::WlSurface>,
         token: CompositorToken<R>,
         callback: Rc<RefCell<dyn FnMut(super::DataDeviceEvent)>>,
-    ) -> DnDGrab<R> {
+    ) -> DnDGrab<R, S> {
         DnDGrab {
             start_data,
             data_source: source,
             current_focus: None,
+            target_location: (0.0, 0.0),
             pending_offers: Vec::with_capacity(1),
+            entered_devices: Vec::new(),
             offer_data: None,
             origin,
             icon,
             callback,
+            pending_events: Vec::new(),
             token,
             seat,
+            finished: false,
         }
     }
-}
 
-impl<R: Role<DnDIconRole> + 'static> PointerGrab for DnDGrab<R> {
-    fn motion(
+    // If the surface currently targeted by the drag was destroyed, forget about it
+    // and notify its client as if the pointer left it
+    fn check_target_alive(&mut self) {
+        if forget_dead_target(&mut self.current_focus, &mut self.entered_devices) {
+            self.disable_offers();
+        }
+    }
+
+    // Disable the offers made to the current target
+    fn disable_offers(&mut self) {
+        self.pending_offers.clear();
+        if let Some(offer_data) = self.offer_data.take() {
+            offer_data.borrow_mut().active = false;
+        }
+    }
+
+    // Abort the drag'n'drop before the drop, as if the pointer left the target and
+    // the drop was made on empty space
+    fn cancel(&mut self) {
+        self.check_target_alive();
+        self.current_focus = None;
+        for device in self.entered_devices.drain(..) {
+            device.leave();
+        }
+        self.disable_offers();
+        if let Some(ref source) = self.data_source {
+            if source.as_ref().is_alive() {
+                source.cancelled();
+            }
+        }
+        self.pending_events.push(super::DataDeviceEvent::DnDCancelled);
+        self.remove_icon_role();
+    }
+
+    // If the client destroyed the source of the drag'n'drop, nothing can be dropped anymore:
+    // cancel it. Returns whether it was cancelled, the grab must then be released.
+    fn check_source_alive(&mut self) -> bool {
+        if self
+            .data_source
+            .as_ref()
+            .map(|source| source.as_ref().is_alive())
+            .unwrap_or(true)
+        {
+            return false;
+        }
+        self.cancel();
+        self.finished = true;
+        true
+    }
+
+    fn remove_icon_role(&mut self) {
+        if let Some(icon) = self.icon.take() {
+            if icon.as_ref().is_alive() {
+                self.token.remove_role::<super::DnDIconRole>(&icon).unwrap();
+            }
+        }
+    }
+
+    // The pointer or touch point moved to this location, over this surface if any
+    fn move_to(
         &mut self,
-        _handle: &mut PointerInnerHandle<'_>,
         location: (f64, f64),
         focus: Option<(wl_surface::WlSurface, (f64, f64))>,
         serial: Serial,
         time: u32,
     ) {
+        self.check_target_alive();
         let (x, y) = location;
-        let seat_data = self
-            .seat
-            .user_data()
-            .get::<RefCell<SeatData>>()
-            .unwrap()
-            .borrow_mut();
+        let mut seat_data = self.seat.user_data().get::<SeatDataCell>().unwrap().borrow_mut();
+        if let Some(ref mut dnd) = seat_data.current_dnd {
+            dnd.location = location;
+        }
+        // `focus` is `None` when the pointer or touch point is not over any surface, for example
+        // if it is over an empty region or between outputs: the current target is left, and no motion
+        // is sent until the pointer enters a surface again
         if focus.as_ref().map(|&(ref s, _)| s) != self.current_focus.as_ref() {
             // focus changed, we need to make a leave if appropriate
             if let Some(surface) = self.current_focus.take() {
                 // only leave if there is a data source or we are on the original client
                 if self.data_source.is_some() || self.origin.as_ref().same_client_as(&surface.as_ref()) {
-                    for device in &seat_data.known_devices {
-                        if device.as_ref().same_client_as(&surface.as_ref()) {
-                            device.leave();
-                        }
+                    for device in self.entered_devices.drain(..) {
+                        device.leave();
                     }
                     // disable the offers
                     self.pending_offers.clear();
@@ -91,58 +173,80 @@ impl<R: Role<DnDIconRole> + 'static> PointerGrab for DnDGrab<R> {
                 Some(c) => c,
                 None => return,
             };
+            self.target_location = (x - sx, y - sy);
             if self.current_focus.is_none() {
                 // We entered a new surface, send the data offer if appropriate
                 if let Some(ref source) = self.data_source {
                     let offer_data = Rc::new(RefCell::new(OfferData {
                         active: true,
                         dropped: false,
-                        accepted: true,
+                        finished: false,
+                        // the target must accept a mime type for the drop to proceed
+                        accepted: false,
+                        accepted_mime_type: None,
                         chosen_action: DndAction::empty(),
+                        negotiates_actions: false,
+                        ask_serial: 0,
+                        ask_pending: false,
                     }));
+                    let ask_action = seat_data.ask_action.clone();
+                    let action_chooser = seat_data.action_chooser.clone();
                     for device in seat_data
                         .known_devices
                         .iter()
                         .filter(|d| d.as_ref().same_client_as(&surface.as_ref()))
                     {
-                        let action_choice = device
-                            .as_ref()
-                            .user_data()
-                            .get::<DataDeviceData>()
-                            .unwrap()
-                            .action_choice
-                            .clone();
-                        // create a data offer
-                        let offer = client
+                        let device_data = device.as_ref().user_data().get::<DataDeviceData>().unwrap();
+                        // the chooser of the seat takes precedence over the one of the global
+                        let action_choice = action_chooser
+                            .clone()
+                            .unwrap_or_else(|| device_data.action_choice.clone());
+                        let loose_text_matching = device_data.config.loose_text_matching;
+                        if device.as_ref().version() >= 3 && source.as_ref().version() >= 3 {
+                            offer_data.borrow_mut().negotiates_actions = true;
+                        }
+                        // create a data offer, unless the client is disconnecting; an object created
+                        // by an event has the version of the object sending it, so the offer has
+                        // the version of the data device whatever the version of the source, and
+                        // the events depending on the version are gated individually
+                        let offer = match client
                             .create_resource::<wl_data_offer::WlDataOffer>(device.as_ref().version())
-                            .map(|offer| {
-                                implement_dnd_data_offer(
-                                    offer,
-                                    source.clone(),
-                                    offer_data.clone(),
-                                    action_choice,
-                                )
-                            })
-                            .unwrap();
+                        {
+                            Some(offer) => implement_dnd_data_offer(
+                                offer,
+                                source.clone(),
+                                offer_data.clone(),
+                                action_choice,
+                                ask_action.clone(),
+                                loose_text_matching,
+                                seat_data.log.clone(),
+                            ),
+                            None => continue,
+                        };
                         // advertize the offer to the client
                         device.data_offer(&offer);
                         with_source_metadata(source, |meta| {
                             for mime_type in meta.mime_types.iter().cloned() {
                                 offer.offer(mime_type);
                             }
-                            offer.source_actions(meta.dnd_action);
+                            // `source_actions` only exists since version 3
+                            if offer.as_ref().version() >= 3 {
+                                offer.source_actions(meta.dnd_action);
+                            }
                         })
                         .unwrap();
                         device.enter(serial.into(), &surface, x - sx, y - sy, Some(&offer));
+                        self.entered_devices.push(device.clone());
                         self.pending_offers.push(offer);
                     }
                     self.offer_data = Some(offer_data);
                 } else {
                     // only send if we are on a surface of the same client
                     if self.origin.as_ref().same_client_as(&surface.as_ref()) {
-                        for device in &seat_data.known_devices {
+                        for device in seat_data.known_devices.iter() {
                             if device.as_ref().same_client_as(&surface.as_ref()) {
                                 device.enter(serial.into(), &surface, x - sx, y - sy, None);
+                                self.entered_devices.push(device.clone());
                             }
                         }
                     }
@@ -150,17 +254,110 @@ impl<R: Role<DnDIconRole> + 'static> PointerGrab for DnDGrab<R> {
                 self.current_focus = Some(surface);
             } else {
                 // make a move
-                if self.data_source.is_some() || self.origin.as_ref().same_client_as(&surface.as_ref()) {
-                    for device in &seat_data.known_devices {
-                        if device.as_ref().same_client_as(&surface.as_ref()) {
-                            device.motion(time, x - sx, y - sy);
-                        }
-                    }
+                for device in &self.entered_devices {
+                    device.motion(time, x - sx, y - sy);
                 }
             }
         }
     }
 
+    // The user released the pointer buttons or the touch point, proceed to the drop
+    fn perform_drop(&mut self) {
+        if self.check_source_alive() {
+            return;
+        }
+        // a drop on a destroyed surface is a drop on empty space
+        self.check_target_alive();
+        let seat_data = self.seat.user_data().get::<SeatDataCell>().unwrap().borrow_mut();
+        let validated = if let Some(ref data) = self.offer_data {
+            let data = data.borrow();
+            // a drop while the compositor did not resolve the `ask` action yet is cancelled
+            data.accepted && !(data.negotiates_actions && data.chosen_action.is_empty()) && !data.ask_pending
+        } else {
+            false
+        };
+        for device in self.entered_devices.drain(..) {
+            if validated {
+                device.drop();
+            } else {
+                device.leave();
+            }
+        }
+        let dnd_audit = seat_data.dnd_audit.clone();
+        ::std::mem::drop(seat_data);
+        if let Some(dnd_audit) = dnd_audit {
+            let action = match self.offer_data {
+                Some(ref data) if validated => data.borrow().chosen_action,
+                _ => DndAction::empty(),
+            };
+            let source_client = match self.data_source {
+                Some(ref source) => source.as_ref().client(),
+                None => self.origin.as_ref().client(),
+            };
+            (&mut *dnd_audit.borrow_mut())(DndAuditRecord {
+                source_client,
+                target_client: self.current_focus.as_ref().and_then(|s| s.as_ref().client()),
+                mime_types: self
+                    .data_source
+                    .as_ref()
+                    .and_then(|source| with_source_metadata(source, |meta| meta.mime_types.clone()).ok())
+                    .unwrap_or_default(),
+                action,
+            });
+        }
+        if let Some(ref offer_data) = self.offer_data {
+            let mut data = offer_data.borrow_mut();
+            if validated {
+                data.dropped = true;
+            } else {
+                data.active = false;
+            }
+        }
+        // a drop that no target accepted is a cancellation, the source must not
+        // believe the data was transferred
+        if let Some(ref source) = self.data_source {
+            if validated {
+                // dnd_drop_performed only exists since version 3
+                if source.as_ref().version() >= 3 {
+                    source.dnd_drop_performed();
+                }
+            } else {
+                source.cancelled();
+            }
+        }
+        if validated {
+            if let Some(ref target) = self.current_focus {
+                self.pending_events.push(super::DataDeviceEvent::DnDDroppedOn {
+                    target: target.clone(),
+                    location: self.target_location,
+                });
+            }
+            self.pending_events.push(super::DataDeviceEvent::DnDDropped);
+        } else {
+            self.pending_events.push(super::DataDeviceEvent::DnDCancelled);
+        }
+        self.remove_icon_role();
+        // in all cases abandon the drop
+        self.finished = true;
+    }
+}
+
+impl<R: Role<DnDIconRole> + 'static> PointerGrab for DnDGrab<R, GrabStartData> {
+    fn motion(
+        &mut self,
+        handle: &mut PointerInnerHandle<'_>,
+        location: (f64, f64),
+        focus: Option<(wl_surface::WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        if self.check_source_alive() {
+            handle.unset_grab(serial, time);
+            return;
+        }
+        self.move_to(location, focus, serial, time);
+    }
+
     fn button(
         &mut self,
         handle: &mut PointerInnerHandle<'_>,
@@ -170,53 +367,7 @@ impl<R: Role<DnDIconRole> + 'static> PointerGrab for DnDGrab<R> {
         time: u32,
     ) {
         if handle.current_pressed().is_empty() {
-            // the user dropped, proceed to the drop
-            let seat_data = self
-                .seat
-                .user_data()
-                .get::<RefCell<SeatData>>()
-                .unwrap()
-                .borrow_mut();
-            let validated = if let Some(ref data) = self.offer_data {
-                let data = data.borrow();
-                data.accepted && (!data.chosen_action.is_empty())
-            } else {
-                false
-            };
-            if let Some(ref surface) = self.current_focus {
-                if self.data_source.is_some() || self.origin.as_ref().same_client_as(&surface.as_ref()) {
-                    for device in &seat_data.known_devices {
-                        if device.as_ref().same_client_as(surface.as_ref()) {
-                            if validated {
-                                device.drop();
-                            } else {
-                                device.leave();
-                            }
-                        }
-                    }
-                }
-            }
-            if let Some(ref offer_data) = self.offer_data {
-                let mut data = offer_data.borrow_mut();
-                if validated {
-                    data.dropped = true;
-                } else {
-                    data.active = false;
-                }
-            }
-            if let Some(ref source) = self.data_source {
-                source.dnd_drop_performed();
-                if !validated {
-                    source.cancelled();
-                }
-            }
-            (&mut *self.callback.borrow_mut())(super::DataDeviceEvent::DnDDropped);
-            if let Some(icon) = self.icon.take() {
-                if icon.as_ref().is_alive() {
-                    self.token.remove_role::<super::DnDIconRole>(&icon).unwrap();
-                }
-            }
-            // in all cases abandon the drop
+            self.perform_drop();
             // no more buttons are pressed, release the grab
             handle.unset_grab(serial, time);
         }
@@ -232,73 +383,238 @@ impl<R: Role<DnDIconRole> + 'static> PointerGrab for DnDGrab<R> {
     }
 }
 
+impl<R: Role<DnDIconRole> + 'static> TouchGrab for DnDGrab<R, TouchGrabStartData> {
+    fn down(
+        &mut self,
+        handle: &mut TouchInnerHandle<'_>,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(wl_surface::WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        // the other touch points are forwarded as is
+        handle.down(slot, location, focus, serial, time);
+    }
+
+    fn up(&mut self, handle: &mut TouchInnerHandle<'_>, slot: i32, serial: Serial, time: u32) {
+        if slot != self.start_data.slot {
+            handle.up(slot, serial, time);
+            return;
+        }
+        // the clients see a drop rather than the touch point going up
+        handle.forget(slot);
+        self.perform_drop();
+        handle.unset_grab();
+    }
+
+    fn motion(
+        &mut self,
+        handle: &mut TouchInnerHandle<'_>,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(wl_surface::WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        if slot != self.start_data.slot {
+            handle.motion(slot, location, time);
+            return;
+        }
+        if self.check_source_alive() {
+            handle.forget(slot);
+            handle.unset_grab();
+            return;
+        }
+        self.move_to(location, focus, serial, time);
+    }
+
+    fn frame(&mut self, handle: &mut TouchInnerHandle<'_>) {
+        handle.frame();
+    }
+
+    fn cancel(&mut self, handle: &mut TouchInnerHandle<'_>) {
+        // the touch point will never go up, there is no drop
+        handle.cancel();
+        self.cancel();
+        self.finished = true;
+        handle.unset_grab();
+    }
+
+    fn start_data(&self) -> &TouchGrabStartData {
+        &self.start_data
+    }
+}
+
+impl<R: Role<DnDIconRole> + 'static, S> Drop for DnDGrab<R, S> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // another grab replaced this one before the drop, the source and
+            // the target must not be left waiting for it
+            self.cancel();
+        }
+        // the drag'n'drop is over, whether it completed or the grab was replaced; the compositor
+        // is notified once the device and its callback are released, so that it can for example
+        // start another drag'n'drop when handling the events
+        if let Some(seat_data) = self.seat.user_data().get::<SeatDataCell>() {
+            let mut seat_data = seat_data.borrow_mut();
+            seat_data.current_dnd = None;
+            for event in self.pending_events.drain(..) {
+                seat_data
+                    .pending_notifications
+                    .push(Notification::Event(self.callback.clone(), event));
+            }
+        }
+    }
+}
+
 struct OfferData {
     active: bool,
     dropped: bool,
+    finished: bool,
     accepted: bool,
+    // the last mime type accepted by the target, the only one it can receive
+    accepted_mime_type: Option<String>,
     chosen_action: DndAction,
+    // whether both sides negotiate an action (version 3 and later), only
+    // then a drop without chosen action is meaningless
+    negotiates_actions: bool,
+    // incremented each time the negotiation results in the `ask` action, invalidating the
+    // resolvers of the previous negotiations
+    ask_serial: u64,
+    // whether the compositor still has to resolve the `ask` action
+    ask_pending: bool,
+}
+
+/// A pending choice of the action of a drag'n'drop, on behalf of the user
+///
+/// When the source and the target of a drag'n'drop negotiated the `ask` action, the callback set
+/// with [`set_ask_action_callback`](super::set_ask_action_callback) receives this resolver, so
+/// that your compositor can for example show a menu to the user. The drag'n'drop is cancelled if
+/// it is dropped before you call [`choose`](AskActionResolver::choose). Dropping the resolver
+/// without choosing leaves the action unresolved.
+pub struct AskActionResolver {
+    offer_data: Weak<RefCell<OfferData>>,
+    ask_serial: u64,
+    offer: wl_data_offer::WlDataOffer,
+    source: wl_data_source::WlDataSource,
+    available: DndAction,
+}
+
+impl AskActionResolver {
+    /// The actions the source and the target have in common
+    pub fn available_actions(&self) -> DndAction {
+        self.available
+    }
+
+    /// Resolve the action of the drag'n'drop
+    ///
+    /// The action must be `Copy` or `Move`, and one of the available actions. It is ignored
+    /// otherwise, as well as if the drag'n'drop was dropped, cancelled or negotiated again in
+    /// the meantime.
+    pub fn choose(self, action: DndAction) {
+        if ![DndAction::Copy, DndAction::Move].contains(&action) || !self.available.contains(action) {
+            return;
+        }
+        let offer_data = match self.offer_data.upgrade() {
+            Some(offer_data) => offer_data,
+            None => return,
+        };
+        let mut data = offer_data.borrow_mut();
+        if !data.active || data.dropped || !data.ask_pending || data.ask_serial != self.ask_serial {
+            return;
+        }
+        data.ask_pending = false;
+        data.chosen_action = action;
+        // notify both sides of the outcome of the negotiation, as for any other action
+        if self.offer.as_ref().is_alive() {
+            self.offer.action(action);
+        }
+        if self.source.as_ref().is_alive() && self.source.as_ref().version() >= 3 {
+            self.source.action(action);
+        }
+    }
 }
 
 fn implement_dnd_data_offer(
     offer: Main<wl_data_offer::WlDataOffer>,
     source: wl_data_source::WlDataSource,
     offer_data: Rc<RefCell<OfferData>>,
-    action_choice: Rc<RefCell<dyn FnMut(DndAction, DndAction) -> DndAction + 'static>>,
+    action_choice: Rc<RefCell<dyn FnMut(&DndActionContext<'_>) -> DndAction + 'static>>,
+    ask_action: Option<Rc<RefCell<dyn FnMut(AskActionResolver)>>>,
+    loose_text_matching: bool,
+    log: ::slog::Logger,
 ) -> wl_data_offer::WlDataOffer {
     use self::wl_data_offer::Request;
+    let destructor_data = offer_data.clone();
+    let destructor_source = source.clone();
     offer.quick_assign(move |offer, req, _| {
         let mut data = offer_data.borrow_mut();
+        let mut ask_resolver = None;
         match req {
             Request::Accept { mime_type, .. } => {
-                if let Some(mtype) = mime_type {
+                if let Some(ref mtype) = mime_type {
                     if let Err(()) = with_source_metadata(&source, |meta| {
-                        data.accepted = meta.mime_types.contains(&mtype);
+                        data.accepted = meta.mime_types.contains(mtype);
                     }) {
                         data.accepted = false;
                     }
                 } else {
                     data.accepted = false;
                 }
+                data.accepted_mime_type = mime_type.clone().filter(|_| data.accepted);
+                // forward the feedback of the target to the source, only while the
+                // drag'n'drop is ongoing as `target` is meaningless otherwise
+                if data.active && !data.dropped && source.as_ref().is_alive() {
+                    source.target(mime_type.filter(|_| data.accepted));
+                }
             }
             Request::Receive { mime_type, fd } => {
-                // check if the source and associated mime type is still valid
-                let valid = with_source_metadata(&source, |meta| meta.mime_types.contains(&mime_type))
-                    .unwrap_or(false)
-                    && source.as_ref().is_alive()
-                    && data.active;
-                if valid {
-                    source.send(mime_type, fd);
+                // the target can only receive the mime type it accepted
+                if data.accepted_mime_type.as_ref() != Some(&mime_type) {
+                    debug!(
+                        log,
+                        "Denying a wl_data_offer.receive for a mime type that was not accepted."
+                    );
+                    super::close_fd(fd);
+                    return;
+                }
+                // check if the source and associated mime type is still valid,
+                // the same way as for the selection, the target must also not
+                // request data anymore once it declared the transfer finished
+                if data.active && !data.finished && source.as_ref().is_alive() {
+                    if let Some(mime_type) = source_mime_type(&source, &mime_type, loose_text_matching) {
+                        source.send(mime_type, fd);
+                    }
                 }
-                let _ = ::nix::unistd::close(fd);
+                super::close_fd(fd);
             }
             Request::Destroy => {}
             Request::Finish => {
-                if !data.active {
-                    offer.as_ref().post_error(
-                        wl_data_offer::Error::InvalidFinish as u32,
-                        "Cannot finish a data offer that is no longer active.".into(),
-                    );
-                }
-                if !data.accepted {
-                    offer.as_ref().post_error(
-                        wl_data_offer::Error::InvalidFinish as u32,
-                        "Cannot finish a data offer that has not been accepted.".into(),
-                    );
-                }
-                if !data.dropped {
-                    offer.as_ref().post_error(
-                        wl_data_offer::Error::InvalidFinish as u32,
-                        "Cannot finish a data offer that has not been dropped.".into(),
-                    );
+                let error = if !data.active {
+                    Some("Cannot finish a data offer that is no longer active.")
+                } else if !data.accepted {
+                    Some("Cannot finish a data offer that has not been accepted.")
+                } else if !data.dropped {
+                    Some("Cannot finish a data offer that has not been dropped.")
+                } else if data.chosen_action.is_empty() {
+                    Some("Cannot finish a data offer with no valid action.")
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    // the protocol error disconnects the target, the transfer must not be reported as done
+                    offer
+                        .as_ref()
+                        .post_error(wl_data_offer::Error::InvalidFinish as u32, error.into());
+                    return;
                 }
-                if data.chosen_action.is_empty() {
-                    offer.as_ref().post_error(
-                        wl_data_offer::Error::InvalidFinish as u32,
-                        "Cannot finish a data offer with no valid action.".into(),
-                    );
+                // dnd_finished only exists since version 3
+                if source.as_ref().is_alive() && source.as_ref().version() >= 3 {
+                    source.dnd_finished();
                 }
-                source.dnd_finished();
                 data.active = false;
+                data.finished = true;
             }
             Request::SetActions {
                 dnd_actions,
@@ -310,21 +626,71 @@ fn implement_dnd_data_offer(
                         wl_data_offer::Error::InvalidAction as u32,
                         "Invalid preferred action.".into(),
                     );
+                    return;
                 }
-                let source_actions = with_source_metadata(&source, |meta| meta.dnd_action)
-                    .unwrap_or_else(|_| DndAction::empty());
+                if !data.active || data.finished {
+                    // the negotiation is over, there is nobody left to notify
+                    return;
+                }
+                let (source_actions, mime_types) =
+                    with_source_metadata(&source, |meta| (meta.dnd_action, meta.mime_types.clone()))
+                        .unwrap_or_else(|_| (DndAction::empty(), Vec::new()));
                 let possible_actions = source_actions & dnd_actions;
-                data.chosen_action = (&mut *action_choice.borrow_mut())(possible_actions, preferred_action);
-                // check that the user provided callback respects that one precise action should be chosen
-                debug_assert!(
-                    [DndAction::Move, DndAction::Copy, DndAction::Ask].contains(&data.chosen_action)
-                );
+                let context = DndActionContext {
+                    available: possible_actions,
+                    preferred: preferred_action,
+                    source_client: source.as_ref().client(),
+                    target_client: offer.as_ref().client(),
+                    mime_types: &mime_types,
+                };
+                data.chosen_action = (&mut *action_choice.borrow_mut())(&context);
+                // check that the user provided callback respects that one precise action should be chosen,
+                // or none if the source and the target have no action in common
+                debug_assert!([
+                    DndAction::empty(),
+                    DndAction::Move,
+                    DndAction::Copy,
+                    DndAction::Ask
+                ]
+                .contains(&data.chosen_action));
+                // notify both sides of the outcome of the negotiation, `action` only exists since version 3
                 offer.action(data.chosen_action);
-                source.action(data.chosen_action);
+                if source.as_ref().is_alive() && source.as_ref().version() >= 3 {
+                    source.action(data.chosen_action);
+                }
+                // let the compositor resolve the `ask` action, if it wants to
+                data.ask_pending = data.chosen_action == DndAction::Ask && ask_action.is_some();
+                if data.ask_pending {
+                    data.ask_serial += 1;
+                    ask_resolver = Some(AskActionResolver {
+                        offer_data: Rc::downgrade(&offer_data),
+                        ask_serial: data.ask_serial,
+                        offer: offer.deref().clone(),
+                        source: source.clone(),
+                        available: possible_actions,
+                    });
+                }
             }
             _ => unreachable!(),
         }
+        // the offer data is released, so that the compositor can resolve the action right away
+        ::std::mem::drop(data);
+        if let (Some(resolver), Some(ask_action)) = (ask_resolver, ask_action.as_ref()) {
+            (&mut *ask_action.borrow_mut())(resolver);
+        }
     });
+    // a target destroying the offer (or disconnecting) after the drop without finishing it
+    // aborts the transfer, the source would otherwise wait for `dnd_finished` forever; before
+    // version 3 there is no `finish`, and destroying the offer is how the transfer ends
+    offer.assign_destructor(Filter::new(move |_offer: wl_data_offer::WlDataOffer, _, _| {
+        let mut data = destructor_data.borrow_mut();
+        if data.negotiates_actions && data.active && data.dropped && !data.finished {
+            data.active = false;
+            if destructor_source.as_ref().is_alive() {
+                destructor_source.cancelled();
+            }
+        }
+    }));
 
     offer.deref().clone()
 }
@@ -0,0 +1,123 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use wayland_server::{protocol::wl_data_device::WlDataDevice, Client};
+
+static NEXT_CLIENT_TOKEN: AtomicUsize = AtomicUsize::new(0);
+
+// A unique identifier of a client, stored in its data map
+struct ClientToken(usize);
+
+fn client_token(client: &Client) -> Option<usize> {
+    client.data_map().get::<ClientToken>().map(|token| token.0)
+}
+
+// The data devices of a seat, grouped by client
+//
+// This allows sending the selection to the focused client without going through the data
+// devices of all the other clients. Clients are ordered by the creation of their first data
+// device.
+#[derive(Default)]
+pub(crate) struct KnownDevices {
+    devices: BTreeMap<usize, Vec<WlDataDevice>>,
+}
+
+impl KnownDevices {
+    pub(crate) fn push(&mut self, dd: WlDataDevice) {
+        let client = match dd.as_ref().client() {
+            Some(client) => client,
+            // the client is already gone
+            None => return,
+        };
+        client
+            .data_map()
+            .insert_if_missing(|| ClientToken(NEXT_CLIENT_TOKEN.fetch_add(1, Ordering::Relaxed)));
+        if let Some(token) = client_token(&client) {
+            self.devices.entry(token).or_insert_with(Vec::new).push(dd);
+        }
+    }
+
+    // The data devices of a client
+    pub(crate) fn of_client(&self, client: &Client) -> &[WlDataDevice] {
+        client_token(client)
+            .and_then(|token| self.devices.get(&token))
+            .map(|devices| &devices[..])
+            .unwrap_or(&[])
+    }
+
+    // The data devices of all the clients
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &WlDataDevice> {
+        self.devices.values().flatten()
+    }
+
+    // The data devices of each client
+    pub(crate) fn per_client(&self) -> impl Iterator<Item = &[WlDataDevice]> {
+        self.devices.values().map(|devices| &devices[..])
+    }
+
+    pub(crate) fn contains(&self, dd: &WlDataDevice) -> bool {
+        self.iter().any(|known| known.as_ref().equals(dd.as_ref()))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub(crate) fn retain<F: FnMut(&WlDataDevice) -> bool>(&mut self, mut keep: F) {
+        for devices in self.devices.values_mut() {
+            devices.retain(&mut keep);
+        }
+        let emptied = self
+            .devices
+            .iter()
+            .filter(|&(_, devices)| devices.is_empty())
+            .map(|(&token, _)| token)
+            .collect::<Vec<_>>();
+        for token in emptied {
+            self.devices.remove(&token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::{io::IntoRawFd, net::UnixStream};
+
+    use wayland_server::Display;
+
+    #[test]
+    fn grouped_by_client() {
+        let mut display = Display::new();
+        let (server, _client) = UnixStream::pair().unwrap();
+        let client = unsafe { display.create_client(server.into_raw_fd(), &mut ()) };
+        let (other_server, _other_client) = UnixStream::pair().unwrap();
+        let other = unsafe { display.create_client(other_server.into_raw_fd(), &mut ()) };
+        let create_device = |client: &Client| (*client.create_resource::<WlDataDevice>(3).unwrap()).clone();
+
+        let mut devices = KnownDevices::default();
+        let first = create_device(&client);
+        devices.push(first.clone());
+        devices.push(create_device(&other));
+        devices.push(create_device(&client));
+        assert_eq!(devices.of_client(&client).len(), 2);
+        assert_eq!(devices.of_client(&other).len(), 1);
+        assert_eq!(
+            devices
+                .per_client()
+                .map(|devices| devices.len())
+                .collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+
+        // the group of a client is dropped once it has no data device left
+        devices.retain(|dd| dd.as_ref().client().map(|c| !c.equals(&other)).unwrap_or(false));
+        assert_eq!(devices.per_client().count(), 1);
+        assert!(devices.of_client(&other).is_empty());
+        assert!(devices.contains(&first));
+        assert_eq!(devices.iter().count(), 2);
+    }
+}
@@ -10,7 +10,8 @@
 //!   during the compositor startup to initialize the data device logic
 //! - [`set_data_device_focus`](::wayland::data_device::set_data_device_focus): this function sets
 //!   the data device focus for a given seat; you'd typically call it whenever the keyboard focus
-//!   changes, to follow it (for example in the focus hook of your keyboards)
+//!   changes, to follow it (for example in the focus hook of your keyboards), or let smithay do it
+//!   by enabling [`follow_keyboard_focus`](::wayland::data_device::DataDeviceConfig::follow_keyboard_focus)
 //!
 //! Using these two functions is enough for your clients to be able to interact with each other using
 //! the data devices.
@@ -19,14 +20,68 @@
 //! the contents of the data device:
 //!
 //! - You can provide a callback closure to [`init_data_device`](::wayland::data_device::init_data_device)
-//!   to peek into the the actions of your clients
+//!   to peek into the the actions of your clients, or receive them through a calloop channel
+//!   using [`init_data_device_channel`](::wayland::data_device::init_data_device_channel)
 //! - the freestanding function [`set_data_device_selection`](::wayland::data_device::set_data_device_selection)
 //!   allows you to set the contents of the selection for your clients
+//! - the freestanding functions [`copy_bytes`](::wayland::data_device::copy_bytes) and
+//!   [`copy_bytes_sniffed`](::wayland::data_device::copy_bytes_sniffed) allow you to set the selection
+//!   to some bytes that smithay will serve to your clients by itself
+//! - if you provide the contents of a selection yourself, the freestanding function
+//!   [`write_selection_async`](::wayland::data_device::write_selection_async) allows you to
+//!   write them from your event loop without blocking it
+//! - the freestanding function [`read_selection`](::wayland::data_device::read_selection) allows
+//!   you to read the current selection yourself, whoever provides it
 //! - the freestanding function [`start_dnd`](::wayland::data_device::start_dnd) allows you to initiate a drag'n'drop event from the compositor
 //!   itself and receive interactions of clients with it via an other dedicated callback.
 //!
 //! The module also defines the `DnDIconRole` that you need to insert into your compositor roles enum, to
-//! represent surfaces that are used as a DnD icon.
+//! represent surfaces that are used as a DnD icon. While a client drag'n'drop is active, its icon can be
+//! retrieved with [`dnd_icon_surface`](::wayland::data_device::dnd_icon_surface) and drawn at the location
+//! given by [`dnd_icon_position`](::wayland::data_device::dnd_icon_position).
+//!
+//! ## Nested compositors
+//!
+//! When your compositor runs inside of an other wayland compositor, you can share the clipboard
+//! of your clients with the ones of the parent compositor by mirroring the selections both ways:
+//!
+//! - when the selection of the parent compositor changes, advertise it to your clients using
+//!   [`install_remote_selection`](::wayland::data_device::install_remote_selection). You only
+//!   need the mime types of the `wl_data_offer` of the parent, each paste of your clients is
+//!   then forwarded to its `receive` request, no data is read beforehand.
+//! - when one of your clients sets the selection, the callback set with
+//!   [`set_selection_change_callback`](::wayland::data_device::set_selection_change_callback)
+//!   receives its mime types. You can then set the selection of the parent compositor to a
+//!   `wl_data_source` of your own offering them, and write the data requested by its `send`
+//!   events with [`request_selection`](::wayland::data_device::request_selection).
+//!
+//! Remote selections are compositor selections, ignoring them when mirroring the selections of
+//! your clients ensures the selection does not bounce back and forth between both compositors.
+//!
+//! ```
+//! # extern crate smithay;
+//! # use std::os::unix::io::RawFd;
+//! use smithay::wayland::data_device::{install_remote_selection, set_selection_change_callback};
+//! # fn parent_receive(_mime_type: String, _fd: RawFd) {}
+//! # fn parent_set_selection(_mime_types: Vec<String>) {}
+//! # fn example(seat: &smithay::wayland::seat::Seat, parent_mime_types: Vec<String>) {
+//!
+//! // the selection of the parent compositor changed
+//! install_remote_selection(seat, parent_mime_types, |mime_type, fd| {
+//!     // forward the request to the `wl_data_offer` of the parent compositor
+//!     parent_receive(mime_type, fd)
+//! });
+//!
+//! set_selection_change_callback(seat, |change| {
+//!     // only mirror the selections of your clients
+//!     if change.selection.client_source().is_some() {
+//!         // set a `wl_data_source` of your own as selection of the parent compositor, and
+//!         // answer its `send` events with `request_selection(&seat, &mime_type, fd)`
+//!         parent_set_selection(change.selection.mime_types().to_vec());
+//!     }
+//! });
+//! # }
+//! ```
 //!
 //! ## Initialization
 //!
@@ -52,8 +107,28 @@
 //!     None                     // insert a logger here
 //! );
 //! ```
+//!
+//! If the [`default_action_chooser`](::wayland::data_device::default_action_chooser) suits you,
+//! [`init_data_device_with_defaults`](::wayland::data_device::init_data_device_with_defaults)
+//! spares you from providing it, and the
+//! [`DataDeviceBuilder`](::wayland::data_device::DataDeviceBuilder) lets you set each of these
+//! arguments by name. A policy depending on the clients involved or on the mime types offered
+//! can be set for a seat with
+//! [`set_dnd_action_chooser`](::wayland::data_device::set_dnd_action_chooser).
+
+use std::{
+    cell::{Cell, Ref, RefCell, RefMut},
+    collections::HashMap,
+    io::Read,
+    ops::{Deref, DerefMut},
+    os::unix::io::RawFd,
+    path::PathBuf,
+    rc::{Rc, Weak},
+    sync::Arc,
+    time::Duration,
+};
 
-use std::{cell::RefCell, ops::Deref as _, os::unix::io::RawFd, rc::Rc};
+use calloop::channel::{self, Channel};
 
 use wayland_server::{
     protocol::{
@@ -66,16 +141,55 @@ use wayland_server::{
 
 use crate::wayland::{
     compositor::{roles::Role, CompositorToken},
-    seat::{GrabStartData, Seat},
+    seat::{GrabStartData, Seat, SeatRc},
     Serial,
 };
 
+mod async_transfer;
+mod builder;
+mod compositor_selection;
 mod data_source;
+mod device_snapshot;
 mod dnd_grab;
+mod known_devices;
+mod paste_confirmation;
+mod selection_cache;
+mod selection_history;
+mod selection_manager;
+mod selection_reader;
+mod selection_snapshot;
 mod server_dnd_grab;
+#[cfg(any(test, feature = "test_utils"))]
+pub mod test_harness;
+mod uri_list;
 
+pub use self::async_transfer::{write_selection_async, write_selection_async_with_completion};
+pub use self::builder::DataDeviceBuilder;
+pub use self::compositor_selection::{
+    sniff_mime_types, OversizedSelection, SelectionSizeLimit, SelectionSource,
+};
+pub(crate) use self::data_source::accept_offered_mime_type;
 pub use self::data_source::{with_source_metadata, SourceMetadata};
+pub use self::device_snapshot::{
+    current_selection, data_device_focus, data_device_snapshot, DataDeviceSnapshot, SelectionOwner,
+};
+pub use self::dnd_grab::AskActionResolver;
+pub use self::paste_confirmation::PasteRequest;
+pub use self::selection_manager::{SelectionKind, SelectionManager};
+pub use self::selection_reader::{read_selection, SelectionReader};
+pub use self::selection_snapshot::{selections_equal, SelectionSnapshot};
 pub use self::server_dnd_grab::ServerDndEvent;
+pub use self::uri_list::{uri_list_metadata, uri_list_provider};
+
+use self::compositor_selection::SelectionContent;
+use self::data_source::{
+    matching_mime_type, normalize_mime_types, ordered_mime_types, source_mime_type, DataSourceData,
+};
+use self::known_devices::KnownDevices;
+use self::paste_confirmation::{confirm_paste, PasteConfirmation};
+use self::selection_cache::SelectionCache;
+use self::selection_history::SelectionHistory;
+use self::server_dnd_grab::ServerDndCallback;
 
 /// Events that are generated by interactions of the clients with the data device
 pub enum DataDeviceEvent {
@@ -91,6 +205,12 @@ pub enum DataDeviceEvent {
         /// The icon the client requested to be used to be associated with the cursor icon
         /// during the drag'n'drop.
         icon: Option<wl_surface::WlSurface>,
+        /// The surface the drag'n'drop originates from
+        ///
+        /// Its client is the one that started the drag'n'drop.
+        origin: wl_surface::WlSurface,
+        /// The serial of the pointer grab the drag'n'drop was started from
+        serial: Serial,
     },
     /// The drag'n'drop action was finished by the user releasing the buttons
     ///
@@ -98,7 +218,48 @@ pub enum DataDeviceEvent {
     ///
     /// Note that this event will only be genrated for client-initiated drag'n'drop session.
     DnDDropped,
+    /// The drag'n'drop was dropped on a surface
+    ///
+    /// This is generated right before [`DnDDropped`](DataDeviceEvent::DnDDropped) when the
+    /// target accepted the drop, and tells where the drop occurred.
+    ///
+    /// Note that this event will only be generated for client-initiated drag'n'drop session.
+    DnDDroppedOn {
+        /// The surface the drag'n'drop was dropped on
+        target: wl_surface::WlSurface,
+        /// The location of the drop, relative to the target surface
+        location: (f64, f64),
+    },
+    /// The drag'n'drop action was cancelled
+    ///
+    /// This happens when the user releases the buttons while no target accepted the
+    /// drag'n'drop, in which case no data will be transferred. At this point, any pointer
+    /// icon should be removed.
+    ///
+    /// This also happens if the pointer grab of the drag'n'drop is replaced or removed
+    /// before the drop, for example by your compositor starting an other grab.
+    ///
+    /// Note that this event will only be generated for client-initiated drag'n'drop session.
+    DnDCancelled,
+    /// The selection was cleared
+    ///
+    /// This happens when a client or your compositor clears the selection, as well as when the
+    /// client providing it is gone.
+    SelectionCleared,
+    /// The selection of a client was replaced by the data source of an other one
+    ///
+    /// The data source that lost the selection was sent a `cancelled` event.
+    SelectionSuperseded {
+        /// The data source that held the selection
+        previous: wl_data_source::WlDataSource,
+        /// The data source now holding the selection
+        source: wl_data_source::WlDataSource,
+    },
     /// A client requested to read the server-set selection
+    ///
+    /// You are responsible for writing the contents into the fd and closing it, which can be
+    /// done without blocking your event loop using [`write_selection_async`]. If you need to
+    /// know when the client is done reading them, use [`write_selection_async_with_completion`].
     SendSelection {
         /// the requested mime type
         mime_type: String,
@@ -107,6 +268,58 @@ pub enum DataDeviceEvent {
     },
 }
 
+/// A notification that the selection of a seat changed
+///
+/// See [`set_selection_change_callback`].
+pub struct SelectionChanged {
+    /// The new selection
+    pub selection: SelectionSnapshot,
+    /// The tag of the new selection
+    ///
+    /// It is only set for selections set using [`set_data_device_selection_tagged`]. This
+    /// allows for example a bridge mirroring the selection with an external system to
+    /// recognize the selections it installed itself, and to not mirror them back.
+    pub tag: Option<u64>,
+}
+
+/// A record describing a drag'n'drop that was dropped by the user
+///
+/// See [`set_dnd_audit_callback`].
+pub struct DndAuditRecord {
+    /// The client that started the drag'n'drop
+    ///
+    /// It is `None` for drag'n'drop sessions initiated by the compositor.
+    pub source_client: Option<Client>,
+    /// The client owning the surface on which the drag'n'drop was dropped
+    ///
+    /// It is `None` if it was dropped on no surface.
+    pub target_client: Option<Client>,
+    /// The mime types offered by the source of the drag'n'drop
+    pub mime_types: Vec<String>,
+    /// The final action negotiated between the source and the target
+    ///
+    /// It is empty if the drop was cancelled, in which case no data will be transferred.
+    pub action: DndAction,
+}
+
+/// The context of the choice of the action of a drag'n'drop
+///
+/// See [`set_dnd_action_chooser`].
+pub struct DndActionContext<'a> {
+    /// The actions supported by both the source and the target
+    pub available: DndAction,
+    /// The action preferred by the target
+    pub preferred: DndAction,
+    /// The client that started the drag'n'drop
+    ///
+    /// It is `None` for drag'n'drop sessions initiated by the compositor.
+    pub source_client: Option<Client>,
+    /// The client owning the surface the drag'n'drop currently hovers
+    pub target_client: Option<Client>,
+    /// The mime types offered by the source of the drag'n'drop
+    pub mime_types: &'a [String],
+}
+
 /// The role applied to surfaces used as DnD icons
 #[derive(Default)]
 pub struct DnDIconRole;
@@ -114,425 +327,5458 @@ pub struct DnDIconRole;
 enum Selection {
     Empty,
     Client(wl_data_source::WlDataSource),
-    Compositor(SourceMetadata),
+    Compositor {
+        metadata: SourceMetadata,
+        content: Rc<SelectionContent>,
+    },
+}
+
+// A notification queued while the data device state of a seat is borrowed
+enum Notification {
+    Event(Rc<RefCell<dyn FnMut(DataDeviceEvent)>>, DataDeviceEvent),
+    Changed(Rc<RefCell<dyn FnMut(SelectionChanged)>>, SelectionChanged),
+    Listener(Rc<dyn Fn(&SelectionSnapshot)>, SelectionSnapshot),
+    Serve(Rc<SelectionContent>, String, RawFd, ::slog::Logger),
+    ServerDnd(Rc<dyn ServerDndCallback>, ServerDndEvent),
+}
+
+impl Notification {
+    // deliver the notification, or give it back if its callback is already running
+    fn deliver(self) -> Option<Notification> {
+        match self {
+            Notification::Event(callback, event) => {
+                let pending = match callback.try_borrow_mut() {
+                    Ok(mut cb) => {
+                        (&mut *cb)(event);
+                        None
+                    }
+                    Err(_) => Some(event),
+                };
+                pending.map(|event| Notification::Event(callback, event))
+            }
+            Notification::Changed(callback, change) => {
+                let pending = match callback.try_borrow_mut() {
+                    Ok(mut cb) => {
+                        (&mut *cb)(change);
+                        None
+                    }
+                    Err(_) => Some(change),
+                };
+                pending.map(|change| Notification::Changed(callback, change))
+            }
+            Notification::Listener(listener, selection) => {
+                listener(&selection);
+                None
+            }
+            Notification::Serve(content, mime_type, fd, log) => {
+                content.serve(&mime_type, fd, None, &log);
+                None
+            }
+            Notification::ServerDnd(callback, event) => callback
+                .try_call(event)
+                .err()
+                .map(|event| Notification::ServerDnd(callback, event)),
+        }
+    }
+}
+
+// The data device state of a seat
+//
+// The notifications queued while it is mutably borrowed are delivered once the borrow is
+// released, so that the compositor can use the functions of this module when handling them.
+struct SeatDataCell(RefCell<SeatData>);
+
+impl SeatDataCell {
+    fn borrow(&self) -> Ref<'_, SeatData> {
+        self.0.borrow()
+    }
+
+    fn borrow_mut(&self) -> SeatDataMut<'_> {
+        SeatDataMut {
+            cell: self,
+            seat_data: Some(self.0.borrow_mut()),
+        }
+    }
+
+    fn deliver_notifications(&self) {
+        loop {
+            let pending = match self.0.try_borrow_mut() {
+                Ok(mut seat_data) => ::std::mem::replace(&mut seat_data.pending_notifications, Vec::new()),
+                // an outer borrow delivers them when it is released
+                Err(_) => return,
+            };
+            if pending.is_empty() {
+                return;
+            }
+            let count = pending.len();
+            // the callbacks already running receive their notifications once they return
+            let busy = pending
+                .into_iter()
+                .filter_map(Notification::deliver)
+                .collect::<Vec<_>>();
+            let delivered = busy.len() < count;
+            if let Ok(mut seat_data) = self.0.try_borrow_mut() {
+                seat_data.pending_notifications.splice(0..0, busy);
+            }
+            if !delivered {
+                // retrying would not deliver anything more
+                return;
+            }
+        }
+    }
+}
+
+// Queue a notification of the compositor, delivered right away unless the state of this seat
+// or the callback is busy
+fn notify(seat: &Seat, notification: Notification) {
+    seat_data(seat)
+        .borrow_mut()
+        .pending_notifications
+        .push(notification);
+}
+
+// Forget the target of a drag'n'drop if its surface was destroyed, leaving the data devices
+// that entered it, in which case the offers made to it must be disabled
+fn forget_dead_target(
+    current_focus: &mut Option<wl_surface::WlSurface>,
+    entered_devices: &mut Vec<wl_data_device::WlDataDevice>,
+) -> bool {
+    if current_focus
+        .as_ref()
+        .map(|surface| surface.as_ref().is_alive())
+        .unwrap_or(true)
+    {
+        return false;
+    }
+    *current_focus = None;
+    for device in entered_devices.drain(..) {
+        device.leave();
+    }
+    true
+}
+
+// A mutable borrow of the data device state of a seat, see `SeatDataCell`
+struct SeatDataMut<'a> {
+    cell: &'a SeatDataCell,
+    seat_data: Option<RefMut<'a, SeatData>>,
+}
+
+impl<'a> Deref for SeatDataMut<'a> {
+    type Target = SeatData;
+
+    fn deref(&self) -> &SeatData {
+        self.seat_data.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for SeatDataMut<'a> {
+    fn deref_mut(&mut self) -> &mut SeatData {
+        self.seat_data.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for SeatDataMut<'a> {
+    fn drop(&mut self) {
+        self.seat_data = None;
+        self.cell.deliver_notifications();
+    }
+}
+
+// The resources involved in a client-initiated drag'n'drop
+struct ActiveDnD {
+    source: Option<wl_data_source::WlDataSource>,
+    origin: wl_surface::WlSurface,
+    icon: Option<wl_surface::WlSurface>,
+    // location of the icon relative to the pointer, moved by the compositor
+    icon_hotspot: (i32, i32),
+    // the last location of the pointer or touch point, updated by the grab as the device does
+    // not see the motions
+    location: (f64, f64),
+    // whether the drag'n'drop follows a touch point rather than the pointer
+    touch: bool,
 }
 
 struct SeatData {
-    known_devices: Vec<wl_data_device::WlDataDevice>,
+    known_devices: KnownDevices,
     selection: Selection,
     log: ::slog::Logger,
     current_focus: Option<Client>,
+    current_dnd: Option<ActiveDnD>,
+    dnd_audit: Option<Rc<RefCell<dyn FnMut(DndAuditRecord)>>>,
+    action_chooser: Option<Rc<RefCell<dyn FnMut(&DndActionContext<'_>) -> DndAction + 'static>>>,
+    ask_action: Option<Rc<RefCell<dyn FnMut(AskActionResolver)>>>,
+    paste_confirmation: PasteConfirmation,
+    selection_cache: Option<SelectionCache>,
+    history: SelectionHistory,
+    server_dnd: Option<Rc<Cell<bool>>>,
+    selection_tag: Option<u64>,
+    // the notifications of the compositor and listeners, delivered once this state is released
+    pending_notifications: Vec<Notification>,
+    selection_change_callback: Option<Rc<RefCell<dyn FnMut(SelectionChanged)>>>,
+    selection_listeners: Vec<Rc<dyn Fn(&SelectionSnapshot)>>,
+    // incremented each time the selection changes, to know which data devices already
+    // received the current one
+    selection_serial: u64,
+    seat_name: String,
+    // the seat owning this state, registered in the data of its client selections
+    seat: Weak<SeatRc>,
+    log_selection_changes: bool,
+    // whether the selection is hidden from every client, shared with the selection offers
+    focus_inhibited: Rc<Cell<bool>>,
+    // whether the data device focus follows the keyboard focus, `None` until the compositor
+    // or the configuration decides
+    follow_keyboard_focus: Option<bool>,
+    // whether the keyboard focus listener of this seat is registered
+    keyboard_focus_listener: bool,
 }
 
 impl SeatData {
     fn set_selection(&mut self, new_selection: Selection) {
+        self.set_tagged_selection(new_selection, None);
+    }
+
+    fn set_tagged_selection(&mut self, mut new_selection: Selection, tag: Option<u64>) {
+        if let Selection::Compositor { ref mut metadata, .. } = new_selection {
+            normalize_mime_types(&mut metadata.mime_types);
+            if metadata.mime_types.is_empty() {
+                // such an offer would advertise nothing to the clients
+                warn!(self.log, "Ignoring a compositor selection without any mime type");
+                return;
+            }
+            clamp_selection_actions(metadata, &self.log);
+        }
+        let cleared = match (&self.selection, &new_selection) {
+            (&Selection::Empty, _) => false,
+            (_, &Selection::Empty) => true,
+            _ => false,
+        };
+        let superseded = match (&self.selection, &new_selection) {
+            (&Selection::Client(ref previous), &Selection::Client(ref source))
+                if !previous.as_ref().equals(source.as_ref()) =>
+            {
+                Some((previous.clone(), source.clone()))
+            }
+            _ => None,
+        };
+        if let Selection::Client(ref source) = new_selection {
+            // so that the selection is re-advertised when the compositor modifies the source
+            if let Some(data) = source.as_ref().user_data().get::<DataSourceData>() {
+                let mut seats = data.seats.borrow_mut();
+                seats.retain(|seat| seat.upgrade().is_some());
+                if !seats.iter().any(|seat| seat.ptr_eq(&self.seat)) {
+                    seats.push(self.seat.clone());
+                }
+            }
+        }
         self.selection = new_selection;
+        self.selection_tag = tag;
+        self.selection_cache = None;
+        match self.selection {
+            Selection::Client(ref source) => self.history.record_client(source, &self.log),
+            Selection::Compositor {
+                ref metadata,
+                ref content,
+            } => self.history.record_compositor(metadata, content, &self.log),
+            Selection::Empty => {}
+        }
+        self.selection_changed();
+        if cleared {
+            self.selection_cleared();
+        }
+        if let Some((previous, source)) = superseded {
+            self.selection_superseded(previous, source);
+        }
         self.send_selection();
     }
 
+    // tell the client that lost the selection to an other one, and notify the compositor
+    fn selection_superseded(
+        &mut self,
+        previous: wl_data_source::WlDataSource,
+        source: wl_data_source::WlDataSource,
+    ) {
+        if !previous.as_ref().is_alive() {
+            // the client destroyed its source, there is nobody to notify
+            return;
+        }
+        previous.cancelled();
+        if let Some(callback) = self.device_callback() {
+            self.pending_notifications.push(Notification::Event(
+                callback,
+                DataDeviceEvent::SelectionSuperseded { previous, source },
+            ));
+        }
+    }
+
+    // notify the compositor that the selection is now empty
+    fn selection_cleared(&mut self) {
+        if let Some(callback) = self.device_callback() {
+            self.pending_notifications
+                .push(Notification::Event(callback, DataDeviceEvent::SelectionCleared));
+        }
+    }
+
+    // the callback provided by the compositor, through the data devices of this seat
+    fn device_callback(&self) -> Option<Rc<RefCell<dyn FnMut(DataDeviceEvent)>>> {
+        self.known_devices
+            .iter()
+            .find(|dd| dd.as_ref().is_alive())
+            .and_then(|dd| dd.as_ref().user_data().get::<DataDeviceData>())
+            .map(|data| data.callback.clone())
+    }
+
+    fn selection_changed(&mut self) {
+        self.selection_serial += 1;
+        self.log_selection_change();
+        if let Some(callback) = self.selection_change_callback.clone() {
+            let selection = self.snapshot();
+            self.pending_notifications.push(Notification::Changed(
+                callback,
+                SelectionChanged {
+                    selection,
+                    tag: self.selection_tag,
+                },
+            ));
+        }
+        if !self.selection_listeners.is_empty() {
+            let selection = self.snapshot();
+            for listener in self.selection_listeners.clone() {
+                self.pending_notifications
+                    .push(Notification::Listener(listener, selection.clone()));
+            }
+        }
+    }
+
+    fn snapshot(&mut self) -> SelectionSnapshot {
+        let mime_types = self.mime_types();
+        match self.selection {
+            Selection::Empty => SelectionSnapshot::empty(),
+            Selection::Client(ref source) => SelectionSnapshot::client(source.clone(), mime_types),
+            Selection::Compositor { ref content, .. } => {
+                SelectionSnapshot::compositor(content.clone(), mime_types)
+            }
+        }
+    }
+
+    // emit a structured log event describing the new selection, if enabled
+    fn log_selection_change(&self) {
+        if !self.log_selection_changes {
+            return;
+        }
+        let (origin, mime_types, content_hash) = match self.selection {
+            Selection::Empty => ("empty", 0, None),
+            Selection::Client(ref data_source) => (
+                "client",
+                with_source_metadata(data_source, |meta| meta.mime_types.len()).unwrap_or(0),
+                None,
+            ),
+            Selection::Compositor {
+                ref metadata,
+                ref content,
+            } => ("compositor", metadata.mime_types.len(), content.content_hash()),
+        };
+        info!(self.log, "Selection changed";
+            "seat" => &self.seat_name,
+            "origin" => origin,
+            "mime_types" => mime_types,
+            "content_hash" => content_hash.map(|hash| format!("{:016x}", hash))
+        );
+    }
+
+    fn mime_types(&mut self) -> Vec<String> {
+        self.sanitize_selection();
+        match self.selection {
+            Selection::Empty => Vec::new(),
+            Selection::Client(ref data_source) => {
+                with_source_metadata(data_source, |meta| meta.mime_types.clone()).unwrap_or_default()
+            }
+            Selection::Compositor { ref metadata, .. } => metadata.mime_types.clone(),
+        }
+    }
+
+    fn has_mime_type(&mut self, mime_type: &str) -> bool {
+        self.sanitize_selection();
+        match self.selection {
+            Selection::Empty => false,
+            Selection::Client(ref data_source) => {
+                with_source_metadata(data_source, |meta| meta.mime_types.iter().any(|m| m == mime_type))
+                    .unwrap_or(false)
+            }
+            Selection::Compositor { ref metadata, .. } => metadata.mime_types.iter().any(|m| m == mime_type),
+        }
+    }
+
+    // reset the selection to null if the client holding it dropped it, or to its cached
+    // copy if there is one
+    fn sanitize_selection(&mut self) {
+        let cleanup = match self.selection {
+            Selection::Client(ref data_source) => !data_source.as_ref().is_alive(),
+            Selection::Compositor { ref content, .. } => content.is_expired(),
+            Selection::Empty => false,
+        };
+        if cleanup {
+            self.selection = self
+                .selection_cache
+                .take()
+                .and_then(SelectionCache::into_selection)
+                .map(|(mut metadata, content)| {
+                    clamp_selection_actions(&mut metadata, &self.log);
+                    Selection::Compositor {
+                        metadata,
+                        content: Rc::new(content),
+                    }
+                })
+                .unwrap_or(Selection::Empty);
+            self.selection_tag = None;
+            self.selection_changed();
+            if let Selection::Empty = self.selection {
+                self.selection_cleared();
+            }
+        }
+    }
+
+    // write the current selection into an fd on behalf of an other protocol, the fd is
+    // given back if the compositor callback must provide the contents
+    fn serve_selection(&mut self, mime_type: &str, fd: RawFd) -> Option<RawFd> {
+        self.sanitize_selection();
+        if let Selection::Compositor {
+            ref metadata,
+            ref content,
+        } = self.selection
+        {
+            if matches!(**content, SelectionContent::Callback)
+                && metadata.mime_types.iter().any(|m| m == mime_type)
+            {
+                return Some(fd);
+            }
+        }
+        self.request_selection(mime_type, fd);
+        None
+    }
+
+    // write the current selection into an fd on behalf of the compositor
+    fn request_selection(&mut self, mime_type: &str, fd: RawFd) -> bool {
+        self.sanitize_selection();
+        match self.selection {
+            Selection::Client(ref source) => {
+                if let Some(mime_type) = source_mime_type(source, mime_type, false) {
+                    source.send(mime_type, fd);
+                    close_fd(fd);
+                    return true;
+                }
+            }
+            Selection::Compositor {
+                ref metadata,
+                ref content,
+            } => {
+                // the compositor provides the contents of callback selections by itself
+                let served = matching_mime_type(&metadata.mime_types, mime_type, false);
+                if let (Some(mime_type), false) = (served, matches!(**content, SelectionContent::Callback)) {
+                    // remote selections call into the compositor to serve their contents
+                    self.pending_notifications.push(Notification::Serve(
+                        content.clone(),
+                        mime_type,
+                        fd,
+                        self.log.clone(),
+                    ));
+                    return true;
+                }
+            }
+            Selection::Empty => {}
+        }
+        close_fd(fd);
+        false
+    }
+
+    // the mime types of a data source changed, advertise them again if it is the selection
+    fn source_updated(&mut self, source: &wl_data_source::WlDataSource) {
+        let is_selection = match self.selection {
+            Selection::Client(ref data_source) => data_source.as_ref().equals(source.as_ref()),
+            _ => false,
+        };
+        if is_selection {
+            // the offers sent so far advertise outdated mime types
+            self.selection_serial += 1;
+            self.send_selection();
+        }
+    }
+
     fn set_focus(&mut self, new_focus: Option<Client>) {
         self.current_focus = new_focus;
         self.send_selection();
     }
 
+    fn set_focus_inhibited(&mut self, inhibited: bool) {
+        if self.focus_inhibited.get() != inhibited {
+            self.focus_inhibited.set(inhibited);
+            // the offers sent so far do not match what is advertised anymore
+            self.selection_serial += 1;
+            self.send_selection();
+        }
+    }
+
     fn send_selection(&mut self) {
+        // first sanitize the selection
+        self.sanitize_selection();
+        // drop the data devices of clients that disconnected without releasing them, only
+        // the dead ones are removed so the live devices of the focused client are kept
+        self.known_devices.retain(|dd| dd.as_ref().is_alive());
         let client = match self.current_focus.as_ref() {
             Some(c) => c,
             None => return,
         };
-        // first sanitize the selection, reseting it to null if the client holding
-        // it dropped it
-        let cleanup = if let Selection::Client(ref data_source) = self.selection {
-            !data_source.as_ref().is_alive()
-        } else {
-            false
+        // then send it to the data devices of the focused client
+        for dd in self.known_devices.of_client(client) {
+            // skip dead data devices, as well as the ones that still have an offer for the
+            // current selection from a previous focus
+            if dd.as_ref().is_alive() && !self.has_current_selection(dd) {
+                self.send_selection_to(dd, client);
+            }
+        }
+    }
+
+    // whether this data device received the current selection, and can still use it
+    fn has_current_selection(&self, dd: &wl_data_device::WlDataDevice) -> bool {
+        let dd_data = match dd.as_ref().user_data().get::<DataDeviceData>() {
+            Some(dd_data) => dd_data,
+            None => return false,
         };
-        if cleanup {
-            self.selection = Selection::Empty;
+        match *dd_data.sent_selection.borrow() {
+            Some((serial, ref offer)) => {
+                serial == self.selection_serial
+                    && offer
+                        .as_ref()
+                        .map(|offer| offer.as_ref().is_alive())
+                        .unwrap_or(true)
+            }
+            None => false,
+        }
+    }
+
+    // remember that this data device received the current selection, through this offer
+    fn selection_sent(
+        &self,
+        dd: &wl_data_device::WlDataDevice,
+        offer: Option<&Main<wl_data_offer::WlDataOffer>>,
+    ) {
+        if let Some(dd_data) = dd.as_ref().user_data().get::<DataDeviceData>() {
+            *dd_data.sent_selection.borrow_mut() =
+                Some((self.selection_serial, offer.map(|offer| (**offer).clone())));
+            if let Some(offer) = offer {
+                dd_data.selection_offers.borrow_mut().push((**offer).clone());
+                // the offer is forgotten as soon as its client destroys it
+                let device = dd.clone();
+                offer.assign_destructor(Filter::new(move |offer: wl_data_offer::WlDataOffer, _, _| {
+                    selection_offer_destroyed(&device, &offer)
+                }));
+            }
+        }
+    }
+
+    // the number of selection offers this client did not destroy yet
+    fn live_selection_offers(&self, client: &Client) -> usize {
+        self.known_devices
+            .of_client(client)
+            .iter()
+            .filter_map(|dd| dd.as_ref().user_data().get::<DataDeviceData>())
+            .map(|dd_data| {
+                let mut offers = dd_data.selection_offers.borrow_mut();
+                offers.retain(|offer| offer.as_ref().is_alive());
+                offers.len()
+            })
+            .sum()
+    }
+
+    // a data device created by the focused client must receive the current selection
+    // right away, as it will not be notified until the next focus change otherwise
+    fn device_created(&mut self, dd: &wl_data_device::WlDataDevice) {
+        self.sanitize_selection();
+        if let Some(client) = self.current_focus.as_ref() {
+            if is_device_of(dd, client) {
+                self.send_selection_to(dd, client);
+            }
+        }
+    }
+
+    fn send_selection_to(&self, dd: &wl_data_device::WlDataDevice, client: &Client) {
+        let dd_data = match dd.as_ref().user_data().get::<DataDeviceData>() {
+            Some(dd_data) => dd_data,
+            None => {
+                // the data device is not initialized, which only happens while its client
+                // is being torn down
+                debug!(
+                    self.log,
+                    "Not sending the selection to an uninitialized data device."
+                );
+                return;
+            }
+        };
+        if self.focus_inhibited.get() {
+            // the real selection is advertised again once the inhibition is lifted
+            dd.selection(None);
+            self.selection_sent(dd, None);
+            return;
+        }
+        let creates_offer = match self.selection {
+            Selection::Empty => false,
+            _ => true,
+        };
+        if creates_offer && self.live_selection_offers(client) >= dd_data.config.max_selection_offers {
+            // it is sent the selection again on the next change or focus, if it destroyed
+            // its previous offers in the meantime
+            debug!(
+                self.log,
+                "Not sending the selection to a client holding too many data offers."
+            );
+            return;
         }
-        // then send it if appropriate
         match self.selection {
             Selection::Empty => {
                 // send an empty selection
-                for dd in &self.known_devices {
-                    // skip data devices not belonging to our client
-                    if dd.as_ref().client().map(|c| !c.equals(client)).unwrap_or(true) {
-                        continue;
-                    }
-                    dd.selection(None);
-                }
+                dd.selection(None);
+                self.selection_sent(dd, None);
             }
             Selection::Client(ref data_source) => {
-                for dd in &self.known_devices {
-                    // skip data devices not belonging to our client
-                    if dd.as_ref().client().map(|c| !c.equals(client)).unwrap_or(true) {
-                        continue;
+                let source = data_source.clone();
+                let paste_confirmation = self.paste_confirmation.clone();
+                let inhibited = self.focus_inhibited.clone();
+                let loose_text_matching = dd_data.config.loose_text_matching;
+                let mime_types = match with_source_metadata(data_source, |meta| {
+                    ordered_mime_types(&meta.mime_types, &dd_data.config.preferred_mime_types)
+                }) {
+                    Ok(mime_types) => mime_types,
+                    Err(()) => {
+                        debug!(
+                            self.log,
+                            "Not sending a selection whose source is not managed by smithay."
+                        );
+                        return;
                     }
-                    let source = data_source.clone();
-                    let log = self.log.clone();
-                    // create a corresponding data offer
-                    let offer = client
-                        .create_resource::<wl_data_offer::WlDataOffer>(dd.as_ref().version())
-                        .unwrap();
-                    offer.quick_assign(move |_offer, req, _| {
-                        // selection data offers only care about the `receive` event
-                        if let wl_data_offer::Request::Receive { fd, mime_type } = req {
-                            // check if the source and associated mime type is still valid
-                            let valid =
-                                with_source_metadata(&source, |meta| meta.mime_types.contains(&mime_type))
-                                    .unwrap_or(false)
-                                    && source.as_ref().is_alive();
-                            if !valid {
-                                // deny the receive
-                                debug!(log, "Denying a wl_data_offer.receive with invalid source.");
-                            } else {
-                                source.send(mime_type, fd);
-                            }
-                            let _ = ::nix::unistd::close(fd);
+                };
+                // create a corresponding data offer
+                let offer = match client.create_resource::<wl_data_offer::WlDataOffer>(dd.as_ref().version())
+                {
+                    Some(offer) => offer,
+                    None => {
+                        // the client is disconnecting, nobody is left to send the selection to
+                        debug!(self.log, "Not sending the selection to a disconnected client.");
+                        return;
+                    }
+                };
+                let log = self.log.clone();
+                offer.quick_assign(move |offer, req, _| {
+                    // selection data offers only care about the `receive` event, in particular
+                    // `accept` is not forwarded as `wl_data_source.target` is only sent during
+                    // a drag'n'drop
+                    if let wl_data_offer::Request::Receive { fd, mime_type } = req {
+                        if inhibited.get() {
+                            debug!(
+                                log,
+                                "Denying a wl_data_offer.receive while the selection is inhibited."
+                            );
+                            close_fd(fd);
+                            return;
                         }
-                    });
-                    // advertize the offer to the client
-                    dd.data_offer(&offer);
-                    with_source_metadata(data_source, |meta| {
-                        for mime_type in meta.mime_types.iter().cloned() {
-                            offer.offer(mime_type);
+                        // check if the source and associated mime type is still valid
+                        let served = source_mime_type(&source, &mime_type, loose_text_matching);
+                        if let Some(mime_type) = served {
+                            let source = source.clone();
+                            confirm_paste(
+                                &paste_confirmation,
+                                offer.as_ref().client(),
+                                mime_type,
+                                fd,
+                                move |mime_type, fd| {
+                                    // the source may have been destroyed while the paste was pending
+                                    if source.as_ref().is_alive() {
+                                        source.send(mime_type, fd);
+                                    }
+                                    close_fd(fd);
+                                },
+                            );
+                        } else {
+                            // deny the receive
+                            debug!(log, "Denying a wl_data_offer.receive with invalid source.");
+                            close_fd(fd);
                         }
-                    })
-                    .unwrap();
-                    dd.selection(Some(&offer));
+                    }
+                });
+                // advertize the offer to the client
+                dd.data_offer(&offer);
+                if !offer.as_ref().is_alive() {
+                    // the client disconnected in the meantime
+                    return;
                 }
+                for mime_type in mime_types {
+                    offer.offer(mime_type);
+                }
+                dd.selection(Some(&offer));
+                self.selection_sent(dd, Some(&offer));
             }
-            Selection::Compositor(ref meta) => {
-                for dd in &self.known_devices {
-                    // skip data devices not belonging to our client
-                    if dd.as_ref().client().map(|c| !c.equals(client)).unwrap_or(true) {
-                        continue;
+            Selection::Compositor {
+                metadata: ref meta,
+                ref content,
+            } => {
+                // the offer of a selection must not advertise any drag'n'drop action
+                debug_assert!(meta.dnd_action.is_empty());
+                let offer_meta = meta.clone();
+                let offer_content = content.clone();
+                let paste_confirmation = self.paste_confirmation.clone();
+                let inhibited = self.focus_inhibited.clone();
+                let callback = dd_data.callback.clone();
+                let preferred_mime_types = &dd_data.config.preferred_mime_types;
+                let loose_text_matching = dd_data.config.loose_text_matching;
+                let size_limit = dd_data.config.selection_size_limit;
+                // create a corresponding data offer
+                let offer = match client.create_resource::<wl_data_offer::WlDataOffer>(dd.as_ref().version())
+                {
+                    Some(offer) => offer,
+                    None => {
+                        // the client is disconnecting, nobody is left to send the selection to
+                        debug!(self.log, "Not sending the selection to a disconnected client.");
+                        return;
                     }
-                    let log = self.log.clone();
-                    let offer_meta = meta.clone();
-                    let callback = dd
-                        .as_ref()
-                        .user_data()
-                        .get::<DataDeviceData>()
-                        .unwrap()
-                        .callback
-                        .clone();
-                    // create a corresponding data offer
-                    let offer = client
-                        .create_resource::<wl_data_offer::WlDataOffer>(dd.as_ref().version())
-                        .unwrap();
-                    offer.quick_assign(move |_offer, req, _| {
-                        // selection data offers only care about the `receive` event
-                        if let wl_data_offer::Request::Receive { fd, mime_type } = req {
-                            // check if the associated mime type is valid
-                            if !offer_meta.mime_types.contains(&mime_type) {
-                                // deny the receive
-                                debug!(log, "Denying a wl_data_offer.receive with invalid source.");
-                                let _ = ::nix::unistd::close(fd);
-                            } else {
-                                (&mut *callback.borrow_mut())(DataDeviceEvent::SendSelection {
-                                    mime_type,
-                                    fd,
-                                });
-                            }
+                };
+                let log = self.log.clone();
+                offer.quick_assign(move |offer, req, _| {
+                    // selection data offers only care about the `receive` event
+                    if let wl_data_offer::Request::Receive { fd, mime_type } = req {
+                        if inhibited.get() {
+                            debug!(
+                                log,
+                                "Denying a wl_data_offer.receive while the selection is inhibited."
+                            );
+                            close_fd(fd);
+                            return;
+                        }
+                        // check if the associated mime type is valid
+                        let served =
+                            matching_mime_type(&offer_meta.mime_types, &mime_type, loose_text_matching);
+                        if let Some(mime_type) = served {
+                            let callback = callback.clone();
+                            let content = offer_content.clone();
+                            let log = log.clone();
+                            let client = offer.as_ref().client();
+                            confirm_paste(
+                                &paste_confirmation,
+                                client.clone(),
+                                mime_type,
+                                fd,
+                                move |mime_type, fd| match (&*content, client) {
+                                    (&SelectionContent::Callback, _) => {
+                                        (&mut *callback.borrow_mut())(DataDeviceEvent::SendSelection {
+                                            mime_type,
+                                            fd,
+                                        });
+                                    }
+                                    // the transfers are bounded for each client
+                                    (content, Some(client)) => {
+                                        content.serve_to(&client, &mime_type, fd, size_limit, &log)
+                                    }
+                                    // nobody is left to read the contents
+                                    (_, None) => close_fd(fd),
+                                },
+                            );
+                        } else {
+                            // deny the receive
+                            debug!(log, "Denying a wl_data_offer.receive with invalid source.");
+                            close_fd(fd);
                         }
-                    });
-                    // advertize the offer to the client
-                    dd.data_offer(&offer);
-                    for mime_type in meta.mime_types.iter().cloned() {
-                        offer.offer(mime_type);
                     }
-                    dd.selection(Some(&offer));
+                });
+                // advertize the offer to the client
+                dd.data_offer(&offer);
+                if !offer.as_ref().is_alive() {
+                    // the client disconnected in the meantime
+                    return;
+                }
+                for mime_type in ordered_mime_types(&meta.mime_types, preferred_mime_types) {
+                    offer.offer(mime_type);
                 }
+                dd.selection(Some(&offer));
+                self.selection_sent(dd, Some(&offer));
             }
         }
     }
 }
 
 impl SeatData {
-    fn new(log: ::slog::Logger) -> SeatData {
+    fn new(log: ::slog::Logger, seat: &Seat) -> SeatData {
         SeatData {
-            known_devices: Vec::new(),
+            known_devices: KnownDevices::default(),
             selection: Selection::Empty,
             log,
             current_focus: None,
+            current_dnd: None,
+            dnd_audit: None,
+            action_chooser: None,
+            ask_action: None,
+            paste_confirmation: Rc::new(RefCell::new(None)),
+            selection_cache: None,
+            history: SelectionHistory::default(),
+            server_dnd: None,
+            selection_tag: None,
+            pending_notifications: Vec::new(),
+            selection_change_callback: None,
+            selection_listeners: Vec::new(),
+            selection_serial: 0,
+            seat_name: seat.arc.name.clone(),
+            seat: Rc::downgrade(&seat.arc),
+            log_selection_changes: false,
+            focus_inhibited: Rc::new(Cell::new(false)),
+            follow_keyboard_focus: None,
+            keyboard_focus_listener: false,
         }
     }
 }
 
-/// Initialize the data device global
-///
-/// You can provide a callback to peek into the actions of your clients over the data devices
-/// (allowing you to retrieve the current selection buffer, or intercept DnD data). See the
-/// [`DataDeviceEvent`] type for details about what notifications you can receive. Note that this
-/// closure will not receive notifications about dnd actions the compositor initiated, see
-/// [`start_dnd`] for details about that.
+/// Configuration of the data device logic
 ///
-/// You also need to provide a `(DndAction, DndAction) -> DndAction` closure that will arbitrate
-/// the choice of action resulting from a drag'n'drop session. Its first argument is the set of
-/// available actions (which is the intersection of the actions supported by the source and targets)
-/// and the second argument is the preferred action reported by the target. If no action should be
-/// chosen (and thus the drag'n'drop should abort on drop), return
-/// [`DndAction::empty()`](wayland_server::protocol::wl_data_device_manager::DndAction::empty).
-pub fn init_data_device<F, C, R, L>(
-    display: &mut Display,
-    callback: C,
-    action_choice: F,
-    token: CompositorToken<R>,
-    logger: L,
-) -> Global<wl_data_device_manager::WlDataDeviceManager>
-where
-    F: FnMut(DndAction, DndAction) -> DndAction + 'static,
-    C: FnMut(DataDeviceEvent) + 'static,
-    R: Role<DnDIconRole> + 'static,
-    L: Into<Option<::slog::Logger>>,
-{
-    let log = crate::slog_or_fallback(logger).new(o!("smithay_module" => "data_device_mgr"));
-    let action_choice = Rc::new(RefCell::new(action_choice));
-    let callback = Rc::new(RefCell::new(callback));
-    display.create_global(
-        3,
-        Filter::new(move |(ddm, _version), _, _| {
-            implement_ddm(ddm, callback.clone(), action_choice.clone(), token, log.clone());
-        }),
-    )
-}
-
-/// Set the data device focus to a certain client for a given seat
-pub fn set_data_device_focus(seat: &Seat, client: Option<Client>) {
-    // ensure the seat user_data is ready
-    // TODO: find a better way to retrieve a logger without requiring the user
-    // to provide one ?
-    // This should be a rare path anyway, it is unlikely that a client gets focus
-    // before initializing its data device, which would already init the user_data.
+/// Its [`Default`] implementation provides sensible values for all the fields.
+#[derive(Debug, Clone)]
+pub struct DataDeviceConfig {
+    /// Maximum number of mime types a client data source can offer
+    ///
+    /// This bounds the number of `offer` events smithay sends every time the selection
+    /// or a drag'n'drop is advertised to a client. Additional mime types are ignored.
+    ///
+    /// Defaults to 256.
+    pub max_mime_types: usize,
+    /// Mime types to advertise first when sending the selection to clients
+    ///
+    /// By default, the mime types of a selection are advertised in exactly the order
+    /// they were provided, by the client or by your compositor. As some clients pick the
+    /// first mime type they support, you can use this list to put the formats you prefer
+    /// first, in order of preference. The other mime types follow in their original order.
+    ///
+    /// Defaults to an empty list.
+    pub preferred_mime_types: Vec<String>,
+    /// Whether to validate the serial provided by clients setting the selection
+    ///
+    /// If enabled, a client can only set the selection using the serial of one of the last key
+    /// or button events sent to it while it had the keyboard or pointer focus.
+    /// Requests with a stale or bogus serial are denied and their source is cancelled,
+    /// preventing clients from changing the selection without user interaction.
+    ///
+    /// Defaults to `false`, accepting any serial.
+    pub strict_selection_serial: bool,
+    /// Whether to consider the variants of `text/plain` compatible when pasting the selection
+    ///
+    /// Some clients offer `text/plain;charset=utf-8` while others request `text/plain`, or the
+    /// other way around. If enabled, a request for `text/plain` or one of its charset-qualified
+    /// variants is accepted when the selection offers any of them, and the data of the offered
+    /// variant is served. An exact match is always preferred.
+    ///
+    /// Defaults to `false`, only accepting the exact mime types of the selection.
+    pub loose_text_matching: bool,
+    /// How long to keep serving the selection of a client after it is gone
+    ///
+    /// If set, smithay reads the contents of every client selection as soon as it is set,
+    /// and keeps a copy of them. If the client holding the selection disappears, for example
+    /// because the user closed the application right after copying something, the copy is
+    /// served as a compositor selection instead for this duration, after which the selection
+    /// is cleared.
+    ///
+    /// This is best-effort: the contents of a mime type are only available once the client
+    /// finished writing them, so mime types the client did not send before disappearing, as
+    /// well as contents larger than 1 MiB, are not available anymore. Note also that this
+    /// requires all the contents to be transferred from the client for each selection, even
+    /// if nobody pastes it.
+    ///
+    /// Defaults to `None`, clearing the selection as soon as its client is gone.
+    pub selection_grace_period: Option<Duration>,
+    /// Whether to log every change of the selection as a structured event
+    ///
+    /// If enabled, an `info` event is logged each time the selection of a seat changes, with
+    /// the following keys: `seat` (the name of the seat), `origin` (`empty`, `client` or
+    /// `compositor`), `mime_types` (the number of offered mime types) and `content_hash` (a
+    /// hash of the contents for compositor selections made of bytes, allowing to detect
+    /// identical copies without logging the contents themselves).
+    ///
+    /// Defaults to `false`.
+    pub log_selection_changes: bool,
+    /// A limit on the size of the compositor selection contents served to each client request
+    ///
+    /// This applies to the contents smithay serves by itself, set with [`copy_bytes`],
+    /// [`set_data_device_selection_file`], [`set_data_device_selection_reader`] or kept
+    /// by [`selection_grace_period`](DataDeviceConfig::selection_grace_period). See
+    /// [`SelectionSizeLimit`] for details.
+    ///
+    /// Defaults to `None`, serving the contents whatever their size.
+    pub selection_size_limit: Option<SelectionSizeLimit>,
+    /// Maximum number of selection offers a client can keep alive
+    ///
+    /// A client is sent a new data offer each time the selection changes or it regains the
+    /// focus, and is expected to destroy the previous ones. Once it holds this many offers it
+    /// is not sent new ones until it destroys some, so that a misbehaving client cannot make
+    /// your compositor allocate them without bound.
+    ///
+    /// Defaults to 64.
+    pub max_selection_offers: usize,
+    /// Number of past selections to keep for each seat
+    ///
+    /// If not zero, a copy of the contents of every selection is kept, allowing you to
+    /// reinstall a past selection with [`restore_selection`], for example to implement a
+    /// clipboard manager. The contents of client selections are read as soon as they are set,
+    /// in the background, and the oldest selections are forgotten first. As with the
+    /// [`selection_grace_period`](DataDeviceConfig::selection_grace_period), contents larger
+    /// than 1 MiB are not kept, and selections set with [`set_data_device_selection`] cannot
+    /// be recorded as your compositor provides their contents.
+    ///
+    /// Defaults to 0, keeping no history.
+    pub selection_history: usize,
+    /// Whether the data device focus follows the keyboard focus
+    ///
+    /// If enabled, the data device focus of each seat is set to the client of the surface
+    /// having its keyboard focus every time it changes, so that you do not need to call
+    /// [`set_data_device_focus`] yourself. This can be changed for each seat with
+    /// [`set_data_device_follows_keyboard_focus`], for example to hide the selection from every
+    /// client while the screen is locked.
+    ///
+    /// Defaults to `false`, the focus being driven by your compositor.
+    pub follow_keyboard_focus: bool,
+}
+
+impl DataDeviceConfig {
+    /// Keep this number of past selections, see
+    /// [`selection_history`](DataDeviceConfig::selection_history)
+    pub fn with_history(mut self, capacity: usize) -> DataDeviceConfig {
+        self.selection_history = capacity;
+        self
+    }
+}
+
+impl Default for DataDeviceConfig {
+    fn default() -> DataDeviceConfig {
+        DataDeviceConfig {
+            max_mime_types: 256,
+            preferred_mime_types: Vec::new(),
+            strict_selection_serial: false,
+            loose_text_matching: false,
+            selection_grace_period: None,
+            log_selection_changes: false,
+            selection_size_limit: None,
+            max_selection_offers: 64,
+            selection_history: 0,
+            follow_keyboard_focus: false,
+        }
+    }
+}
+
+// Close an fd provided by a client
+//
+// A `close` interrupted by a signal must not be retried: on Linux the fd is released
+// anyway, and by the time we would retry it may already have been reused by an other
+// thread. `EINTR` is thus ignored like any other error, there is nothing more we can do
+// with this fd. Writes on the other hand are retried, which `Write::write_all` and
+// `io::copy` do on `ErrorKind::Interrupted`.
+fn close_fd(fd: RawFd) {
+    let _ = ::nix::unistd::close(fd);
+}
+
+// Forget a selection offer destroyed by its client, so that the data device it was sent to no
+// longer refers to it, and is sent the selection again on its next focus
+fn selection_offer_destroyed(dd: &wl_data_device::WlDataDevice, offer: &wl_data_offer::WlDataOffer) {
+    let dd_data = match dd.as_ref().user_data().get::<DataDeviceData>() {
+        Some(dd_data) => dd_data,
+        None => return,
+    };
+    dd_data
+        .selection_offers
+        .borrow_mut()
+        .retain(|known| known.as_ref().is_alive() && !known.as_ref().equals(offer.as_ref()));
+    let mut sent_selection = dd_data.sent_selection.borrow_mut();
+    let destroyed = match *sent_selection {
+        Some((_, Some(ref sent))) => !sent.as_ref().is_alive() || sent.as_ref().equals(offer.as_ref()),
+        _ => false,
+    };
+    if destroyed {
+        *sent_selection = None;
+    }
+}
+
+// Whether this data device is alive and belongs to this client
+fn is_device_of(dd: &wl_data_device::WlDataDevice, client: &Client) -> bool {
+    dd.as_ref().is_alive() && dd.as_ref().client().map(|c| c.equals(client)).unwrap_or(false)
+}
+
+// Access the data device state of a seat, initializing it if needed
+fn seat_data(seat: &Seat) -> &SeatDataCell {
+    // TODO: find a better way to retrieve a logger without requiring the user
+    // to provide one ?
+    // This should be a rare path anyway, it is unlikely that a client gets focus
+    // before initializing its data device, which would already init the user_data.
     seat.user_data().insert_if_missing(|| {
-        RefCell::new(SeatData::new(
+        SeatDataCell(RefCell::new(SeatData::new(
             seat.arc.log.new(o!("smithay_module" => "data_device_mgr")),
-        ))
+            seat,
+        )))
     });
-    let seat_data = seat.user_data().get::<RefCell<SeatData>>().unwrap();
-    seat_data.borrow_mut().set_focus(client);
+    seat.user_data().get::<SeatDataCell>().unwrap()
 }
 
-/// Set a compositor-provided selection for this seat
+/// Initialize the data device global
 ///
-/// You need to provide the available mime types for this selection.
+/// You can provide a callback to peek into the actions of your clients over the data devices
+/// (allowing you to retrieve the current selection buffer, or intercept DnD data). See the
+/// [`DataDeviceEvent`] type for details about what notifications you can receive. Note that this
+/// closure will not receive notifications about dnd actions the compositor initiated, see
+/// [`start_dnd`] for details about that.
 ///
-/// Whenever a client requests to read the selection, your callback will
-/// receive a [`DataDeviceEvent::SendSelection`] event.
-pub fn set_data_device_selection(seat: &Seat, mime_types: Vec<String>) {
-    // TODO: same question as in set_data_device_focus
-    seat.user_data().insert_if_missing(|| {
-        RefCell::new(SeatData::new(
-            seat.arc.log.new(o!("smithay_module" => "data_device_mgr")),
-        ))
-    });
-    let seat_data = seat.user_data().get::<RefCell<SeatData>>().unwrap();
-    seat_data
-        .borrow_mut()
-        .set_selection(Selection::Compositor(SourceMetadata {
-            mime_types,
-            dnd_action: DndAction::empty(),
-        }));
+/// You also need to provide a `(DndAction, DndAction) -> DndAction` closure that will arbitrate
+/// the choice of action resulting from a drag'n'drop session. Its first argument is the set of
+/// available actions (which is the intersection of the actions supported by the source and targets)
+/// and the second argument is the preferred action reported by the target. If no action should be
+/// chosen (and thus the drag'n'drop should abort on drop), return
+/// [`DndAction::empty()`](wayland_server::protocol::wl_data_device_manager::DndAction::empty).
+///
+/// If you need to tear the data device functionality down later, initialize it with a
+/// [`DataDeviceBuilder`] instead, which returns a [`DataDeviceHandle`].
+pub fn init_data_device<F, C, R, L>(
+    display: &mut Display,
+    callback: C,
+    action_choice: F,
+    token: CompositorToken<R>,
+    logger: L,
+) -> Global<wl_data_device_manager::WlDataDeviceManager>
+where
+    F: FnMut(DndAction, DndAction) -> DndAction + 'static,
+    C: FnMut(DataDeviceEvent) + 'static,
+    R: Role<DnDIconRole> + 'static,
+    L: Into<Option<::slog::Logger>>,
+{
+    init_data_device_with_config(
+        display,
+        callback,
+        action_choice,
+        token,
+        DataDeviceConfig::default(),
+        logger,
+    )
 }
 
-/// Start a drag'n'drop from a ressource controlled by the compositor
+/// Initialize the data device global, using the [`default_action_chooser`]
 ///
-/// You'll receive events generated by the interaction of clients with your
-/// drag'n'drop in the provided callback. See [`ServerDndEvent`] for details about
-/// which events can be generated and what response is expected from you to them.
-pub fn start_dnd<C>(
-    seat: &Seat,
-    serial: Serial,
-    start_data: GrabStartData,
-    metadata: SourceMetadata,
+/// This is the same as [`init_data_device`], for the common case of not needing a custom
+/// choice of the drag'n'drop actions.
+pub fn init_data_device_with_defaults<C, R, L>(
+    display: &mut Display,
     callback: C,
-) where
-    C: FnMut(ServerDndEvent) + 'static,
+    token: CompositorToken<R>,
+    logger: L,
+) -> Global<wl_data_device_manager::WlDataDeviceManager>
+where
+    C: FnMut(DataDeviceEvent) + 'static,
+    R: Role<DnDIconRole> + 'static,
+    L: Into<Option<::slog::Logger>>,
 {
-    // TODO: same question as in set_data_device_focus
-    seat.user_data().insert_if_missing(|| {
-        RefCell::new(SeatData::new(
-            seat.arc.log.new(o!("smithay_module" => "data_device_mgr")),
-        ))
-    });
-    if let Some(pointer) = seat.get_pointer() {
-        pointer.set_grab(
-            server_dnd_grab::ServerDnDGrab::new(
-                start_data,
-                metadata,
-                seat.clone(),
-                Rc::new(RefCell::new(callback)),
-            ),
-            serial,
-        );
-        return;
-    }
+    init_data_device(display, callback, default_action_chooser, token, logger)
 }
 
-fn implement_ddm<F, C, R>(
-    ddm: Main<wl_data_device_manager::WlDataDeviceManager>,
-    callback: Rc<RefCell<C>>,
-    action_choice: Rc<RefCell<F>>,
+/// Initialize the data device global with a custom configuration
+///
+/// This is the same as [`init_data_device`], but allows you to adjust the behavior of
+/// the data device logic using a [`DataDeviceConfig`].
+pub fn init_data_device_with_config<F, C, R, L>(
+    display: &mut Display,
+    callback: C,
+    action_choice: F,
     token: CompositorToken<R>,
-    log: ::slog::Logger,
-) -> wl_data_device_manager::WlDataDeviceManager
+    config: DataDeviceConfig,
+    logger: L,
+) -> Global<wl_data_device_manager::WlDataDeviceManager>
 where
     F: FnMut(DndAction, DndAction) -> DndAction + 'static,
     C: FnMut(DataDeviceEvent) + 'static,
     R: Role<DnDIconRole> + 'static,
+    L: Into<Option<::slog::Logger>>,
 {
-    use self::wl_data_device_manager::Request;
-    ddm.quick_assign(move |_ddm, req, _data| match req {
-        Request::CreateDataSource { id } => {
-            self::data_source::implement_data_source(id);
-        }
-        Request::GetDataDevice { id, seat } => match Seat::from_resource(&seat) {
-            Some(seat) => {
-                // ensure the seat user_data is ready
-                seat.user_data()
-                    .insert_if_missing(|| RefCell::new(SeatData::new(log.clone())));
-                let seat_data = seat.user_data().get::<RefCell<SeatData>>().unwrap();
-                let data_device = implement_data_device(
-                    id,
-                    seat.clone(),
-                    callback.clone(),
-                    action_choice.clone(),
-                    token,
-                    log.clone(),
-                );
-                seat_data.borrow_mut().known_devices.push(data_device);
-            }
-            None => {
-                error!(log, "Unmanaged seat given to a data device.");
-            }
-        },
-        _ => unreachable!(),
-    });
-
-    ddm.deref().clone()
+    init_data_device_with_shared_callback(
+        display,
+        Rc::new(RefCell::new(callback)),
+        Rc::new(RefCell::new(action_choice)),
+        token,
+        config,
+        logger,
+    )
 }
 
-struct DataDeviceData {
-    callback: Rc<RefCell<dyn FnMut(DataDeviceEvent) + 'static>>,
-    action_choice: Rc<RefCell<dyn FnMut(DndAction, DndAction) -> DndAction + 'static>>,
+/// Initialize the data device global, sending its events through a calloop channel
+///
+/// This is the same as [`init_data_device`], but rather than being given to a callback, the
+/// [`DataDeviceEvent`]s are sent into the returned channel. Insert it into your event loop to
+/// handle them from there, outside of the processing of the client requests.
+///
+/// Events sent once the channel has been dropped are discarded, the file descriptors of the
+/// discarded [`DataDeviceEvent::SendSelection`] are closed.
+pub fn init_data_device_channel<F, R, L>(
+    display: &mut Display,
+    action_choice: F,
+    token: CompositorToken<R>,
+    logger: L,
+) -> (
+    Global<wl_data_device_manager::WlDataDeviceManager>,
+    Channel<DataDeviceEvent>,
+)
+where
+    F: FnMut(DndAction, DndAction) -> DndAction + 'static,
+    R: Role<DnDIconRole> + 'static,
+    L: Into<Option<::slog::Logger>>,
+{
+    let (sender, channel) = channel::channel();
+    let global = init_data_device(
+        display,
+        move |event| {
+            // nobody is left to write the selection into the fd of the client
+            if let Err(::std::sync::mpsc::SendError(DataDeviceEvent::SendSelection { fd, .. })) =
+                sender.send(event)
+            {
+                close_fd(fd);
+            }
+        },
+        action_choice,
+        token,
+        logger,
+    );
+    (global, channel)
 }
 
-fn implement_data_device<F, C, R>(
-    dd: Main<wl_data_device::WlDataDevice>,
-    seat: Seat,
+/// Initialize the data device global with callbacks shared with other globals
+///
+/// This is the same as [`init_data_device_with_config`], but the callbacks are provided
+/// already wrapped, so that you can keep a handle to them and provide them to several data
+/// device globals. For example a compositor handling several displays can that way handle the
+/// clipboard of all of them with a single state.
+///
+/// The callbacks are shared using `Rc<RefCell<_>>`: all the displays using them must be
+/// dispatched from the same thread, and the callbacks will be mutably borrowed whenever smithay
+/// needs to invoke them. Thus you must not keep them borrowed while dispatching the displays,
+/// and they must not dispatch a display sharing them themselves, otherwise smithay will panic.
+pub fn init_data_device_with_shared_callback<F, C, R, L>(
+    display: &mut Display,
     callback: Rc<RefCell<C>>,
     action_choice: Rc<RefCell<F>>,
     token: CompositorToken<R>,
-    log: ::slog::Logger,
-) -> wl_data_device::WlDataDevice
+    config: DataDeviceConfig,
+    logger: L,
+) -> Global<wl_data_device_manager::WlDataDeviceManager>
 where
     F: FnMut(DndAction, DndAction) -> DndAction + 'static,
     C: FnMut(DataDeviceEvent) + 'static,
     R: Role<DnDIconRole> + 'static,
+    L: Into<Option<::slog::Logger>>,
 {
-    use self::wl_data_device::Request;
-    let dd_data = DataDeviceData {
-        callback: callback.clone(),
-        action_choice,
-    };
-    dd.quick_assign(move |dd, req, _| match req {
-        Request::StartDrag {
-            source,
-            origin,
-            icon,
-            serial,
-        } => {
-            /* TODO: handle the icon */
-            let serial = Serial::from(serial);
-            if let Some(pointer) = seat.get_pointer() {
-                if pointer.has_grab(serial) {
-                    if let Some(ref icon) = icon {
-                        if token.give_role::<DnDIconRole>(icon).is_err() {
-                            dd.as_ref().post_error(
-                                wl_data_device::Error::Role as u32,
-                                "Given surface already has an other role".into(),
-                            );
-                            return;
-                        }
-                    }
-                    // The StartDrag is in response to a pointer implicit grab, all is good
-                    (&mut *callback.borrow_mut())(DataDeviceEvent::DnDStarted {
-                        source: source.clone(),
-                        icon: icon.clone(),
-                    });
-                    let start_data = pointer.grab_start_data().unwrap();
-                    pointer.set_grab(
-                        dnd_grab::DnDGrab::new(
-                            start_data,
-                            source,
-                            origin,
-                            seat.clone(),
-                            icon,
-                            token,
-                            callback.clone(),
-                        ),
-                        serial,
-                    );
-                    return;
-                }
-            }
-            debug!(log, "denying drag from client without implicit grab");
+    init_data_device_handle(display, callback, action_choice, token, config, logger).global
+}
+
+/// A handle to a data device global, allowing to tear it down
+///
+/// It is returned by [`DataDeviceBuilder::init`].
+pub struct DataDeviceHandle {
+    global: Global<wl_data_device_manager::WlDataDeviceManager>,
+    known_seats: Rc<RefCell<Vec<Seat>>>,
+    destroyed: Rc<Cell<bool>>,
+}
+
+impl DataDeviceHandle {
+    /// Access the global of the data device manager
+    pub fn global(&self) -> &Global<wl_data_device_manager::WlDataDeviceManager> {
+        &self.global
+    }
+
+    /// Remove the data device functionality
+    ///
+    /// The global is destroyed, and for all the seats the clients created data devices for, the
+    /// drag'n'drops are cancelled, the data source of a client holding the selection is
+    /// cancelled and the data devices are sent an empty selection, so that the clients drop the
+    /// offers they were sent. The data devices and data device managers the clients still hold
+    /// are then ignored.
+    pub fn destroy(self) {
+        self.destroyed.set(true);
+        self.global.destroy();
+        let seats = ::std::mem::replace(&mut *self.known_seats.borrow_mut(), Vec::new());
+        for seat in seats {
+            reset_seat_data(&seat);
         }
-        Request::SetSelection { source, .. } => {
-            if let Some(keyboard) = seat.get_keyboard() {
-                if dd
-                    .as_ref()
-                    .client()
-                    .as_ref()
-                    .map(|c| keyboard.has_focus(c))
-                    .unwrap_or(false)
-                {
-                    let seat_data = seat.user_data().get::<RefCell<SeatData>>().unwrap();
-                    (&mut *callback.borrow_mut())(DataDeviceEvent::NewSelection(source.clone()));
-                    // The client has kbd focus, it can set the selection
-                    seat_data
-                        .borrow_mut()
-                        .set_selection(source.map(Selection::Client).unwrap_or(Selection::Empty));
-                    return;
-                }
-            }
-            debug!(log, "denying setting selection by a non-focused client");
+    }
+}
+
+// Forget all the data device state of this seat
+fn reset_seat_data(seat: &Seat) {
+    cancel_dnd(seat);
+    let seat_data = match seat.user_data().get::<SeatDataCell>() {
+        Some(seat_data) => seat_data,
+        None => return,
+    };
+    let mut seat_data = seat_data.borrow_mut();
+    if let Selection::Client(ref source) = seat_data.selection {
+        if source.as_ref().is_alive() {
+            source.cancelled();
         }
-        Request::Release => {
-            // Clean up the known devices
-            seat.user_data()
-                .get::<RefCell<SeatData>>()
-                .unwrap()
-                .borrow_mut()
-                .known_devices
-                .retain(|ndd| ndd.as_ref().is_alive() && (!ndd.as_ref().equals(&dd.as_ref())))
+    }
+    for dd in seat_data.known_devices.iter() {
+        if dd.as_ref().is_alive() {
+            dd.selection(None);
         }
-        _ => unreachable!(),
-    });
-    dd.as_ref().user_data().set(|| dd_data);
+    }
+    let fresh = SeatData::new(seat_data.log.clone(), seat);
+    let previous = ::std::mem::replace(&mut *seat_data, fresh);
+    // the previous state is dropped once the seat data is released, as it may hold
+    // callbacks of your compositor
+    ::std::mem::drop(seat_data);
+    ::std::mem::drop(previous);
+}
 
-    dd.deref().clone()
+fn init_data_device_handle<F, C, R, L>(
+    display: &mut Display,
+    callback: Rc<RefCell<C>>,
+    action_choice: Rc<RefCell<F>>,
+    token: CompositorToken<R>,
+    config: DataDeviceConfig,
+    logger: L,
+) -> DataDeviceHandle
+where
+    F: FnMut(DndAction, DndAction) -> DndAction + 'static,
+    C: FnMut(DataDeviceEvent) + 'static,
+    R: Role<DnDIconRole> + 'static,
+    L: Into<Option<::slog::Logger>>,
+{
+    let log = crate::slog_or_fallback(logger).new(o!("smithay_module" => "data_device_mgr"));
+    let config = Rc::new(config);
+    let known_seats = Rc::new(RefCell::new(Vec::new()));
+    let destroyed = Rc::new(Cell::new(false));
+    // this chooser only needs the actions of the context
+    let action_choice: Rc<RefCell<dyn FnMut(&DndActionContext<'_>) -> DndAction + 'static>> =
+        Rc::new(RefCell::new(move |context: &DndActionContext<'_>| {
+            (&mut *action_choice.borrow_mut())(context.available, context.preferred)
+        }));
+    let ddm_seats = known_seats.clone();
+    let ddm_destroyed = destroyed.clone();
+    let global = display.create_global(
+        3,
+        Filter::new(move |(ddm, _version), _, _| {
+            implement_ddm(
+                ddm,
+                callback.clone(),
+                action_choice.clone(),
+                token,
+                config.clone(),
+                ddm_seats.clone(),
+                ddm_destroyed.clone(),
+                log.clone(),
+            );
+        }),
+    );
+    DataDeviceHandle {
+        global,
+        known_seats,
+        destroyed,
+    }
 }
 
-/// A simple action chooser for DnD negociation
+/// Set the data device focus to a certain client for a given seat
 ///
-/// If the preferred action is available, it'll pick it. Otherwise, it'll pick the first
-/// available in the following order: Ask, Copy, Move.
-pub fn default_action_chooser(available: DndAction, preferred: DndAction) -> DndAction {
-    // if the preferred action is valid (a single action) and in the available actions, use it
-    // otherwise, follow a fallback stategy
-    if [DndAction::Move, DndAction::Copy, DndAction::Ask].contains(&preferred)
-        && available.contains(preferred)
-    {
-        preferred
-    } else if available.contains(DndAction::Ask) {
-        DndAction::Ask
-    } else if available.contains(DndAction::Copy) {
-        DndAction::Copy
-    } else if available.contains(DndAction::Move) {
-        DndAction::Move
+/// The focused client is sent the current selection. Setting the focus to `None`, for
+/// example while the focused window is minimized, only stops delivering it: the selection
+/// itself is kept, and sent again to the next focused client.
+pub fn set_data_device_focus(seat: &Seat, client: Option<Client>) {
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_focus(client);
+}
+
+/// Hide the selection of this seat from every client
+///
+/// While inhibited, every client is advertised an empty selection whatever the actual one,
+/// and the offers of the selection sent before are denied, for example so that a lock screen
+/// cannot read what was copied before the session was locked. The selection is still tracked
+/// as usual in the meantime, and the actual one is advertised again to the focused client
+/// once the inhibition is lifted. Your compositor can still read it with [`read_selection`].
+pub fn set_data_device_focus_inhibited(seat: &Seat, inhibited: bool) {
+    seat_data(seat).borrow_mut().set_focus_inhibited(inhibited);
+}
+
+/// Set whether the data device focus of this seat follows its keyboard focus
+///
+/// While enabled, the data device focus is set to the client of the surface having the
+/// keyboard focus each time it changes, starting with the current one. Once disabled, the
+/// data device focus is kept as is, and you drive it with [`set_data_device_focus`], which
+/// allows you to hide the selection from every client for a while. Calling
+/// `set_data_device_focus` while it is enabled only lasts until the next keyboard focus change.
+///
+/// This overrides the [`follow_keyboard_focus`](DataDeviceConfig::follow_keyboard_focus)
+/// configuration for this seat.
+pub fn set_data_device_follows_keyboard_focus(seat: &Seat, follow: bool) {
+    let seat_data = seat_data(seat);
+    let register = {
+        let mut seat_data = seat_data.borrow_mut();
+        seat_data.follow_keyboard_focus = Some(follow);
+        let register = follow && !seat_data.keyboard_focus_listener;
+        seat_data.keyboard_focus_listener |= follow;
+        register
+    };
+    if register {
+        // registered once per seat, it does nothing while following is disabled
+        seat.add_keyboard_focus_listener(keyboard_focus_changed);
+    }
+    if follow {
+        let client = seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .and_then(|surface| surface.as_ref().client());
+        seat_data.borrow_mut().set_focus(client);
+    }
+}
+
+// Update the data device focus of a seat following its keyboard focus
+fn keyboard_focus_changed(seat: &Seat, focus: Option<&wl_surface::WlSurface>) {
+    let seat_data = seat_data(seat);
+    if seat_data.borrow().follow_keyboard_focus == Some(true) {
+        let client = focus.and_then(|surface| surface.as_ref().client());
+        seat_data.borrow_mut().set_focus(client);
+    }
+}
+
+/// Clear the selection of this seat
+///
+/// The focused client will be notified that there is no longer any selection.
+pub fn clear_data_device_selection(seat: &Seat) {
+    seat_data(seat).borrow_mut().set_selection(Selection::Empty);
+}
+
+/// Retrieve the mime types offered by the current selection of this seat
+///
+/// Returns an empty list if there is no selection. This allows you to pick the mime type
+/// to request with [`read_selection`].
+pub fn selection_mime_types(seat: &Seat) -> Vec<String> {
+    seat_data(seat).borrow_mut().mime_types()
+}
+
+/// Check whether the current selection of this seat offers a given mime type
+///
+/// Returns `false` if there is no selection.
+pub fn selection_has_mime(seat: &Seat, mime_type: &str) -> bool {
+    seat_data(seat).borrow_mut().has_mime_type(mime_type)
+}
+
+/// Take a snapshot of the current selection of this seat
+///
+/// See [`selections_equal`] to compare it with another snapshot.
+pub fn selection_snapshot(seat: &Seat) -> SelectionSnapshot {
+    seat_data(seat).borrow_mut().snapshot()
+}
+
+/// Retrieve the client owning the current selection of this seat
+///
+/// Returns `None` if the selection is empty or was set by the compositor, including
+/// when it was cached from a client that has since disconnected.
+pub fn selection_owner_client(seat: &Seat) -> Option<Client> {
+    selection_snapshot(seat)
+        .client_source()
+        .and_then(|source| source.as_ref().client())
+}
+
+/// Set a compositor-provided selection for this seat
+///
+/// You need to provide the available mime types for this selection. Duplicated mime types
+/// are only advertised once, and a selection without any mime type is ignored.
+///
+/// Whenever a client requests to read the selection, your callback will
+/// receive a [`DataDeviceEvent::SendSelection`] event.
+pub fn set_data_device_selection(seat: &Seat, mime_types: Vec<String>) {
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_selection(Selection::Compositor {
+        metadata: SourceMetadata::new(mime_types),
+        content: Rc::new(SelectionContent::Callback),
+    });
+}
+
+/// Set a compositor-provided selection for this seat, marked with a tag
+///
+/// This is the same as [`set_data_device_selection`], but the tag is reported to the
+/// callback set with [`set_selection_change_callback`], allowing you to recognize this
+/// selection when notified of its installation.
+pub fn set_data_device_selection_tagged(seat: &Seat, mime_types: Vec<String>, tag: u64) {
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_tagged_selection(
+        Selection::Compositor {
+            metadata: SourceMetadata::new(mime_types),
+            content: Rc::new(SelectionContent::Callback),
+        },
+        Some(tag),
+    );
+}
+
+/// Forcibly take over the selection of this seat
+///
+/// This behaves like [`set_data_device_selection`], except that if a client is holding
+/// the selection, its data source is guaranteed to receive a `cancelled` event, so that
+/// it knows it lost the selection. Only the mime types of the provided metadata are used.
+///
+/// This is useful for example to override the selection of a client with a value of
+/// the compositor.
+pub fn take_selection(seat: &Seat, metadata: SourceMetadata) {
+    let mut seat_data = seat_data(seat).borrow_mut();
+    // validated the way the new selection will be, before the client loses its own
+    let metadata = SourceMetadata::new(metadata.mime_types);
+    if metadata.mime_types.is_empty() {
+        // the selection would be ignored, the client must keep it
+        warn!(
+            seat_data.log,
+            "Ignoring a compositor selection without any mime type"
+        );
+        return;
+    }
+    if let Selection::Client(ref source) = seat_data.selection {
+        if source.as_ref().is_alive() {
+            source.cancelled();
+        }
+    }
+    seat_data.set_selection(Selection::Compositor {
+        metadata,
+        content: Rc::new(SelectionContent::Callback),
+    });
+}
+
+/// Install the data source of a client as the selection of this seat
+///
+/// Clients can only set the selection while they have the keyboard focus, this allows your
+/// compositor to adopt the source of a client as the selection regardless, for example to
+/// restore it after switching virtual desktops. If another client is holding the selection,
+/// its data source receives a `cancelled` event.
+///
+/// Returns `false` and leaves the selection unchanged if the source is no longer alive, was not
+/// created by a data device manager of smithay, or does not offer any mime type.
+pub fn force_set_client_selection(seat: &Seat, source: wl_data_source::WlDataSource) -> bool {
+    let offers_something = with_source_metadata(&source, |meta| !meta.mime_types.is_empty()).unwrap_or(false);
+    if !source.as_ref().is_alive() || !offers_something {
+        return false;
+    }
+    // the source losing the selection is cancelled when it is replaced
+    seat_data(seat)
+        .borrow_mut()
+        .set_selection(Selection::Client(source));
+    true
+}
+
+/// Modify the metadata of a data source, re-advertising the selections it is part of
+///
+/// This allows your compositor to update the mime types or actions of a source after its
+/// creation, for example when implementing a protocol that manages selections on behalf of
+/// clients. The mime types are normalized afterwards, the same way as by
+/// [`SourceMetadata::new`], and the ones beyond the
+/// [`max_mime_types`](DataDeviceConfig::max_mime_types) of the data device manager that created
+/// the source are dropped. For every seat the source is the selection of, the focused client
+/// is sent a new offer advertising the updated mime types.
+///
+/// Returns `Err(())` if the source was not created by a data device manager of smithay, or if
+/// its metadata is currently being accessed, which happens if you call this from the closure
+/// given to [`with_source_metadata`].
+pub fn with_source_metadata_mut<T, F>(source: &wl_data_source::WlDataSource, f: F) -> Result<T, ()>
+where
+    F: FnOnce(&mut SourceMetadata) -> T,
+{
+    let data = source.as_ref().user_data().get::<DataSourceData>().ok_or(())?;
+    let result = {
+        let mut metadata = data.metadata.try_borrow_mut().map_err(|_| ())?;
+        let result = f(&mut metadata);
+        normalize_mime_types(&mut metadata.mime_types);
+        metadata.mime_types.truncate(data.max_mime_types);
+        result
+    };
+    // the metadata is released, as it is read when sending the selection
+    let seats = data
+        .seats
+        .borrow()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|arc| Seat { arc })
+        .collect::<Vec<_>>();
+    for seat in seats {
+        seat_data(&seat).borrow_mut().source_updated(source);
+    }
+    Ok(result)
+}
+
+/// Set the selection of this seat to some bytes provided by the compositor
+///
+/// The same bytes will be served for all the provided mime types. Contrary to
+/// [`set_data_device_selection`], smithay will write the data to the clients by
+/// itself, and your callback will not receive any [`DataDeviceEvent::SendSelection`]
+/// event for this selection.
+pub fn copy_bytes(seat: &Seat, mime_types: Vec<String>, data: Vec<u8>) {
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_selection(Selection::Compositor {
+        metadata: SourceMetadata::new(mime_types),
+        content: Rc::new(SelectionContent::Bytes(Arc::new(data))),
+    });
+}
+
+/// Set the selection of this seat to some bytes provided by the compositor, for each mime type
+///
+/// Each mime type is advertised with its own contents, in the provided order. As with
+/// [`copy_bytes`], smithay serves the data to the clients by itself, and your callback
+/// will not receive any [`DataDeviceEvent::SendSelection`] event for this selection. If a
+/// mime type is given several times, only its last contents are kept.
+pub fn set_data_device_selection_buffer(seat: &Seat, contents: Vec<(String, Vec<u8>)>) {
+    let mut mime_types = Vec::new();
+    let mut buffers = HashMap::new();
+    for (mime_type, data) in contents {
+        if !mime_types.contains(&mime_type) {
+            mime_types.push(mime_type.clone());
+        }
+        buffers.insert(mime_type, Arc::new(data));
+    }
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_selection(Selection::Compositor {
+        metadata: SourceMetadata::new(mime_types),
+        content: Rc::new(SelectionContent::Buffers(buffers)),
+    });
+}
+
+/// Set the selection of this seat to some bytes, only if there is currently no selection
+///
+/// This behaves like [`copy_bytes`], but does nothing if a client or your compositor
+/// already provides a selection, for example to give a default value to the clipboard
+/// of a new session without overriding anything. Returns whether the selection was set.
+pub fn set_data_device_selection_if_empty(seat: &Seat, mime_types: Vec<String>, data: Vec<u8>) -> bool {
+    let mut seat_data = seat_data(seat).borrow_mut();
+    seat_data.sanitize_selection();
+    if let Selection::Empty = seat_data.selection {
+        seat_data.set_selection(Selection::Compositor {
+            metadata: SourceMetadata::new(mime_types),
+            content: Rc::new(SelectionContent::Bytes(Arc::new(data))),
+        });
+        true
+    } else {
+        false
+    }
+}
+
+/// Set the selection of this seat to the contents of a file
+///
+/// The file is only opened and read when a client requests the selection, so its
+/// contents are never loaded in memory as a whole. If the file can no longer be opened
+/// at that point, the client will simply receive no data.
+///
+/// As with [`copy_bytes`], smithay will write the data to the clients by itself.
+pub fn set_data_device_selection_file(seat: &Seat, mime_type: String, path: PathBuf) {
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_selection(Selection::Compositor {
+        metadata: SourceMetadata::new(vec![mime_type]),
+        content: Rc::new(SelectionContent::File(path)),
+    });
+}
+
+/// Set the selection of this seat to the contents of readers created on demand
+///
+/// Each time a client requests the selection, the provided closure is called to create a
+/// new reader, whose contents are streamed to the client. This allows you to serve data
+/// from arbitrary sources (like decompression or network streams) without buffering it.
+/// If the reader fails, the transfer is aborted.
+///
+/// As with [`copy_bytes`], smithay will write the data to the clients by itself.
+pub fn set_data_device_selection_reader<F>(seat: &Seat, mime_type: String, reader_factory: F)
+where
+    F: FnMut() -> Box<dyn Read + Send> + 'static,
+{
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_selection(Selection::Compositor {
+        metadata: SourceMetadata::new(vec![mime_type]),
+        content: Rc::new(SelectionContent::Reader(RefCell::new(Box::new(reader_factory)))),
+    });
+}
+
+/// Set the selection of this seat to a selection provided from outside of this display
+///
+/// This is meant for selections smithay cannot serve by itself, like the selection of the parent
+/// compositor when running nested. Each time a client requests the selection, the requested mime
+/// type and the fd to write the data into are given to the `forward` closure, which takes
+/// ownership of the fd. See the [module-level documentation](self#nested-compositors) for an
+/// example.
+///
+/// Unlike with [`set_data_device_selection`], these requests are not given to the callback of
+/// [`init_data_device`].
+pub fn install_remote_selection<F>(seat: &Seat, mime_types: Vec<String>, forward: F)
+where
+    F: FnMut(String, RawFd) + 'static,
+{
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_selection(Selection::Compositor {
+        metadata: SourceMetadata::new(mime_types),
+        content: Rc::new(SelectionContent::Remote(RefCell::new(Box::new(forward)))),
+    });
+}
+
+/// Set the selection of this seat to contents generated by a compositor-provided source
+///
+/// The advertised mime types are the ones returned by [`SelectionSource::mime_types`] when the
+/// selection is set. Whenever a client requests to read the selection, smithay asks the source
+/// to [write](SelectionSource::write) its contents in the requested mime type and serves them
+/// by itself: your callback will not receive any [`DataDeviceEvent::SendSelection`] event for
+/// this selection.
+pub fn set_data_device_selection_source(seat: &Seat, source: Box<dyn SelectionSource>) {
+    let seat_data = seat_data(seat);
+    seat_data.borrow_mut().set_selection(Selection::Compositor {
+        metadata: SourceMetadata::new(source.mime_types()),
+        content: Rc::new(SelectionContent::Source(source)),
+    });
+}
+
+/// Retrieve the mime types of the past selections of this seat, the most recent first
+///
+/// The current selection is the first one, unless it could not be recorded. This is empty
+/// unless the [`selection_history`](DataDeviceConfig::selection_history) is enabled.
+pub fn selection_history(seat: &Seat) -> Vec<Vec<String>> {
+    seat_data(seat).borrow().history.mime_types()
+}
+
+/// Reinstall a past selection of this seat
+///
+/// The selection at this index of the [`selection_history`] is set again as a selection
+/// provided by the compositor, and becomes the most recent one. Only its mime types whose
+/// contents could be kept are offered.
+///
+/// Returns `false` and leaves the selection unchanged if there is no such selection, or if
+/// none of its contents could be kept, in which case it is removed from the history.
+pub fn restore_selection(seat: &Seat, index: usize) -> bool {
+    let mut seat_data = seat_data(seat).borrow_mut();
+    match seat_data.history.take(index) {
+        Some((metadata, content)) => {
+            seat_data.set_selection(Selection::Compositor {
+                metadata,
+                content: Rc::new(content),
+            });
+            true
+        }
+        None => false,
+    }
+}
+
+/// Write the current selection of this seat into an fd
+///
+/// This allows your compositor to read the selection whoever provides it, for example to
+/// forward it to the parent compositor when running nested. The data of the given mime type
+/// is written into `fd` the same way as when a client pastes it, without asking for a
+/// [paste confirmation](set_paste_confirmation). This takes ownership of the fd.
+///
+/// Returns `false` and closes the fd if the selection is empty, does not offer this mime type,
+/// or was set with [`set_data_device_selection`], as you provide its contents yourself.
+pub fn request_selection(seat: &Seat, mime_type: &str, fd: RawFd) -> bool {
+    seat_data(seat).borrow_mut().request_selection(mime_type, fd)
+}
+
+// Write some bytes into an fd provided by a client without blocking, the way the contents of
+// compositor selections are served, for the helpers bridging other selection mechanisms
+pub(crate) fn serve_bytes(fd: RawFd, data: Vec<u8>, log: &::slog::Logger) {
+    SelectionContent::Bytes(Arc::new(data)).serve("", fd, None, log);
+}
+
+// Register a listener notified of every change of the selection of this seat, for the
+// protocols giving access to the selection besides the data device
+pub(crate) fn add_selection_listener(seat: &Seat, listener: Rc<dyn Fn(&SelectionSnapshot)>) {
+    seat_data(seat).borrow_mut().selection_listeners.push(listener);
+}
+
+/// Set the selection of this seat to some bytes of unknown type
+///
+/// The advertised mime types are guessed from the contents of the data, see
+/// [`sniff_mime_types`] for details about the detection. If you want to
+/// advertise other mime types, use [`copy_bytes`] instead.
+pub fn copy_bytes_sniffed(seat: &Seat, data: Vec<u8>) {
+    let mime_types = sniff_mime_types(&data);
+    copy_bytes(seat, mime_types, data);
+}
+
+/// Access the icon surface of the drag'n'drop currently active on this seat
+///
+/// This allows you to draw the icon at the pointer location while a client-initiated
+/// drag'n'drop is in progress. Returns `None` if there is no such drag'n'drop, or if
+/// it has no icon.
+pub fn dnd_icon_surface(seat: &Seat) -> Option<wl_surface::WlSurface> {
+    seat.user_data()
+        .get::<SeatDataCell>()
+        .and_then(|seat_data| seat_data.borrow().current_dnd.as_ref()?.icon.clone())
+        .filter(|icon| icon.as_ref().is_alive())
+}
+
+/// Retrieve the location at which the icon of the drag'n'drop currently active on this seat should be drawn
+///
+/// This is the pointer location offset by the hotspot of the icon, see [`move_dnd_icon`]. Returns
+/// `None` if there is no client-initiated drag'n'drop with an icon on this seat.
+pub fn dnd_icon_position(seat: &Seat) -> Option<(f64, f64)> {
+    dnd_icon_surface(seat)?;
+    let seat_data = seat.user_data().get::<SeatDataCell>()?.borrow();
+    let dnd = seat_data.current_dnd.as_ref()?;
+    let ((x, y), (dx, dy)) = (dnd.location, dnd.icon_hotspot);
+    Some((x + f64::from(dx), y + f64::from(dy)))
+}
+
+/// Move the icon of the drag'n'drop currently active on this seat relative to the pointer
+///
+/// The hotspot of the icon starts at `(0, 0)`, and clients move the icon by attaching buffers
+/// with an offset, so you should call this with the `delta` of every new buffer attached to the
+/// icon surface when it is committed. Does nothing if there is no client-initiated drag'n'drop
+/// on this seat.
+pub fn move_dnd_icon(seat: &Seat, delta: (i32, i32)) {
+    if let Some(seat_data) = seat.user_data().get::<SeatDataCell>() {
+        if let Some(dnd) = seat_data.borrow_mut().current_dnd.as_mut() {
+            dnd.icon_hotspot = (dnd.icon_hotspot.0 + delta.0, dnd.icon_hotspot.1 + delta.1);
+        }
+    }
+}
+
+/// Access the surface from which the drag'n'drop currently active on this seat originates
+///
+/// Returns `None` if there is no client-initiated drag'n'drop on this seat.
+pub fn dnd_origin_surface(seat: &Seat) -> Option<wl_surface::WlSurface> {
+    seat.user_data()
+        .get::<SeatDataCell>()
+        .and_then(|seat_data| {
+            seat_data
+                .borrow()
+                .current_dnd
+                .as_ref()
+                .map(|dnd| dnd.origin.clone())
+        })
+        .filter(|origin| origin.as_ref().is_alive())
+}
+
+/// The kind of a drag'n'drop active on a seat
+///
+/// See [`active_dnd`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DndKind {
+    /// The drag'n'drop was started by a client, which may have provided an icon for it,
+    /// see [`dnd_icon_surface`]
+    Client,
+    /// The drag'n'drop was started by your compositor with [`start_dnd`], you are
+    /// responsible for drawing its icon, if any
+    Compositor,
+}
+
+/// Retrieve the kind of the drag'n'drop currently active on this seat
+///
+/// Returns `None` if there is no drag'n'drop in progress. A drag'n'drop is active from the
+/// moment its pointer grab is set, until the grab is removed or replaced.
+pub fn active_dnd(seat: &Seat) -> Option<DndKind> {
+    let seat_data = seat.user_data().get::<SeatDataCell>()?.borrow();
+    if seat_data.server_dnd.is_some() {
+        Some(DndKind::Compositor)
+    } else if seat_data.current_dnd.is_some() {
+        Some(DndKind::Client)
     } else {
-        DndAction::empty()
+        None
+    }
+}
+
+/// Check whether a drag'n'drop is currently active on this seat
+///
+/// This is true for drag'n'drops started by clients as well as by your compositor, and
+/// allows you to suppress other pointer behaviors in the meantime. See [`active_dnd`] to
+/// tell them apart.
+pub fn is_dnd_active(seat: &Seat) -> bool {
+    active_dnd(seat).is_some()
+}
+
+/// Retrieve the mime types offered by the drag'n'drop currently active on this seat
+///
+/// This allows you to check whether the dragged data is relevant for a drop target drawn
+/// by your compositor (for example to highlight it) while the user is still dragging.
+///
+/// Returns an empty list if there is no client-initiated drag'n'drop on this seat, or if
+/// the drag'n'drop has no data source (in which case the data transfer is handled by the
+/// client itself).
+pub fn current_dnd_mime_types(seat: &Seat) -> Vec<String> {
+    seat.user_data()
+        .get::<SeatDataCell>()
+        .and_then(|seat_data| {
+            let seat_data = seat_data.borrow();
+            let source = seat_data.current_dnd.as_ref()?.source.as_ref()?;
+            with_source_metadata(source, |meta| meta.mime_types.clone()).ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Retrieve the version of the data device a client created for this seat
+///
+/// This allows you to adapt to the capabilities of the client, for example drag'n'drop
+/// actions are only supported starting with version 3. Returns `None` if the client has
+/// no data device for this seat.
+pub fn client_data_device_version(seat: &Seat, client: &Client) -> Option<u32> {
+    seat.user_data().get::<SeatDataCell>().and_then(|seat_data| {
+        seat_data
+            .borrow()
+            .known_devices
+            .of_client(client)
+            .iter()
+            .find(|dd| dd.as_ref().is_alive())
+            .map(|dd| dd.as_ref().version())
+    })
+}
+
+/// Set a callback notified of every drag'n'drop dropped on this seat
+///
+/// The callback receives a single [`DndAuditRecord`] for each drag'n'drop when the user
+/// drops it, both for client-initiated and compositor-initiated sessions, making it suitable
+/// to maintain an audit log of the data exchanged between clients. Note that the data
+/// transfer itself happens after the drop.
+///
+/// This replaces any previously set callback.
+pub fn set_dnd_audit_callback<F>(seat: &Seat, callback: F)
+where
+    F: FnMut(DndAuditRecord) + 'static,
+{
+    seat_data(seat).borrow_mut().dnd_audit = Some(Rc::new(RefCell::new(callback)));
+}
+
+/// Set a callback choosing the action of the drag'n'drops on this seat
+///
+/// The action chooser given when initializing the data device global only sees the available
+/// and preferred actions. This one replaces it for this seat, and also receives the clients
+/// involved and the mime types offered, in a [`DndActionContext`], so that you can for example
+/// forbid moving data between clients that should stay isolated from each other. It follows the
+/// same rules: return a single action among the available ones, or
+/// [`DndAction::empty()`](wayland_server::protocol::wl_data_device_manager::DndAction::empty)
+/// to abort the drag'n'drop on drop.
+///
+/// This replaces any previously set chooser, and only applies to drag'n'drops started
+/// afterwards.
+pub fn set_dnd_action_chooser<F>(seat: &Seat, chooser: F)
+where
+    F: FnMut(&DndActionContext<'_>) -> DndAction + 'static,
+{
+    seat_data(seat).borrow_mut().action_chooser = Some(Rc::new(RefCell::new(chooser)));
+}
+
+/// Set a callback resolving the `ask` action of the drag'n'drops on this seat
+///
+/// When the source and the target of a client drag'n'drop negotiate the `ask` action, the
+/// callback receives an [`AskActionResolver`], allowing your compositor to ask the user which
+/// action to perform. Until you resolve it, the drag'n'drop is pending, and it is cancelled if
+/// the user drops it before. Without this callback, the `ask` action is left to the target to
+/// resolve after the drop, as the protocol intends.
+///
+/// This replaces any previously set callback, and only applies to drag'n'drops started
+/// afterwards.
+pub fn set_ask_action_callback<F>(seat: &Seat, callback: F)
+where
+    F: FnMut(AskActionResolver) + 'static,
+{
+    seat_data(seat).borrow_mut().ask_action = Some(Rc::new(RefCell::new(callback)));
+}
+
+/// Set a callback notified of every change of the selection of this seat
+///
+/// The callback receives a [`SelectionChanged`] each time the selection changes, whether it
+/// was set by a client or by your compositor.
+///
+/// This replaces any previously set callback.
+pub fn set_selection_change_callback<F>(seat: &Seat, callback: F)
+where
+    F: FnMut(SelectionChanged) + 'static,
+{
+    seat_data(seat).borrow_mut().selection_change_callback = Some(Rc::new(RefCell::new(callback)));
+}
+
+/// Require the compositor to confirm every paste from the selection of this seat
+///
+/// Once set, every request of a client to read the selection of this seat, be it provided
+/// by a client or by the compositor, is given to the callback as a [`PasteRequest`]. The
+/// data is only sent to the client once the request is approved, which can be done later
+/// on, for example after asking the user through a dialog.
+///
+/// Note that the requesting client is waiting for the data during this time, so leaving
+/// requests pending for long may make it appear frozen to the user.
+///
+/// This replaces any previously set callback.
+pub fn set_paste_confirmation<F>(seat: &Seat, callback: F)
+where
+    F: FnMut(PasteRequest) + 'static,
+{
+    *seat_data(seat).borrow().paste_confirmation.borrow_mut() = Some(Rc::new(RefCell::new(callback)));
+}
+
+/// Stop requiring the compositor to confirm pastes from the selection of this seat
+///
+/// All future pastes are allowed directly, requests that are still pending are unaffected.
+pub fn unset_paste_confirmation(seat: &Seat) {
+    if let Some(seat_data) = seat.user_data().get::<SeatDataCell>() {
+        *seat_data.borrow().paste_confirmation.borrow_mut() = None;
+    }
+}
+
+/// Start a drag'n'drop from a ressource controlled by the compositor
+///
+/// You'll receive events generated by the interaction of clients with your
+/// drag'n'drop in the provided callback. See [`ServerDndEvent`] for details about
+/// which events can be generated and what response is expected from you to them.
+///
+/// The drag'n'drop is driven by the pointer of the seat.
+///
+/// The returned [`ServerDndHandle`] allows you to cancel this drag'n'drop later on.
+pub fn start_dnd<C>(
+    seat: &Seat,
+    serial: Serial,
+    start_data: GrabStartData,
+    metadata: SourceMetadata,
+    callback: C,
+) -> ServerDndHandle
+where
+    C: FnMut(ServerDndEvent) + 'static,
+{
+    start_server_dnd(seat, serial, start_data, metadata, false, callback)
+}
+
+/// Start a dry-run drag'n'drop from a ressource controlled by the compositor
+///
+/// This behaves as [`start_dnd`] while the pointer moves, giving feedback to the clients it
+/// goes over, but no data is ever transferred: the drop is reported as
+/// [`ServerDndEvent::DryRunDrop`] and the drag'n'drop is then cancelled. This allows you to
+/// find out the potential targets of a drag'n'drop.
+pub fn start_dnd_dry_run<C>(
+    seat: &Seat,
+    serial: Serial,
+    start_data: GrabStartData,
+    metadata: SourceMetadata,
+    callback: C,
+) -> ServerDndHandle
+where
+    C: FnMut(ServerDndEvent) + 'static,
+{
+    start_server_dnd(seat, serial, start_data, metadata, true, callback)
+}
+
+fn start_server_dnd<C>(
+    seat: &Seat,
+    serial: Serial,
+    start_data: GrabStartData,
+    metadata: SourceMetadata,
+    dry_run: bool,
+    callback: C,
+) -> ServerDndHandle
+where
+    C: FnMut(ServerDndEvent) + 'static,
+{
+    // ensure the seat user_data is ready
+    seat_data(seat);
+    let finish_requested = Rc::new(Cell::new(false));
+    let handle = ServerDndHandle {
+        seat: seat.clone(),
+        finish_requested: Rc::downgrade(&finish_requested),
+    };
+    if let Some(pointer) = seat.get_pointer() {
+        pointer.set_grab(
+            server_dnd_grab::ServerDnDGrab::new(
+                start_data,
+                metadata,
+                seat.clone(),
+                Rc::new(RefCell::new(callback)),
+                finish_requested.clone(),
+                dry_run,
+            ),
+            serial,
+        );
+        // set after the grab, as replacing a previous server drag'n'drop grab clears it
+        seat_data(seat).borrow_mut().server_dnd = Some(finish_requested);
+    }
+    handle
+}
+
+/// A handle to a drag'n'drop started by the compositor
+///
+/// It is returned by [`start_dnd`].
+#[derive(Clone)]
+pub struct ServerDndHandle {
+    seat: Seat,
+    finish_requested: Weak<Cell<bool>>,
+}
+
+impl ServerDndHandle {
+    /// Cancel this drag'n'drop
+    ///
+    /// This is the same as [`cancel_server_dnd`], except that it only affects this precise
+    /// drag'n'drop: the handle does nothing once it completed, was cancelled, or was replaced by
+    /// another drag'n'drop. The pointer of the seat then goes back to its default behavior.
+    pub fn cancel(&self) {
+        if self.is_active() {
+            end_server_dnd(&self.seat, false);
+        }
+    }
+
+    /// Whether this drag'n'drop is still ongoing
+    pub fn is_active(&self) -> bool {
+        let finish_requested = match self.finish_requested.upgrade() {
+            Some(finish_requested) => finish_requested,
+            None => return false,
+        };
+        self.seat
+            .user_data()
+            .get::<SeatDataCell>()
+            .and_then(|seat_data| seat_data.borrow().server_dnd.clone())
+            .map(|current| Rc::ptr_eq(&current, &finish_requested))
+            .unwrap_or(false)
+    }
+}
+
+/// Complete the drag'n'drop started by the compositor on this seat
+///
+/// This drops the drag'n'drop on its current target, as if the user released the pointer
+/// button: your callback receives [`ServerDndEvent::Dropped`], followed by
+/// [`ServerDndEvent::Cancelled`] if the target did not accept it or no action was negotiated.
+/// The pointer grab of the drag'n'drop is then removed.
+///
+/// Does nothing if no drag'n'drop started by the compositor is active.
+pub fn finish_server_dnd(seat: &Seat) {
+    end_server_dnd(seat, true);
+}
+
+/// Cancel the drag'n'drop started by the compositor on this seat
+///
+/// The current target is left, your callback receives [`ServerDndEvent::Cancelled`] and the
+/// pointer grab of the drag'n'drop is removed.
+///
+/// Does nothing if no drag'n'drop started by the compositor is active.
+pub fn cancel_server_dnd(seat: &Seat) {
+    end_server_dnd(seat, false);
+}
+
+/// Cancel the drag'n'drop active on this seat, if any
+///
+/// This cancels both drag'n'drops started by clients and by the compositor: the current
+/// target is left, the data source of the client is cancelled and your callback receives
+/// [`DataDeviceEvent::DnDCancelled`] or [`ServerDndEvent::Cancelled`] respectively. The pointer
+/// grab of the drag'n'drop is then removed.
+///
+/// Does nothing if no drag'n'drop is active.
+pub fn cancel_dnd(seat: &Seat) {
+    let (client_dnd, server_dnd) = match seat.user_data().get::<SeatDataCell>() {
+        Some(seat_data) => {
+            let seat_data = seat_data.borrow();
+            (
+                seat_data.current_dnd.as_ref().map(|dnd| dnd.touch),
+                seat_data.server_dnd.is_some(),
+            )
+        }
+        None => (None, false),
+    };
+    // removing the grab before the drop cancels the drag'n'drop
+    if server_dnd {
+        end_server_dnd(seat, false);
+    } else if let (Some(false), Some(pointer)) = (client_dnd, seat.get_pointer()) {
+        pointer.unset_grab();
+    } else if let (Some(true), Some(touch)) = (client_dnd, seat.get_touch()) {
+        touch.unset_grab();
+    }
+}
+
+/// Cancel the drag'n'drops active on all these seats
+///
+/// This is the same as calling [`cancel_dnd`] on each seat, for example to make sure no
+/// drag'n'drop goes on while locking the session. Seats without an active drag'n'drop are
+/// left untouched.
+pub fn cancel_all_drags(seats: &[Seat]) {
+    for seat in seats {
+        cancel_dnd(seat);
+    }
+}
+
+fn end_server_dnd(seat: &Seat, finish: bool) {
+    let finish_requested = match seat.user_data().get::<SeatDataCell>() {
+        Some(seat_data) => seat_data.borrow().server_dnd.clone(),
+        None => None,
+    };
+    if let (Some(finish_requested), Some(pointer)) = (finish_requested, seat.get_pointer()) {
+        finish_requested.set(finish);
+        // removing the grab ends the drag'n'drop
+        pointer.unset_grab();
+    }
+}
+
+fn implement_ddm<C, R>(
+    ddm: Main<wl_data_device_manager::WlDataDeviceManager>,
+    callback: Rc<RefCell<C>>,
+    action_choice: Rc<RefCell<dyn FnMut(&DndActionContext<'_>) -> DndAction + 'static>>,
+    token: CompositorToken<R>,
+    config: Rc<DataDeviceConfig>,
+    known_seats: Rc<RefCell<Vec<Seat>>>,
+    destroyed: Rc<Cell<bool>>,
+    log: ::slog::Logger,
+) -> wl_data_device_manager::WlDataDeviceManager
+where
+    C: FnMut(DataDeviceEvent) + 'static,
+    R: Role<DnDIconRole> + 'static,
+{
+    use self::wl_data_device_manager::Request;
+    ddm.quick_assign(move |_ddm, req, _data| match req {
+        Request::CreateDataSource { id } => {
+            let seats = known_seats.clone();
+            self::data_source::implement_data_source(
+                id,
+                config.max_mime_types,
+                move |source| {
+                    // some clients offer mime types after setting their source as the
+                    // selection, advertise them as well
+                    for seat in seats.borrow().iter() {
+                        if let Some(seat_data) = seat.user_data().get::<SeatDataCell>() {
+                            seat_data.borrow_mut().source_updated(source);
+                        }
+                    }
+                },
+                log.clone(),
+            );
+        }
+        Request::GetDataDevice { id, .. } if destroyed.get() => {
+            // the global was destroyed, the data device stays inert
+            id.quick_assign(|_, _, _| {});
+        }
+        Request::GetDataDevice { id, seat } => match Seat::from_resource(&seat) {
+            Some(seat) => {
+                {
+                    let mut seats = known_seats.borrow_mut();
+                    if !seats.contains(&seat) {
+                        seats.push(seat.clone());
+                    }
+                }
+                // ensure the seat user_data is ready
+                seat.user_data()
+                    .insert_if_missing(|| SeatDataCell(RefCell::new(SeatData::new(log.clone(), &seat))));
+                let seat_data = seat.user_data().get::<SeatDataCell>().unwrap();
+                if config.log_selection_changes {
+                    seat_data.borrow_mut().log_selection_changes = true;
+                }
+                if config.selection_history > 0 {
+                    seat_data
+                        .borrow_mut()
+                        .history
+                        .set_capacity(config.selection_history);
+                }
+                if config.follow_keyboard_focus && seat_data.borrow().follow_keyboard_focus.is_none() {
+                    set_data_device_follows_keyboard_focus(&seat, true);
+                }
+                let data_device = implement_data_device(
+                    id,
+                    seat.clone(),
+                    callback.clone(),
+                    action_choice.clone(),
+                    token,
+                    config.clone(),
+                    log.clone(),
+                );
+                let mut seat_data = seat_data.borrow_mut();
+                seat_data.known_devices.push(data_device.clone());
+                // a client creating its data device while already focused gets the current
+                // selection right away, rather than on the next focus change
+                seat_data.device_created(&data_device);
+            }
+            None => {
+                error!(log, "Unmanaged seat given to a data device.");
+            }
+        },
+        _ => unreachable!(),
+    });
+
+    ddm.deref().clone()
+}
+
+struct DataDeviceData {
+    callback: Rc<RefCell<dyn FnMut(DataDeviceEvent) + 'static>>,
+    action_choice: Rc<RefCell<dyn FnMut(&DndActionContext<'_>) -> DndAction + 'static>>,
+    config: Rc<DataDeviceConfig>,
+    // the serial of the last selection sent to this data device, with its offer
+    sent_selection: RefCell<Option<(u64, Option<wl_data_offer::WlDataOffer>)>>,
+    // the selection offers sent to this data device, which may still be alive
+    selection_offers: RefCell<Vec<wl_data_offer::WlDataOffer>>,
+}
+
+fn implement_data_device<C, R>(
+    dd: Main<wl_data_device::WlDataDevice>,
+    seat: Seat,
+    callback: Rc<RefCell<C>>,
+    action_choice: Rc<RefCell<dyn FnMut(&DndActionContext<'_>) -> DndAction + 'static>>,
+    token: CompositorToken<R>,
+    config: Rc<DataDeviceConfig>,
+    log: ::slog::Logger,
+) -> wl_data_device::WlDataDevice
+where
+    C: FnMut(DataDeviceEvent) + 'static,
+    R: Role<DnDIconRole> + 'static,
+{
+    use self::wl_data_device::Request;
+    let dd_data = DataDeviceData {
+        callback: callback.clone(),
+        action_choice,
+        config: config.clone(),
+        sent_selection: RefCell::new(None),
+        selection_offers: RefCell::new(Vec::new()),
+    };
+    dd.quick_assign(move |dd, req, _| {
+        // a misbehaving client may keep using a data device it released, which must not
+        // affect the state of the seat anymore
+        let known = seat
+            .user_data()
+            .get::<SeatDataCell>()
+            .map(|seat_data| seat_data.borrow().known_devices.contains(&dd))
+            .unwrap_or(false);
+        if !known {
+            debug!(log, "Ignoring a request on a released data device.");
+            return;
+        }
+        match req {
+            Request::StartDrag {
+                source,
+                origin,
+                icon,
+                serial,
+            } => {
+                let serial = Serial::from(serial);
+                // the drag'n'drop follows the pointer or the touch point owning the implicit grab
+                let pointer = seat.get_pointer().filter(|pointer| pointer.has_grab(serial));
+                let touch = seat.get_touch().filter(|touch| touch.has_grab(serial));
+                if pointer.is_some() || touch.is_some() {
+                    if let Some(ref icon) = icon {
+                        if token.give_role::<DnDIconRole>(icon).is_err() {
+                            dd.as_ref().post_error(
+                                wl_data_device::Error::Role as u32,
+                                "Given surface already has an other role".into(),
+                            );
+                            return;
+                        }
+                    }
+                    // The StartDrag is in response to an implicit grab, all is good
+                    (&mut *callback.borrow_mut())(DataDeviceEvent::DnDStarted {
+                        source: source.clone(),
+                        icon: icon.clone(),
+                        origin: origin.clone(),
+                        serial,
+                    });
+                    let mut active_dnd = ActiveDnD {
+                        source: source.clone(),
+                        origin: origin.clone(),
+                        icon: icon.clone(),
+                        icon_hotspot: (0, 0),
+                        location: (0.0, 0.0),
+                        touch: false,
+                    };
+                    if let Some(pointer) = pointer {
+                        let start_data = pointer.grab_start_data().unwrap();
+                        active_dnd.location = start_data.location;
+                        pointer.set_grab(
+                            dnd_grab::DnDGrab::new(
+                                start_data,
+                                source,
+                                origin,
+                                seat.clone(),
+                                icon,
+                                token,
+                                callback.clone(),
+                            ),
+                            serial,
+                        );
+                    } else if let Some(touch) = touch {
+                        let start_data = touch.grab_start_data().unwrap();
+                        active_dnd.location = start_data.location;
+                        active_dnd.touch = true;
+                        touch.set_grab(
+                            dnd_grab::DnDGrab::new(
+                                start_data,
+                                source,
+                                origin,
+                                seat.clone(),
+                                icon,
+                                token,
+                                callback.clone(),
+                            ),
+                            serial,
+                        );
+                    }
+                    // set after the grab, as replacing a previous drag'n'drop grab clears it
+                    seat.user_data()
+                        .get::<SeatDataCell>()
+                        .unwrap()
+                        .borrow_mut()
+                        .current_dnd = Some(active_dnd);
+                    return;
+                }
+                debug!(log, "denying drag from client without implicit grab");
+            }
+            Request::SetSelection { source, serial } => {
+                let serial = Serial::from(serial);
+                if let Some(keyboard) = seat.get_keyboard() {
+                    if dd
+                        .as_ref()
+                        .client()
+                        .as_ref()
+                        .map(|c| keyboard.has_focus(c))
+                        .unwrap_or(false)
+                    {
+                        if config.strict_selection_serial && !selection_serial_valid(&seat, serial) {
+                            debug!(log, "denying setting selection with an invalid serial");
+                            if let Some(source) = source {
+                                source.cancelled();
+                            }
+                            return;
+                        }
+                        let offers_nothing = source
+                            .as_ref()
+                            .map(|source| {
+                                with_source_metadata(source, |meta| meta.mime_types.is_empty())
+                                    .unwrap_or(true)
+                            })
+                            .unwrap_or(false);
+                        if offers_nothing {
+                            // clients could not paste anything from this source
+                            debug!(
+                                log,
+                                "denying setting a source without any mime type as the selection"
+                            );
+                            if let Some(source) = source {
+                                source.cancelled();
+                            }
+                            return;
+                        }
+                        let seat_data = seat.user_data().get::<SeatDataCell>().unwrap();
+                        (&mut *callback.borrow_mut())(DataDeviceEvent::NewSelection(source.clone()));
+                        // The client has kbd focus, it can set the selection
+                        let mut seat_data = seat_data.borrow_mut();
+                        seat_data
+                            .set_selection(source.clone().map(Selection::Client).unwrap_or(Selection::Empty));
+                        if let (Some(period), Some(source)) = (config.selection_grace_period, source) {
+                            seat_data.selection_cache = Some(SelectionCache::start(&source, period, &log));
+                        }
+                        return;
+                    }
+                }
+                debug!(log, "denying setting selection by a non-focused client");
+            }
+            Request::Release => {
+                // Clean up the known devices
+                seat.user_data()
+                    .get::<SeatDataCell>()
+                    .unwrap()
+                    .borrow_mut()
+                    .known_devices
+                    .retain(|ndd| ndd.as_ref().is_alive() && (!ndd.as_ref().equals(&dd.as_ref())))
+            }
+            _ => unreachable!(),
+        }
+    });
+    dd.as_ref().user_data().set(|| dd_data);
+
+    dd.deref().clone()
+}
+
+// Whether a client may set a selection with this serial: it must be the one of a recent key
+// or button event sent to the focused client
+//
+// This is the validation used by both the clipboard and the primary selection.
+pub(crate) fn selection_serial_valid(seat: &Seat, serial: Serial) -> bool {
+    seat.get_keyboard()
+        .map(|keyboard| keyboard.has_focus_serial(serial))
+        .unwrap_or(false)
+        || seat
+            .get_pointer()
+            .map(|pointer| pointer.has_focus_serial(serial))
+            .unwrap_or(false)
+}
+
+// Drag'n'drop actions are meaningless for a selection, and advertising them on its offer
+// would be a protocol error
+fn clamp_selection_actions(metadata: &mut SourceMetadata, log: &::slog::Logger) {
+    if !metadata.dnd_action.is_empty() {
+        debug!(log, "Clearing the drag'n'drop actions of a selection");
+        metadata.dnd_action = DndAction::empty();
+    }
+}
+
+/// A simple action chooser for DnD negociation
+///
+/// If the preferred action is available, it'll pick it. Otherwise, it'll pick the first
+/// available in the following order: Ask, Copy, Move.
+pub fn default_action_chooser(available: DndAction, preferred: DndAction) -> DndAction {
+    // if the preferred action is valid (a single action) and in the available actions, use it
+    // otherwise, follow a fallback stategy
+    if [DndAction::Move, DndAction::Copy, DndAction::Ask].contains(&preferred)
+        && available.contains(preferred)
+    {
+        preferred
+    } else {
+        first_action(available)
+    }
+}
+
+/// An action chooser for DnD negociation also honoring several preferred actions
+///
+/// This behaves like [`default_action_chooser`], except when `preferred` contains several
+/// actions: the available ones among them are considered first, in the following order:
+/// Ask, Copy, Move. If none of them is available, the first available action is picked in
+/// the same order.
+pub fn strict_action_chooser(available: DndAction, preferred: DndAction) -> DndAction {
+    match first_action(available & preferred) {
+        action if action.is_empty() => first_action(available),
+        action => action,
+    }
+}
+
+// The first of these actions in the order Ask, Copy, Move, or none if there is none
+fn first_action(actions: DndAction) -> DndAction {
+    [DndAction::Ask, DndAction::Copy, DndAction::Move]
+        .iter()
+        .cloned()
+        .find(|&action| actions.contains(action))
+        .unwrap_or_else(DndAction::empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        fs::File,
+        io::{self, Write},
+        os::unix::{io::FromRawFd, net::UnixStream},
+    };
+
+    use wayland_server::protocol::wl_pointer;
+
+    use crate::backend::input::KeyState;
+
+    use super::test_harness::{
+        create_client, create_seat, offers_of, read_messages, received_events, received_offers,
+        send_int_request, send_request, string_arg, HarnessRoles,
+    };
+
+    // Create a data device for the seat on behalf of the client
+    fn create_data_device(
+        seat: &Seat,
+        token: CompositorToken<HarnessRoles>,
+        client: &Client,
+        version: u32,
+    ) -> wl_data_device::WlDataDevice {
+        create_data_device_with_callback(seat, token, client, version, |_| {})
+    }
+
+    // Create a data device for the seat on behalf of the client, with a compositor callback
+    fn create_data_device_with_callback<C: FnMut(DataDeviceEvent) + 'static>(
+        seat: &Seat,
+        token: CompositorToken<HarnessRoles>,
+        client: &Client,
+        version: u32,
+        callback: C,
+    ) -> wl_data_device::WlDataDevice {
+        let dd = client
+            .create_resource::<wl_data_device::WlDataDevice>(version)
+            .unwrap();
+        let dd = implement_data_device(
+            dd,
+            seat.clone(),
+            Rc::new(RefCell::new(callback)),
+            Rc::new(RefCell::new(|context: &DndActionContext<'_>| {
+                default_action_chooser(context.available, context.preferred)
+            })),
+            token,
+            Rc::new(DataDeviceConfig::default()),
+            ::slog::Logger::root(::slog::Discard, o!()),
+        );
+        let seat_data = seat_data(seat);
+        let mut seat_data = seat_data.borrow_mut();
+        seat_data.known_devices.push(dd.clone());
+        seat_data.device_created(&dd);
+        dd
+    }
+
+    // Create a data source on behalf of the client
+    fn create_data_source(client: &Client, mime_types: &[&str]) -> wl_data_source::WlDataSource {
+        let source = client.create_resource::<wl_data_source::WlDataSource>(3).unwrap();
+        let source = self::data_source::implement_data_source(
+            source,
+            DataDeviceConfig::default().max_mime_types,
+            |_| {},
+            ::slog::Logger::root(::slog::Discard, o!()),
+        );
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .mime_types = strings(mime_types);
+        source
+    }
+
+    // Read the events sent to a client, along with the fds they carry
+    fn received_events_with_fds(
+        display: &mut Display,
+        socket: &mut UnixStream,
+    ) -> (Vec<(u32, u16)>, Vec<RawFd>) {
+        let (messages, fds) = read_messages(display, socket);
+        let events = messages
+            .into_iter()
+            .map(|message| (message.sender, message.opcode))
+            .collect();
+        (events, fds)
+    }
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn selection_mime_availability() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        assert!(!selection_has_mime(&seat, "text/plain"));
+
+        set_data_device_selection(&seat, strings(&["text/html", "text/plain", "UTF8_STRING"]));
+        assert!(selection_has_mime(&seat, "text/plain"));
+        assert!(selection_has_mime(&seat, "UTF8_STRING"));
+        assert!(!selection_has_mime(&seat, "image/png"));
+
+        clear_data_device_selection(&seat);
+        assert!(!selection_has_mime(&seat, "text/plain"));
+    }
+
+    #[test]
+    fn strict_selection_serial_of_key_events() {
+        let mut display = Display::new();
+        let (mut seat, _) = create_seat(&mut display);
+        let keyboard = seat.add_keyboard(Default::default(), 200, 25, |_, _| {}).unwrap();
+        let (client, _socket) = create_client(&mut display);
+        let surface = client.create_resource::<wl_surface::WlSurface>(4).unwrap();
+        keyboard.set_focus(Some(&*surface), Serial::from(10));
+        keyboard.input(30, KeyState::Pressed, Serial::from(11), 0, |_, _| true);
+        keyboard.input(30, KeyState::Released, Serial::from(13), 1, |_, _| true);
+
+        // the strict mode only accepts the serials of the key events sent to the focused client
+        for &serial in &[3, 10, 12, 14] {
+            assert!(!keyboard.has_focus_serial(Serial::from(serial)));
+        }
+        assert!(keyboard.has_focus_serial(Serial::from(11)));
+        assert!(keyboard.has_focus_serial(Serial::from(13)));
+
+        // and forgets them once the focus moves
+        keyboard.set_focus(None, Serial::from(15));
+        assert!(!keyboard.has_focus_serial(Serial::from(13)));
+    }
+
+    #[test]
+    fn data_device_version() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, _socket) = create_client(&mut display);
+        assert_eq!(client_data_device_version(&seat, &client), None);
+
+        create_data_device(&seat, token, &client, 2);
+        assert_eq!(client_data_device_version(&seat, &client), Some(2));
+
+        // devices of other clients are not considered
+        let (other, _other_socket) = create_client(&mut display);
+        assert_eq!(client_data_device_version(&seat, &other), None);
+        create_data_device(&seat, token, &other, 3);
+        assert_eq!(client_data_device_version(&seat, &other), Some(3));
+        assert_eq!(client_data_device_version(&seat, &client), Some(2));
+    }
+
+    #[test]
+    fn take_selection_cancels_client_source() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+
+        take_selection(
+            &seat,
+            SourceMetadata {
+                mime_types: strings(&["text/plain"]),
+                dnd_action: DndAction::empty(),
+            },
+        );
+
+        // wl_data_source.cancelled is the third event of the interface
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(source.as_ref().id(), 2)));
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain"]));
+    }
+
+    #[test]
+    fn take_selection_without_valid_mime_type() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+        let _ = received_events(&mut display, &mut socket);
+
+        // nothing is left once the mime types are normalized
+        take_selection(
+            &seat,
+            SourceMetadata {
+                mime_types: strings(&["", ""]),
+                dnd_action: DndAction::empty(),
+            },
+        );
+
+        // the client keeps its selection
+        // (wl_data_source.cancelled is the third event of the interface)
+        let events = received_events(&mut display, &mut socket);
+        assert!(!events.contains(&(source.as_ref().id(), 2)));
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain"]));
+    }
+
+    #[test]
+    fn dnd_over_empty_region() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let _ = received_events(&mut display, &mut socket);
+
+        // the pointer leaves the surface to a region without any surface
+        pointer.motion((-500.0, 10.0), None, Serial::from(3), 1);
+        pointer.motion((-510.0, 20.0), None, Serial::from(4), 2);
+
+        // wl_data_device.leave is the third event of the interface, and motion the fourth
+        let events = received_events(&mut display, &mut socket);
+        assert_eq!(events, vec![(device.as_ref().id(), 2)]);
+    }
+
+    #[test]
+    fn dnd_enter_and_motion() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+
+        // the target is given an offer when the pointer enters its surface
+        // (wl_data_device.data_offer is the first event of the interface, and enter the second)
+        pointer.motion(
+            (10.0, 10.0),
+            Some((surface.clone(), (0.0, 0.0))),
+            Serial::from(2),
+            0,
+        );
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 1)));
+
+        // further moves over the surface are streamed as motion, the fourth event of the interface
+        pointer.motion((12.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(3), 1);
+        let events = received_events(&mut display, &mut socket);
+        assert_eq!(events, vec![(device.as_ref().id(), 3)]);
+    }
+
+    #[test]
+    fn replaced_dnd_grab_is_cancelled() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let cancelled = Rc::new(::std::cell::Cell::new(false));
+        let cancelled2 = cancelled.clone();
+        let callback: Rc<RefCell<dyn FnMut(DataDeviceEvent)>> = Rc::new(RefCell::new(move |event| {
+            if let DataDeviceEvent::DnDCancelled = event {
+                cancelled2.set(true);
+            }
+        }));
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                callback,
+            ),
+            Serial::from(1),
+        );
+        // drag over a surface of the client
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        assert!(!cancelled.get());
+
+        // an other part of the compositor takes over the pointer
+        pointer.unset_grab();
+
+        assert!(cancelled.get());
+        let events = received_events(&mut display, &mut socket);
+        // wl_data_device.leave and wl_data_source.cancelled are both the third event
+        // of their interface
+        assert!(events.contains(&(device.as_ref().id(), 2)));
+        assert!(events.contains(&(source.as_ref().id(), 2)));
+    }
+
+    #[test]
+    fn dnd_cancelled_from_running_callback() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, _socket) = create_client(&mut display);
+        create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let grabbed_on_cancel = Rc::new(Cell::new(None));
+        let grabbed_on_cancel2 = grabbed_on_cancel.clone();
+        let pointer2 = pointer.clone();
+        let callback: Rc<RefCell<dyn FnMut(DataDeviceEvent)>> = Rc::new(RefCell::new(move |event| {
+            if let DataDeviceEvent::DnDCancelled = event {
+                // the pointer is released by then
+                grabbed_on_cancel2.set(Some(pointer2.is_grabbed()));
+            }
+        }));
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                callback.clone(),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+
+        // the compositor removes the grab from its callback
+        let running = callback.borrow_mut();
+        pointer.unset_grab();
+        assert_eq!(grabbed_on_cancel.get(), None);
+        ::std::mem::drop(running);
+
+        // the cancellation is delivered once the data device state is used again
+        assert!(selection_mime_types(&seat).is_empty());
+        assert_eq!(grabbed_on_cancel.get(), Some(false));
+    }
+
+    #[test]
+    fn touch_dnd() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let touch = seat.add_touch();
+        let (client, mut socket) = create_client(&mut display);
+        // without action negotiation, accepting a mime type is enough for the drop
+        let device = create_data_device(&seat, token, &client, 2);
+        let source = create_data_source(&client, &["text/plain"]);
+        let origin = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let target = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        touch.down(
+            0,
+            (5.0, 5.0),
+            Some((origin.clone(), (0.0, 0.0))),
+            Serial::from(1),
+            0,
+        );
+
+        // only the serial of the touch-down grab starts a drag'n'drop
+        // (wl_data_device.start_drag is the first request of the interface)
+        let args = |serial: u32| [source.as_ref().id(), origin.as_ref().id(), 0, serial];
+        send_int_request(&mut display, &mut socket, device.as_ref().id(), 0, &args(2));
+        assert!(seat_data(&seat).borrow().current_dnd.is_none());
+        send_int_request(&mut display, &mut socket, device.as_ref().id(), 0, &args(1));
+        assert!(seat_data(&seat).borrow().current_dnd.is_some());
+
+        // the drag'n'drop follows the touch point
+        touch.motion(0, (20.0, 20.0), Some((target, (10.0, 10.0))), Serial::from(3), 1);
+        let offer_id = received_events(&mut display, &mut socket)
+            .into_iter()
+            .map(|(id, _)| id)
+            .find(|&id| id >= 0xff00_0000 && id != device.as_ref().id() && id != source.as_ref().id())
+            .unwrap();
+        // (wl_data_offer.accept is the first request of the interface)
+        let mut accept = vec![3];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+
+        // the data is dropped once the touch point goes up
+        touch.up(0, Serial::from(4), 2);
+        assert!(!touch.is_grabbed());
+        assert!(seat_data(&seat).borrow().current_dnd.is_none());
+        // wl_data_device.drop is the fifth event of the interface
+        assert!(received_events(&mut display, &mut socket).contains(&(device.as_ref().id(), 4)));
+    }
+
+    #[test]
+    fn dnd_drop_events_after_release() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, _socket) = create_client(&mut display);
+        create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let grabbed_on_cancel = Rc::new(Cell::new(None));
+        let grabbed_on_cancel2 = grabbed_on_cancel.clone();
+        let pointer2 = pointer.clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(move |event| {
+                    if let DataDeviceEvent::DnDCancelled = event {
+                        // the compositor can use the pointer from its callback
+                        grabbed_on_cancel2.set(Some(pointer2.is_grabbed()));
+                    }
+                })),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+
+        // the target never accepted the data, the drop is a cancellation
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+        assert_eq!(grabbed_on_cancel.get(), Some(false));
+    }
+
+    #[test]
+    fn cached_client_selection() {
+        let mut display = Display::new();
+        let (client, _socket) = create_client(&mut display);
+        let source = create_data_source(&client, &["text/plain", "text/html", "image/png"]);
+        let cache = SelectionCache::start(
+            &source,
+            Duration::from_secs(60),
+            &::slog::Logger::root(::slog::Discard, o!()),
+        );
+        // the client did not write anything yet
+        assert!(SelectionCache::start(
+            &source,
+            Duration::from_secs(60),
+            &::slog::Logger::root(::slog::Discard, o!())
+        )
+        .into_selection()
+        .is_none());
+
+        // simulate the client having written some of its contents
+        {
+            let mut contents = cache.contents().lock().unwrap();
+            contents.insert("text/html".into(), Arc::new(b"<i>Hi</i>".to_vec()));
+            contents.insert("text/plain".into(), Arc::new(b"Hi".to_vec()));
+        }
+        let (metadata, content) = cache.into_selection().unwrap();
+        // only the cached mime types remain, in their original order
+        assert_eq!(metadata.mime_types, strings(&["text/plain", "text/html"]));
+        assert!(!content.is_expired());
+    }
+
+    #[test]
+    fn focus_and_selection_accessors() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        // nothing was initialized for this seat yet
+        assert!(data_device_focus(&seat).is_none());
+        assert_eq!(current_selection(&seat), SelectionOwner::Empty);
+
+        let (client, _socket) = create_client(&mut display);
+        create_data_device(&seat, token, &client, 3);
+        set_data_device_focus(&seat, Some(client.clone()));
+        assert!(data_device_focus(&seat).unwrap().equals(&client));
+
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source));
+        assert_eq!(current_selection(&seat), SelectionOwner::Client);
+        copy_bytes(&seat, strings(&["text/plain"]), b"Hello".to_vec());
+        assert_eq!(current_selection(&seat), SelectionOwner::Compositor);
+    }
+
+    #[test]
+    fn read_current_selection() {
+        use std::io::Read;
+
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        assert_eq!(
+            read_selection(&seat, "text/plain").unwrap_err().kind(),
+            ::std::io::ErrorKind::NotFound
+        );
+
+        // a compositor selection is served by smithay
+        copy_bytes(&seat, strings(&["text/plain"]), b"From the compositor".to_vec());
+        let mut data = Vec::new();
+        read_selection(&seat, "text/plain")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"From the compositor");
+
+        // a client selection is requested from its source
+        // (wl_data_source.send is the second event of the interface)
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+        assert!(read_selection(&seat, "image/png").is_err());
+        let mut reader = read_selection(&seat, "text/plain").unwrap();
+        let (events, fds) = received_events_with_fds(&mut display, &mut socket);
+        assert!(events.contains(&(source.as_ref().id(), 1)));
+        assert_eq!(fds.len(), 1);
+        unsafe { File::from_raw_fd(fds[0]) }
+            .write_all(b"From the client")
+            .unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"From the client");
+    }
+
+    #[test]
+    fn selection_manager_primary() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let manager = SelectionManager::new(&seat);
+        manager.set_bytes(
+            SelectionKind::Primary,
+            strings(&["text/plain"]),
+            b"Primary".to_vec(),
+        );
+        assert_eq!(manager.get(SelectionKind::Primary), strings(&["text/plain"]));
+        assert!(manager.get(SelectionKind::Clipboard).is_empty());
+
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        assert!(manager.read(SelectionKind::Primary, "text/plain", write_fd));
+        let mut data = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"Primary");
+
+        manager.clear(SelectionKind::Primary);
+        assert!(manager.get(SelectionKind::Primary).is_empty());
+    }
+
+    #[test]
+    fn device_state_snapshot() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let snapshot = data_device_snapshot(&seat);
+        assert_eq!(snapshot.selection, SelectionOwner::Empty);
+        assert!(snapshot.devices.is_empty());
+
+        let (client, _socket) = create_client(&mut display);
+        let (other, _other_socket) = create_client(&mut display);
+        create_data_device(&seat, token, &client, 3);
+        create_data_device(&seat, token, &client, 3);
+        create_data_device(&seat, token, &other, 3);
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        set_data_device_focus(&seat, Some(other));
+
+        let snapshot = data_device_snapshot(&seat);
+        assert_eq!(snapshot.selection, SelectionOwner::Compositor);
+        assert_eq!(snapshot.selection_mime_types, strings(&["text/plain"]));
+        assert_eq!(
+            snapshot
+                .devices
+                .iter()
+                .map(|&(_, count)| count)
+                .collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        assert!(!snapshot.dnd_active);
+        assert_eq!(
+            format!("{:?}", snapshot),
+            "DataDeviceSnapshot { selection: Compositor, selection_mime_types: [\"text/plain\"], \
+             devices_per_client: [2, 1], has_focus: true, dnd_active: false }"
+        );
+    }
+
+    #[test]
+    fn selection_sent_to_new_device() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        // the client is focused before it has any data device
+        set_data_device_focus(&seat, Some(client.clone()));
+
+        // wl_data_device.data_offer and selection are the first and sixth events of the interface
+        let device = create_data_device(&seat, token, &client, 3);
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 5)));
+    }
+
+    #[test]
+    fn uninitialized_device_skipped() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        // a data device without its smithay state must not bring the compositor down
+        let device = client
+            .create_resource::<wl_data_device::WlDataDevice>(3)
+            .unwrap()
+            .deref()
+            .clone();
+        seat_data(&seat).borrow_mut().known_devices.push(device.clone());
+        set_data_device_focus(&seat, Some(client));
+        assert!(!received_events(&mut display, &mut socket).contains(&(device.as_ref().id(), 5)));
+    }
+
+    #[test]
+    fn inhibited_selection_hidden() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+        set_data_device_focus(&seat, Some(client.clone()));
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+
+        // the focused client is sent an empty selection, without any offer
+        set_data_device_focus_inhibited(&seat, true);
+        let events = received_events(&mut display, &mut socket);
+        // wl_data_device.data_offer and selection are the first and sixth events of the interface
+        assert!(!events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 5)));
+        // and can no longer read the previous offer
+        // (wl_data_offer.receive is the second request of the interface)
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer_id, 1, "text/plain", write_fd);
+        let _ = ::nix::unistd::close(write_fd);
+        let (events, fds) = received_events_with_fds(&mut display, &mut socket);
+        assert!(!events.iter().any(|&(id, _)| id == source.as_ref().id()));
+        assert!(fds.is_empty());
+        let _ = ::nix::unistd::close(read_fd);
+        // the selection itself is kept
+        assert!(selection_owner_client(&seat).is_some());
+
+        // the actual selection is advertised again once lifted
+        set_data_device_focus_inhibited(&seat, false);
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 5)));
+    }
+
+    #[test]
+    fn destroyed_selection_offer_forgotten() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        copy_bytes(&seat, strings(&["text/plain"]), b"Hello".to_vec());
+        set_data_device_focus(&seat, Some(client.clone()));
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+
+        // wl_data_offer.destroy is the third request of the interface
+        send_int_request(&mut display, &mut socket, offer_id, 2, &[]);
+        let dd_data = device.as_ref().user_data().get::<DataDeviceData>().unwrap();
+        assert!(dd_data.selection_offers.borrow().is_empty());
+        assert!(dd_data.sent_selection.borrow().is_none());
+
+        // the selection is sent again through a new offer on the next focus
+        set_data_device_focus(&seat, None);
+        set_data_device_focus(&seat, Some(client));
+        let events = received_events(&mut display, &mut socket);
+        // wl_data_device.data_offer and selection are the first and sixth events of the interface
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 5)));
+        assert!(!events.iter().any(|&(id, _)| id == offer_id));
+    }
+
+    #[test]
+    fn selection_offers_limited() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        set_data_device_focus(&seat, Some(client));
+        let limit = DataDeviceConfig::default().max_selection_offers;
+
+        // the client never destroys the offers it is sent
+        // (wl_data_device.data_offer is the first event of the interface)
+        for i in 0..limit + 8 {
+            copy_bytes(&seat, strings(&["text/plain"]), vec![i as u8]);
+        }
+        let events = received_events(&mut display, &mut socket);
+        let offers = events
+            .iter()
+            .filter(|&&event| event == (device.as_ref().id(), 0))
+            .count();
+        assert_eq!(offers, limit);
+    }
+
+    #[test]
+    fn late_offer_is_advertised() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+        set_data_device_focus(&seat, Some(client));
+        let _ = received_events(&mut display, &mut socket);
+
+        // the client offers a new mime type on its source after setting the selection
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .mime_types
+            .push("text/html".into());
+        seat_data(&seat).borrow_mut().source_updated(&source);
+
+        // wl_data_device.data_offer and selection are the first and sixth events of the
+        // interface, and wl_data_offer.offer is its first event
+        let events = received_events(&mut display, &mut socket);
+        let offer_id = events
+            .iter()
+            .filter(|&&(id, opcode)| id != device.as_ref().id() && opcode == 0)
+            .map(|&(id, _)| id)
+            .next()
+            .unwrap();
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 5)));
+        assert_eq!(events.iter().filter(|&&e| e == (offer_id, 0)).count(), 2);
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain", "text/html"]));
+    }
+
+    #[test]
+    fn source_metadata_updated_by_compositor() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        assert!(force_set_client_selection(&seat, source.clone()));
+        set_data_device_focus(&seat, Some(client.clone()));
+        let _ = received_events(&mut display, &mut socket);
+
+        let updated = with_source_metadata_mut(&source, |meta| {
+            meta.mime_types.push("text/html".into());
+            meta.mime_types.push("text/plain".into());
+        });
+        assert_eq!(updated, Ok(()));
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain", "text/html"]));
+        // wl_data_device.data_offer and selection are the first and sixth events of the interface
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 5)));
+
+        // the limit of mime types of the data device manager still applies
+        let max_mime_types = DataDeviceConfig::default().max_mime_types;
+        let updated = with_source_metadata_mut(&source, |meta| {
+            meta.mime_types
+                .extend((0..max_mime_types).map(|i| format!("text/x-test-{}", i)));
+        });
+        assert_eq!(updated, Ok(()));
+        assert_eq!(selection_mime_types(&seat).len(), max_mime_types);
+
+        // the metadata cannot be modified while it is accessed
+        let nested = with_source_metadata(&source, |_| with_source_metadata_mut(&source, |_| ()));
+        assert_eq!(nested, Ok(Err(())));
+        // sources not managed by smithay have no metadata
+        let unmanaged = client
+            .create_resource::<wl_data_source::WlDataSource>(3)
+            .unwrap()
+            .deref()
+            .clone();
+        assert_eq!(with_source_metadata_mut(&unmanaged, |_| ()), Err(()));
+    }
+
+    #[test]
+    fn dnd_offer_receive() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain", "text/html"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+
+        // the target requests the data with a mime type the source does not offer
+        // (wl_data_offer.receive is the second request of the interface)
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer_id, 1, "image/png", write_fd);
+        let _ = ::nix::unistd::close(write_fd);
+        let (events, fds) = received_events_with_fds(&mut display, &mut socket);
+        assert!(!events.iter().any(|&(id, _)| id == source.as_ref().id()));
+        assert!(fds.is_empty());
+        let _ = ::nix::unistd::close(read_fd);
+
+        // the target requests the data with a valid mime type it did not accept
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer_id, 1, "text/plain", write_fd);
+        let _ = ::nix::unistd::close(write_fd);
+        let (events, fds) = received_events_with_fds(&mut display, &mut socket);
+        assert!(!events.iter().any(|&(id, _)| id == source.as_ref().id()));
+        assert!(fds.is_empty());
+        let _ = ::nix::unistd::close(read_fd);
+
+        // the target accepts the mime type and requests the data
+        // (wl_data_offer.accept is the first request of the interface)
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer_id, 1, "text/plain", write_fd);
+        let _ = ::nix::unistd::close(write_fd);
+        // the request is forwarded to the source (wl_data_source.send is its second event)
+        let (events, fds) = received_events_with_fds(&mut display, &mut socket);
+        assert!(events.contains(&(source.as_ref().id(), 1)));
+        assert_eq!(fds.len(), 1);
+        // the source writes its data, which the target receives
+        unsafe { File::from_raw_fd(fds[0]) }
+            .write_all(b"Dragged data")
+            .unwrap();
+        let mut data = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"Dragged data");
+
+        // the target requests an offered mime type other than the one it accepted
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer_id, 1, "text/html", write_fd);
+        let _ = ::nix::unistd::close(write_fd);
+        let (events, fds) = received_events_with_fds(&mut display, &mut socket);
+        assert!(!events.iter().any(|&(id, _)| id == source.as_ref().id()));
+        assert!(fds.is_empty());
+        let _ = ::nix::unistd::close(read_fd);
+    }
+
+    // Start a compositor drag'n'drop over a surface of a client, which accepts it, returning
+    // the id of the offer the client received and the list of events your callback received
+    fn start_server_dnd_over_surface(
+        display: &mut Display,
+        seat: &mut Seat,
+        token: CompositorToken<HarnessRoles>,
+        client: &Client,
+        socket: &mut UnixStream,
+        dry_run: bool,
+    ) -> (u32, Rc<RefCell<Vec<&'static str>>>) {
+        let pointer = seat.add_pointer(token, |_| {});
+        let device = create_data_device(seat, token, client, 3);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events2 = events.clone();
+        start_server_dnd(
+            seat,
+            Serial::from(1),
+            GrabStartData {
+                focus: None,
+                button: 0x110,
+                location: (0.0, 0.0),
+            },
+            SourceMetadata {
+                mime_types: strings(&["text/plain"]),
+                dnd_action: DndAction::Copy,
+            },
+            dry_run,
+            move |event| {
+                events2.borrow_mut().push(match event {
+                    ServerDndEvent::Action(_) => "action",
+                    ServerDndEvent::Dropped { .. } => "dropped",
+                    ServerDndEvent::Cancelled => "cancelled",
+                    ServerDndEvent::Send { .. } => "send",
+                    ServerDndEvent::Finished => "finished",
+                    ServerDndEvent::DryRunDrop { .. } => "dry_run_drop",
+                })
+            },
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(display, socket, &[device.as_ref().id()])[0];
+        // the target accepts the data (wl_data_offer.accept is the first request of the interface)
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(display, socket, offer_id, 0, &accept);
+        (offer_id, events)
+    }
+
+    #[test]
+    fn finish_server_dnd_programmatically() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let (offer_id, events) =
+            start_server_dnd_over_surface(&mut display, &mut seat, token, &client, &mut socket, false);
+        // the target negotiates the copy action
+        // (wl_data_offer.set_actions is the fifth request of the interface)
+        let copy = DndAction::Copy.bits();
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+        let _ = received_events(&mut display, &mut socket);
+
+        finish_server_dnd(&seat);
+
+        assert_eq!(*events.borrow(), vec!["action", "dropped"]);
+        // wl_data_device.drop is the fifth event of the interface
+        let device_id = seat_data(&seat)
+            .borrow()
+            .known_devices
+            .iter()
+            .next()
+            .unwrap()
+            .as_ref()
+            .id();
+        assert!(received_events(&mut display, &mut socket).contains(&(device_id, 4)));
+        assert!(!seat.get_pointer().unwrap().is_grabbed());
+        // nothing happens once the drag'n'drop is over
+        cancel_server_dnd(&seat);
+        assert_eq!(*events.borrow(), vec!["action", "dropped"]);
+    }
+
+    #[test]
+    fn server_dnd_action_changes() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let (offer_id, events) =
+            start_server_dnd_over_surface(&mut display, &mut seat, token, &client, &mut socket, false);
+        let copy = DndAction::Copy.bits();
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+        // negotiating the same action again is not reported
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+        assert_eq!(*events.borrow(), vec!["action"]);
+
+        // leaving the target resets the action
+        seat.get_pointer()
+            .unwrap()
+            .motion((20.0, 20.0), None, Serial::from(3), 0);
+        assert_eq!(*events.borrow(), vec!["action", "action"]);
+        cancel_server_dnd(&seat);
+        assert_eq!(*events.borrow(), vec!["action", "action", "cancelled"]);
+    }
+
+    #[test]
+    fn server_dnd_finished_or_cancelled() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let (offer_id, events) =
+            start_server_dnd_over_surface(&mut display, &mut seat, token, &client, &mut socket, false);
+        let copy = DndAction::Copy.bits();
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+        finish_server_dnd(&seat);
+
+        // the target destroys its offer without finishing the transfer
+        // (wl_data_offer.destroy is the third request of the interface)
+        send_int_request(&mut display, &mut socket, offer_id, 2, &[]);
+        assert_eq!(*events.borrow(), vec!["action", "dropped", "cancelled"]);
+    }
+
+    #[test]
+    fn server_dnd_dropped_on_target() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let drops = Rc::new(RefCell::new(Vec::new()));
+        let drops2 = drops.clone();
+        start_dnd(
+            &seat,
+            Serial::from(1),
+            GrabStartData {
+                focus: None,
+                button: 0x110,
+                location: (0.0, 0.0),
+            },
+            SourceMetadata {
+                mime_types: strings(&["text/plain"]),
+                dnd_action: DndAction::Copy | DndAction::Move,
+            },
+            move |event| {
+                if let ServerDndEvent::Dropped {
+                    target,
+                    location,
+                    action,
+                } = event
+                {
+                    drops2.borrow_mut().push((target, location, action));
+                }
+            },
+        );
+        pointer.motion(
+            (10.0, 10.0),
+            Some((surface.clone(), (5.0, 2.0))),
+            Serial::from(2),
+            0,
+        );
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        // the target accepts the data and negotiates the move action
+        // (wl_data_offer.accept and set_actions are the first and fifth requests of the interface)
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let (copy, moving) = (DndAction::Copy.bits(), DndAction::Move.bits());
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy | moving, moving]);
+
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+        let drops = drops.borrow();
+        assert_eq!(drops.len(), 1);
+        assert!(drops[0].0.as_ref().unwrap().as_ref().equals(surface.as_ref()));
+        // the location is relative to the target surface
+        assert_eq!(drops[0].1, (5.0, 8.0));
+        assert_eq!(drops[0].2, DndAction::Move);
+    }
+
+    #[test]
+    fn cancel_server_dnd_with_handle() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let start = |seat: &Seat, events: &Rc<RefCell<Vec<ServerDndEvent>>>| {
+            let events = events.clone();
+            start_dnd(
+                seat,
+                Serial::from(1),
+                GrabStartData {
+                    focus: None,
+                    button: 0x110,
+                    location: (0.0, 0.0),
+                },
+                SourceMetadata {
+                    mime_types: strings(&["text/plain"]),
+                    dnd_action: DndAction::Copy,
+                },
+                move |event| events.borrow_mut().push(event),
+            )
+        };
+        let first_events = Rc::new(RefCell::new(Vec::new()));
+        let first = start(&seat, &first_events);
+        assert!(first.is_active());
+
+        first.cancel();
+        assert!(!first.is_active());
+        assert!(matches!(first_events.borrow()[..], [ServerDndEvent::Cancelled]));
+        assert!(!pointer.is_grabbed());
+
+        // the handle of a finished drag'n'drop does not affect the next one
+        let second_events = Rc::new(RefCell::new(Vec::new()));
+        let second = start(&seat, &second_events);
+        first.cancel();
+        assert!(second.is_active());
+        assert!(second_events.borrow().is_empty());
+        assert!(pointer.is_grabbed());
+    }
+
+    #[test]
+    fn cancel_server_dnd_programmatically() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let (_, events) =
+            start_server_dnd_over_surface(&mut display, &mut seat, token, &client, &mut socket, false);
+
+        cancel_server_dnd(&seat);
+
+        assert_eq!(*events.borrow(), vec!["cancelled"]);
+        // wl_data_device.leave is the third event of the interface
+        let device_id = seat_data(&seat)
+            .borrow()
+            .known_devices
+            .iter()
+            .next()
+            .unwrap()
+            .as_ref()
+            .id();
+        assert!(received_events(&mut display, &mut socket).contains(&(device_id, 2)));
+        assert!(!seat.get_pointer().unwrap().is_grabbed());
+    }
+
+    #[test]
+    fn cancel_server_dnd_from_callback() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let device = create_data_device(&seat, token, &client, 3);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let grabbed_on_cancel = Rc::new(Cell::new(None));
+        let grabbed_on_cancel2 = grabbed_on_cancel.clone();
+        let seat2 = seat.clone();
+        start_dnd(
+            &seat,
+            Serial::from(1),
+            GrabStartData {
+                focus: None,
+                button: 0x110,
+                location: (0.0, 0.0),
+            },
+            SourceMetadata {
+                mime_types: strings(&["text/plain"]),
+                dnd_action: DndAction::Copy,
+            },
+            move |event| match event {
+                // the compositor ends the drag'n'drop as soon as an action is negotiated
+                ServerDndEvent::Action(action) if !action.is_empty() => cancel_server_dnd(&seat2),
+                ServerDndEvent::Cancelled => {
+                    grabbed_on_cancel2.set(Some(seat2.get_pointer().unwrap().is_grabbed()));
+                }
+                _ => {}
+            },
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        // the target negotiates the copy action
+        // (wl_data_offer.set_actions is the fifth request of the interface)
+        let copy = DndAction::Copy.bits();
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+
+        // the cancellation is delivered once the callback returned, with the pointer released
+        assert_eq!(grabbed_on_cancel.get(), Some(false));
+        assert!(!pointer.is_grabbed());
+    }
+
+    #[test]
+    fn tagged_selection_changes() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, _socket) = create_client(&mut display);
+        let tags = Rc::new(RefCell::new(Vec::new()));
+        let tags2 = tags.clone();
+        set_selection_change_callback(&seat, move |change| {
+            tags2.borrow_mut().push(change.tag);
+        });
+
+        // a bridge installs a selection from its external system...
+        set_data_device_selection_tagged(&seat, strings(&["text/plain"]), 42);
+        // ...then the compositor and a client set their own
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source));
+
+        // only the selection of the bridge is tagged
+        assert_eq!(*tags.borrow(), vec![Some(42), None, None]);
+    }
+
+    #[test]
+    fn client_dnd_drop_location() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        // the target can only negotiate an action the source supports
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .dnd_action = DndAction::Copy;
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let drops = Rc::new(RefCell::new(Vec::new()));
+        let drops2 = drops.clone();
+        pointer.button(0x110, wl_pointer::ButtonState::Pressed, Serial::from(1), 0);
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                GrabStartData {
+                    focus: None,
+                    button: 0x110,
+                    location: (0.0, 0.0),
+                },
+                Some(source),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(move |event: DataDeviceEvent| {
+                    if let DataDeviceEvent::DnDDroppedOn { target, location } = event {
+                        drops2.borrow_mut().push((target, location));
+                    }
+                })),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion(
+            (10.0, 10.0),
+            Some((surface.clone(), (4.0, 4.0))),
+            Serial::from(2),
+            0,
+        );
+        pointer.motion(
+            (12.0, 15.0),
+            Some((surface.clone(), (4.0, 4.0))),
+            Serial::from(3),
+            1,
+        );
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        // the target accepts the drag'n'drop and negotiates an action before the drop
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let copy = DndAction::Copy.bits();
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(4), 2);
+
+        let drops = drops.borrow();
+        assert_eq!(drops.len(), 1);
+        assert!(drops[0].0.as_ref().equals(surface.as_ref()));
+        assert_eq!(drops[0].1, (8.0, 11.0));
+    }
+
+    #[test]
+    fn dnd_offer_receive_after_finish() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        // the target can only negotiate an action the source supports
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .dnd_action = DndAction::Copy;
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        pointer.button(0x110, wl_pointer::ButtonState::Pressed, Serial::from(1), 0);
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                GrabStartData {
+                    focus: None,
+                    button: 0x110,
+                    location: (0.0, 0.0),
+                },
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        // the target negotiates an action, the user drops, and the target finishes
+        // (wl_data_offer.set_actions and finish are the fifth and fourth requests)
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let copy = DndAction::Copy.bits();
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+        send_int_request(&mut display, &mut socket, offer_id, 3, &[]);
+        let _ = received_events(&mut display, &mut socket);
+
+        // the target then requests the data again, which is denied
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer_id, 1, "text/plain", write_fd);
+        let _ = ::nix::unistd::close(write_fd);
+        let (events, fds) = received_events_with_fds(&mut display, &mut socket);
+        assert!(!events.iter().any(|&(id, _)| id == source.as_ref().id()));
+        assert!(fds.is_empty());
+        // the fd given by the target was closed
+        let mut data = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut data)
+            .unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn restore_selection_from_history() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        seat_data(&seat).borrow_mut().history.set_capacity(3);
+        copy_bytes(&seat, strings(&["image/png"]), b"\x89PNG\r\n".to_vec());
+        copy_bytes(&seat, strings(&["text/plain"]), b"Some text".to_vec());
+        // selections provided through the callback are not recorded
+        set_data_device_selection(&seat, strings(&["text/html"]));
+        assert_eq!(
+            selection_history(&seat),
+            vec![strings(&["text/plain"]), strings(&["image/png"])]
+        );
+
+        // the restored selection becomes the most recent one
+        assert!(restore_selection(&seat, 1));
+        assert_eq!(selection_mime_types(&seat), strings(&["image/png"]));
+        assert_eq!(
+            selection_history(&seat),
+            vec![strings(&["image/png"]), strings(&["text/plain"])]
+        );
+        let mut contents = Vec::new();
+        read_selection(&seat, "image/png")
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"\x89PNG\r\n");
+        assert!(!restore_selection(&seat, 2));
+    }
+
+    #[test]
+    fn selection_if_empty() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        assert!(set_data_device_selection_if_empty(
+            &seat,
+            strings(&["text/plain"]),
+            b"Default".to_vec()
+        ));
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain"]));
+
+        // an existing selection is not replaced
+        set_data_device_selection(&seat, strings(&["text/html"]));
+        assert!(!set_data_device_selection_if_empty(
+            &seat,
+            strings(&["text/plain"]),
+            b"Default".to_vec()
+        ));
+        assert_eq!(selection_mime_types(&seat), strings(&["text/html"]));
+
+        clear_data_device_selection(&seat);
+        assert!(set_data_device_selection_if_empty(
+            &seat,
+            strings(&["text/plain"]),
+            b"Default".to_vec()
+        ));
+    }
+
+    #[test]
+    fn selection_actions_cleared() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Compositor {
+                metadata: SourceMetadata {
+                    mime_types: strings(&["text/plain"]),
+                    dnd_action: DndAction::Copy | DndAction::Move,
+                },
+                content: Rc::new(SelectionContent::Callback),
+            });
+        match seat_data(&seat).borrow().selection {
+            Selection::Compositor { ref metadata, .. } => assert!(metadata.dnd_action.is_empty()),
+            _ => panic!("the selection was not set"),
+        };
+    }
+
+    #[test]
+    fn selection_without_mime_types() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        // duplicated mime types are only advertised once
+        set_data_device_selection(&seat, strings(&["text/plain", "text/html", "text/plain"]));
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain", "text/html"]));
+        // and a selection without any is ignored
+        set_data_device_selection(&seat, Vec::new());
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain", "text/html"]));
+
+        // the same goes for a client source that never offered a mime type
+        let keyboard = seat.add_keyboard(Default::default(), 200, 25, |_, _| {}).unwrap();
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &[]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        keyboard.set_focus(Some(&surface), Serial::from(1));
+        let _ = received_events(&mut display, &mut socket);
+        send_int_request(
+            &mut display,
+            &mut socket,
+            device.as_ref().id(),
+            1,
+            &[source.as_ref().id(), 1],
+        );
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain", "text/html"]));
+        // (wl_data_source.cancelled is the third event of the interface)
+        assert!(received_events(&mut display, &mut socket).contains(&(source.as_ref().id(), 2)));
+    }
+
+    #[test]
+    fn data_device_focus_follows_keyboard() {
+        let mut display = Display::new();
+        let (mut seat, _) = create_seat(&mut display);
+        let keyboard = seat.add_keyboard(Default::default(), 200, 25, |_, _| {}).unwrap();
+        let (client, _socket) = create_client(&mut display);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        keyboard.set_focus(Some(&surface), Serial::from(1));
+        assert!(data_device_focus(&seat).is_none());
+
+        // enabling it picks the current keyboard focus up
+        set_data_device_follows_keyboard_focus(&seat, true);
+        assert!(data_device_focus(&seat)
+            .map(|c| c.equals(&client))
+            .unwrap_or(false));
+        keyboard.set_focus(None, Serial::from(2));
+        assert!(data_device_focus(&seat).is_none());
+
+        // once disabled, the compositor drives the focus
+        set_data_device_follows_keyboard_focus(&seat, false);
+        keyboard.set_focus(Some(&surface), Serial::from(3));
+        assert!(data_device_focus(&seat).is_none());
+        set_data_device_focus(&seat, Some(client.clone()));
+        assert!(data_device_focus(&seat)
+            .map(|c| c.equals(&client))
+            .unwrap_or(false));
+    }
+
+    #[test]
+    fn strict_selection_serial_checked() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let keyboard = seat.add_keyboard(Default::default(), 200, 25, |_, _| {}).unwrap();
+        let (client, mut socket) = create_client(&mut display);
+        let device = implement_data_device(
+            client.create_resource::<wl_data_device::WlDataDevice>(3).unwrap(),
+            seat.clone(),
+            Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            Rc::new(RefCell::new(|context: &DndActionContext<'_>| {
+                default_action_chooser(context.available, context.preferred)
+            })),
+            token,
+            Rc::new(DataDeviceConfig {
+                strict_selection_serial: true,
+                ..Default::default()
+            }),
+            ::slog::Logger::root(::slog::Discard, o!()),
+        );
+        seat_data(&seat).borrow_mut().known_devices.push(device.clone());
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        keyboard.set_focus(Some(&surface), Serial::from(10));
+        keyboard.input(30, KeyState::Pressed, Serial::from(11), 0, |_, _| true);
+        keyboard.input(30, KeyState::Released, Serial::from(13), 1, |_, _| true);
+        let _ = received_events(&mut display, &mut socket);
+
+        // serials the client was never sent are denied, and the sources cancelled, even
+        // between the ones of its key events
+        // (wl_data_device.set_selection is the second request of the interface)
+        for &serial in &[3, 12] {
+            let stale = create_data_source(&client, &["text/plain"]);
+            send_int_request(
+                &mut display,
+                &mut socket,
+                device.as_ref().id(),
+                1,
+                &[stale.as_ref().id(), serial],
+            );
+            assert!(selection_owner_client(&seat).is_none());
+            // (wl_data_source.cancelled is the third event of the interface)
+            assert!(received_events(&mut display, &mut socket).contains(&(stale.as_ref().id(), 2)));
+        }
+
+        // the serial of a key event sent to the focused client is accepted
+        let source = create_data_source(&client, &["text/plain"]);
+        send_int_request(
+            &mut display,
+            &mut socket,
+            device.as_ref().id(),
+            1,
+            &[source.as_ref().id(), 13],
+        );
+        assert!(selection_owner_client(&seat).unwrap().equals(&client));
+    }
+
+    #[test]
+    fn strict_selection_serial_of_button() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = implement_data_device(
+            client.create_resource::<wl_data_device::WlDataDevice>(3).unwrap(),
+            seat.clone(),
+            Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            Rc::new(RefCell::new(|context: &DndActionContext<'_>| {
+                default_action_chooser(context.available, context.preferred)
+            })),
+            token,
+            Rc::new(DataDeviceConfig {
+                strict_selection_serial: true,
+                ..Default::default()
+            }),
+            ::slog::Logger::root(::slog::Discard, o!()),
+        );
+        seat_data(&seat).borrow_mut().known_devices.push(device.clone());
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(1), 0);
+        pointer.button(0x110, wl_pointer::ButtonState::Pressed, Serial::from(2), 1);
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 2);
+        let _ = received_events(&mut display, &mut socket);
+
+        // the serial of the pointer entering the surface is not the one of a button event
+        // (wl_data_device.set_selection is the second request of the interface)
+        let stale = create_data_source(&client, &["text/plain"]);
+        send_int_request(
+            &mut display,
+            &mut socket,
+            device.as_ref().id(),
+            1,
+            &[stale.as_ref().id(), 1],
+        );
+        assert!(selection_owner_client(&seat).is_none());
+
+        // the serial of a button event sent to the focused client is accepted
+        let source = create_data_source(&client, &["text/plain"]);
+        send_int_request(
+            &mut display,
+            &mut socket,
+            device.as_ref().id(),
+            1,
+            &[source.as_ref().id(), 3],
+        );
+        assert!(selection_owner_client(&seat).unwrap().equals(&client));
+    }
+
+    #[test]
+    fn selection_with_disconnected_client() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, _socket) = create_client(&mut display);
+        let (other, mut other_socket) = create_client(&mut display);
+        create_data_device(&seat, token, &client, 3);
+        let other_device = create_data_device(&seat, token, &other, 3);
+
+        // the focused client disconnects while the selection is sent to it
+        set_data_device_focus(&seat, Some(client.clone()));
+        client.kill();
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+
+        // other clients still receive the selection
+        set_data_device_focus(&seat, Some(other));
+        // wl_data_device.selection is the sixth event of the interface
+        let events = received_events(&mut display, &mut other_socket);
+        assert!(events.contains(&(other_device.as_ref().id(), 5)));
+    }
+
+    // Drop a client drag'n'drop on a target without any action negotiated, returning
+    // the events received by the client along with the device and source ids
+    fn dnd_drop_without_action(version: u32) -> (Vec<(u32, u16)>, u32, u32) {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, version);
+        let source = client
+            .create_resource::<wl_data_source::WlDataSource>(version)
+            .unwrap();
+        let source = self::data_source::implement_data_source(
+            source,
+            DataDeviceConfig::default().max_mime_types,
+            |_| {},
+            ::slog::Logger::root(::slog::Discard, o!()),
+        );
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .mime_types
+            .push("text/plain".into());
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        // the target accepts the data, but does not negotiate any action
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let _ = received_events(&mut display, &mut socket);
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+        let events = received_events(&mut display, &mut socket);
+        (events, device.as_ref().id(), source.as_ref().id())
+    }
+
+    #[test]
+    fn dnd_drop_without_action_v3() {
+        let (events, device_id, source_id) = dnd_drop_without_action(3);
+        // wl_data_device.drop is the fifth event of the interface, wl_data_source.cancelled
+        // the third one and wl_data_source.dnd_drop_performed the fourth one
+        assert!(!events.contains(&(device_id, 4)));
+        assert!(events.contains(&(source_id, 2)));
+        assert!(!events.contains(&(source_id, 3)));
+    }
+
+    #[test]
+    fn dnd_drop_without_action_v2() {
+        // actions do not exist before version 3, the drop proceeds
+        let (events, device_id, source_id) = dnd_drop_without_action(2);
+        assert!(events.contains(&(device_id, 4)));
+        assert!(!events.contains(&(source_id, 2)));
+    }
+
+    #[test]
+    fn dnd_never_accepted() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .dnd_action = DndAction::Copy;
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let cancelled = Rc::new(::std::cell::Cell::new(false));
+        let cancelled2 = cancelled.clone();
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                GrabStartData {
+                    focus: None,
+                    button: 0x110,
+                    location: (0.0, 0.0),
+                },
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(move |event: DataDeviceEvent| {
+                    if let DataDeviceEvent::DnDCancelled = event {
+                        cancelled2.set(true);
+                    }
+                })),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        // the target negotiates an action, but never accepts any mime type
+        let copy = DndAction::Copy.bits();
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+
+        // wl_data_device.drop is the fifth event of the interface, and
+        // wl_data_source.cancelled the third one
+        let events = received_events(&mut display, &mut socket);
+        assert!(!events.contains(&(device.as_ref().id(), 4)));
+        assert!(events.contains(&(source.as_ref().id(), 2)));
+        assert!(cancelled.get());
+    }
+
+    #[test]
+    fn dnd_target_destroyed() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let create_surface = || {
+            let surface = client.create_resource::<wl_surface::WlSurface>(4).unwrap();
+            surface.quick_assign(|_, _, _| {});
+            surface.deref().clone()
+        };
+        let target = create_surface();
+        let other = create_surface();
+        let cancelled = Rc::new(::std::cell::Cell::new(false));
+        let cancelled2 = cancelled.clone();
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                GrabStartData {
+                    focus: None,
+                    button: 0x110,
+                    location: (0.0, 0.0),
+                },
+                Some(source.clone()),
+                other.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(move |event: DataDeviceEvent| {
+                    if let DataDeviceEvent::DnDCancelled = event {
+                        cancelled2.set(true);
+                    }
+                })),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion(
+            (10.0, 10.0),
+            Some((target.clone(), (0.0, 0.0))),
+            Serial::from(2),
+            0,
+        );
+        let _ = received_events(&mut display, &mut socket);
+
+        // the client destroys the target in the middle of the drag'n'drop
+        // (wl_surface.destroy is the first request of the interface)
+        send_int_request(&mut display, &mut socket, target.as_ref().id(), 0, &[]);
+        assert!(!target.as_ref().is_alive());
+
+        // the grab goes on over the other surfaces
+        // (wl_data_device.enter and leave are the second and third events of the interface)
+        pointer.motion((20.0, 20.0), Some((other, (0.0, 0.0))), Serial::from(3), 1);
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 2)));
+        assert!(events.contains(&(device.as_ref().id(), 1)));
+        assert!(pointer.is_grabbed());
+
+        // a drop over empty space cancels the drag'n'drop
+        // (wl_data_device.drop is the fifth event of the interface, and
+        // wl_data_source.cancelled the third one)
+        pointer.motion((30.0, 30.0), None, Serial::from(4), 2);
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(5), 3);
+        let events = received_events(&mut display, &mut socket);
+        assert!(!events.contains(&(device.as_ref().id(), 4)));
+        assert!(events.contains(&(source.as_ref().id(), 2)));
+        assert!(cancelled.get());
+        assert!(!pointer.is_grabbed());
+    }
+
+    #[test]
+    fn dnd_action_negotiation() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .dnd_action = DndAction::Copy | DndAction::Move;
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+
+        // the target accepts the data and prefers moving it
+        // (wl_data_offer.accept and set_actions are the first and fifth requests of the interface)
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let (copy, moving) = (DndAction::Copy.bits(), DndAction::Move.bits());
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy | moving, moving]);
+        // wl_data_offer.action is the third event of the interface, wl_data_source.action the sixth
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(offer_id, 2)));
+        assert!(events.contains(&(source.as_ref().id(), 5)));
+
+        // the source is only told the transfer is over once the target finishes
+        // (wl_data_source.dnd_finished is the fifth event of the interface)
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(source.as_ref().id(), 3)));
+        assert!(!events.contains(&(source.as_ref().id(), 4)));
+        send_int_request(&mut display, &mut socket, offer_id, 3, &[]);
+        assert!(received_events(&mut display, &mut socket).contains(&(source.as_ref().id(), 4)));
+    }
+
+    #[test]
+    fn choose_action_among_preferred() {
+        let all = DndAction::Copy | DndAction::Move | DndAction::Ask;
+        // the default chooser only honors a single preferred action
+        assert_eq!(
+            default_action_chooser(DndAction::Copy | DndAction::Move, DndAction::Move),
+            DndAction::Move
+        );
+        assert_eq!(
+            default_action_chooser(DndAction::Move, DndAction::Copy | DndAction::Move),
+            DndAction::Move
+        );
+        assert_eq!(
+            default_action_chooser(all, DndAction::Copy | DndAction::Move),
+            DndAction::Ask
+        );
+
+        // the strict chooser picks among the available preferred actions first
+        assert_eq!(
+            strict_action_chooser(all, DndAction::Copy | DndAction::Move),
+            DndAction::Copy
+        );
+        assert_eq!(
+            strict_action_chooser(
+                DndAction::Ask | DndAction::Move,
+                DndAction::Copy | DndAction::Move
+            ),
+            DndAction::Move
+        );
+        assert_eq!(strict_action_chooser(all, DndAction::Move), DndAction::Move);
+        // and falls back to the available actions
+        assert_eq!(
+            strict_action_chooser(DndAction::Move | DndAction::Ask, DndAction::Copy),
+            DndAction::Ask
+        );
+        assert_eq!(strict_action_chooser(DndAction::empty(), all), DndAction::empty());
+    }
+
+    #[test]
+    fn dnd_action_chooser_context() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let contexts = Rc::new(RefCell::new(Vec::new()));
+        let chooser_contexts = contexts.clone();
+        set_dnd_action_chooser(&seat, move |context| {
+            let same_client = match (&context.source_client, &context.target_client) {
+                (Some(source), Some(target)) => source.equals(target),
+                _ => false,
+            };
+            chooser_contexts
+                .borrow_mut()
+                .push((context.available, same_client, context.mime_types.to_vec()));
+            // only copy, whatever the target prefers
+            context.available & DndAction::Copy
+        });
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .dnd_action = DndAction::Copy | DndAction::Move;
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let (copy, moving) = (DndAction::Copy.bits(), DndAction::Move.bits());
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy | moving, moving]);
+        let _ = received_events(&mut display, &mut socket);
+        assert_eq!(
+            *contexts.borrow(),
+            vec![(
+                DndAction::Copy | DndAction::Move,
+                true,
+                vec!["text/plain".to_string()]
+            )]
+        );
+
+        // the action chosen by the seat chooser allows the drop
+        // (wl_data_source.dnd_drop_performed is the fourth event of the interface)
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+        assert!(received_events(&mut display, &mut socket).contains(&(source.as_ref().id(), 3)));
+    }
+
+    fn dnd_ask_action(resolve: bool) -> (Vec<(u32, u16)>, u32, u32) {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let resolvers = Rc::new(RefCell::new(Vec::new()));
+        let hook_resolvers = resolvers.clone();
+        set_ask_action_callback(&seat, move |resolver| hook_resolvers.borrow_mut().push(resolver));
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .dnd_action = DndAction::Copy | DndAction::Move | DndAction::Ask;
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+
+        // the target accepts the data and lets the user choose the action
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let all = (DndAction::Copy | DndAction::Move | DndAction::Ask).bits();
+        send_int_request(
+            &mut display,
+            &mut socket,
+            offer_id,
+            4,
+            &[all, DndAction::Ask.bits()],
+        );
+        let _ = received_events(&mut display, &mut socket);
+        let resolver = resolvers.borrow_mut().pop().unwrap();
+        assert_eq!(
+            resolver.available_actions(),
+            DndAction::Copy | DndAction::Move | DndAction::Ask
+        );
+        if resolve {
+            resolver.choose(DndAction::Copy);
+        }
+
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+        let events = received_events(&mut display, &mut socket);
+        (events, offer_id, source.as_ref().id())
+    }
+
+    #[test]
+    fn dnd_ask_action_resolved() {
+        let (events, offer_id, source_id) = dnd_ask_action(true);
+        // the chosen action is advertised to both sides before the drop is performed
+        // (wl_data_offer.action is the third event of the interface, wl_data_source.action the
+        // sixth and wl_data_source.dnd_drop_performed the fourth)
+        assert!(events.contains(&(offer_id, 2)));
+        assert!(events.contains(&(source_id, 5)));
+        assert!(events.contains(&(source_id, 3)));
+    }
+
+    #[test]
+    fn dnd_ask_action_unresolved() {
+        // a drop before the compositor resolved the action is cancelled
+        // (wl_data_source.cancelled is the third event of the interface)
+        let (events, _, source_id) = dnd_ask_action(false);
+        assert!(events.contains(&(source_id, 2)));
+        assert!(!events.contains(&(source_id, 3)));
+    }
+
+    #[test]
+    fn dnd_source_destroyed() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion(
+            (10.0, 10.0),
+            Some((surface.clone(), (0.0, 0.0))),
+            Serial::from(2),
+            0,
+        );
+        let _ = received_events(&mut display, &mut socket);
+
+        // the client destroys its source in the middle of the drag'n'drop
+        // (wl_data_source.destroy is the second request of the interface)
+        send_int_request(&mut display, &mut socket, source.as_ref().id(), 1, &[]);
+        pointer.motion((12.0, 12.0), Some((surface, (0.0, 0.0))), Serial::from(3), 1);
+
+        // the target is left and the grab released
+        // (wl_data_device.leave is the third event of the interface)
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 2)));
+        assert!(!pointer.is_grabbed());
+    }
+
+    #[test]
+    fn dnd_offer_versions() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let old_device = create_data_device(&seat, token, &client, 2);
+        // a source from before the drag'n'drop actions
+        let source = client.create_resource::<wl_data_source::WlDataSource>(1).unwrap();
+        let source = self::data_source::implement_data_source(
+            source,
+            DataDeviceConfig::default().max_mime_types,
+            |_| {},
+            ::slog::Logger::root(::slog::Discard, o!()),
+        );
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .mime_types
+            .push("text/plain".into());
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let (messages, _) = read_messages(&mut display, &mut socket);
+        let events = messages
+            .iter()
+            .map(|message| (message.sender, message.opcode))
+            .collect::<Vec<_>>();
+        let offers = offers_of(&messages, &[device.as_ref().id(), old_device.as_ref().id()]);
+        assert_eq!(offers.len(), 2);
+        // only the offer of the version 3 data device gets wl_data_offer.source_actions,
+        // its second event
+        let recent_offers = offers
+            .iter()
+            .cloned()
+            .filter(|&id| events.contains(&(id, 1)))
+            .collect::<Vec<_>>();
+        assert_eq!(recent_offers.len(), 1);
+
+        // the target negotiating an action does not notify the old source
+        // (wl_data_offer.set_actions is the fifth request of the interface, and
+        // wl_data_source.action the sixth event of the interface)
+        let (copy, moving) = (DndAction::Copy.bits(), DndAction::Move.bits());
+        send_int_request(
+            &mut display,
+            &mut socket,
+            recent_offers[0],
+            4,
+            &[copy | moving, copy],
+        );
+        let events = received_events(&mut display, &mut socket);
+        assert!(!events.contains(&(source.as_ref().id(), 5)));
+    }
+
+    #[test]
+    fn dnd_offer_destroyed_without_finish() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        source
+            .as_ref()
+            .user_data()
+            .get::<DataSourceData>()
+            .unwrap()
+            .metadata
+            .borrow_mut()
+            .dnd_action = DndAction::Copy | DndAction::Move;
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+
+        // the target accepts the data and prefers moving it
+        // (wl_data_offer.accept and set_actions are the first and fifth requests of the interface)
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let (copy, moving) = (DndAction::Copy.bits(), DndAction::Move.bits());
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy | moving, moving]);
+        let _ = received_events(&mut display, &mut socket);
+
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(3), 1);
+        let _ = received_events(&mut display, &mut socket);
+
+        // the target destroys the offer instead of finishing it
+        // (wl_data_offer.destroy is the third request of the interface, wl_data_source.cancelled
+        // the third event of the interface)
+        send_int_request(&mut display, &mut socket, offer_id, 2, &[]);
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(source.as_ref().id(), 2)));
+        assert!(!events.contains(&(source.as_ref().id(), 4)));
+    }
+
+    #[test]
+    fn data_device_events_channel() {
+        let (sender, channel) = channel::channel();
+        let mut event_loop = ::calloop::EventLoop::<Vec<DataDeviceEvent>>::new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(channel, |event, _, events| {
+                if let channel::Event::Msg(event) = event {
+                    events.push(event);
+                }
+            })
+            .map_err(|_| ())
+            .unwrap();
+
+        sender.send(DataDeviceEvent::DnDDropped).unwrap();
+        sender.send(DataDeviceEvent::NewSelection(None)).unwrap();
+
+        let mut events = Vec::new();
+        event_loop
+            .dispatch(Some(Duration::from_millis(0)), &mut events)
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], DataDeviceEvent::DnDDropped));
+        assert!(matches!(events[1], DataDeviceEvent::NewSelection(None)));
+    }
+
+    #[test]
+    fn selection_owner() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, _socket) = create_client(&mut display);
+        let (other, _other_socket) = create_client(&mut display);
+        assert!(selection_owner_client(&seat).is_none());
+
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source));
+        let owner = selection_owner_client(&seat).unwrap();
+        assert!(owner.equals(&client));
+        assert!(!owner.equals(&other));
+
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        assert!(selection_owner_client(&seat).is_none());
+    }
+
+    #[test]
+    fn data_device_created_during_dnd() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (origin_client, _origin_socket) = create_client(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let source = create_data_source(&origin_client, &["text/plain"]);
+        let origin = origin_client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let device = create_data_device(&seat, token, &client, 3);
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source),
+                origin,
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion(
+            (10.0, 10.0),
+            Some((surface.clone(), (0.0, 0.0))),
+            Serial::from(2),
+            0,
+        );
+        set_data_device_focus(&seat, Some(client.clone()));
+        let _ = received_events(&mut display, &mut socket);
+
+        // the focused client creates a new data device during the drag
+        let new_device = create_data_device(&seat, token, &client, 3);
+        pointer.motion((20.0, 20.0), Some((surface, (0.0, 0.0))), Serial::from(3), 1);
+        pointer.button(0x110, wl_pointer::ButtonState::Released, Serial::from(4), 2);
+
+        // the new data device receives the selection (sixth event of wl_data_device), but
+        // none of the enter, leave, motion or drop events of the drag'n'drop
+        let events = received_events(&mut display, &mut socket);
+        let new_id = new_device.as_ref().id();
+        assert!(events.contains(&(new_id, 5)));
+        assert!(!events
+            .iter()
+            .any(|&(id, opcode)| id == new_id && (1..=4).contains(&opcode)));
+        // while the drag'n'drop goes on with the data device that entered the target
+        let id = device.as_ref().id();
+        assert!(events.contains(&(id, 3)));
+        assert!(!events.contains(&(id, 5)));
+    }
+
+    #[test]
+    fn target_only_sent_during_dnd() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        // wl_data_offer.accept is the first request of the interface, and
+        // wl_data_source.target the first event of the interface
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+
+        // a client erroneously accepts a selection offer
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+        set_data_device_focus(&seat, Some(client.clone()));
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        assert!(!received_events(&mut display, &mut socket).contains(&(source.as_ref().id(), 0)));
+
+        // the feedback of the target is forwarded during a drag'n'drop
+        let source = create_data_source(&client, &["text/plain"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                surface.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        pointer.motion((10.0, 10.0), Some((surface, (0.0, 0.0))), Serial::from(2), 0);
+        let offer_id = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        assert!(received_events(&mut display, &mut socket).contains(&(source.as_ref().id(), 0)));
+    }
+
+    #[test]
+    fn dry_run_server_dnd() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let (offer_id, events) =
+            start_server_dnd_over_surface(&mut display, &mut seat, token, &client, &mut socket, true);
+        // the target negotiates the copy action and requests the data
+        // (wl_data_offer.set_actions is the fifth request of the interface)
+        let mut accept = vec![0];
+        accept.extend(string_arg("text/plain"));
+        send_int_request(&mut display, &mut socket, offer_id, 0, &accept);
+        let copy = DndAction::Copy.bits();
+        send_int_request(&mut display, &mut socket, offer_id, 4, &[copy, copy]);
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer_id, 1, "text/plain", write_fd);
+        let _ = ::nix::unistd::close(write_fd);
+        let _ = received_events(&mut display, &mut socket);
+        // the request was denied and its fd closed
+        let mut data = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut data)
+            .unwrap();
+        assert!(data.is_empty());
+
+        finish_server_dnd(&seat);
+
+        assert_eq!(*events.borrow(), vec!["action", "dry_run_drop", "cancelled"]);
+        // wl_data_device.leave is the third event of the interface, and drop the fifth one
+        let device_id = seat_data(&seat)
+            .borrow()
+            .known_devices
+            .iter()
+            .next()
+            .unwrap()
+            .as_ref()
+            .id();
+        let client_events = received_events(&mut display, &mut socket);
+        assert!(client_events.contains(&(device_id, 2)));
+        assert!(!client_events.contains(&(device_id, 4)));
+    }
+
+    #[test]
+    fn remote_selection() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        create_data_device(&seat, token, &client, 3);
+        let request = |mime_type: &str| {
+            let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+            let requested = request_selection(&seat, mime_type, write_fd);
+            let _ = ::nix::unistd::close(read_fd);
+            requested
+        };
+
+        // the selection of a client is written into the fd by its source
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+        assert!(!request("image/png"));
+        assert!(request("text/plain"));
+        // wl_data_source.send is the second event of the interface
+        let (events, fds) = received_events_with_fds(&mut display, &mut socket);
+        assert!(events.contains(&(source.as_ref().id(), 1)));
+        assert_eq!(fds.len(), 1);
+        for fd in fds {
+            let _ = ::nix::unistd::close(fd);
+        }
+
+        // the requests for a remote selection are forwarded
+        let requests = Rc::new(RefCell::new(Vec::new()));
+        let requests2 = requests.clone();
+        install_remote_selection(&seat, strings(&["text/plain"]), move |mime_type, fd| {
+            requests2.borrow_mut().push(mime_type);
+            let _ = ::nix::unistd::close(fd);
+        });
+        assert!(selection_owner_client(&seat).is_none());
+        assert!(request("text/plain"));
+        assert_eq!(*requests.borrow(), strings(&["text/plain"]));
+
+        // selections provided through the callback cannot be requested
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        assert!(!request("text/plain"));
+    }
+
+    #[test]
+    fn selection_from_source() {
+        struct Rendered(Rc<Cell<usize>>);
+        impl SelectionSource for Rendered {
+            fn mime_types(&self) -> Vec<String> {
+                strings(&["image/png", "text/plain"])
+            }
+            fn write(&self, mime_type: &str, writer: &mut dyn Write) -> io::Result<()> {
+                self.0.set(self.0.get() + 1);
+                match mime_type {
+                    "text/plain" => writer.write_all(b"Rendered lazily"),
+                    _ => Err(io::Error::new(io::ErrorKind::Other, "rendering failed")),
+                }
+            }
+        }
+
+        let mut display = Display::new();
+        let (seat, _token) = create_seat(&mut display);
+        let renders = Rc::new(Cell::new(0));
+        set_data_device_selection_source(&seat, Box::new(Rendered(renders.clone())));
+        assert_eq!(selection_mime_types(&seat), strings(&["image/png", "text/plain"]));
+        // the contents are only generated when requested
+        assert_eq!(renders.get(), 0);
+
+        let read = |mime_type: &str| {
+            let mut contents = Vec::new();
+            read_selection(&seat, mime_type)
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert_eq!(read("text/plain"), b"Rendered lazily");
+        // a failing source serves nothing
+        assert!(read("image/png").is_empty());
+        assert_eq!(renders.get(), 2);
+    }
+
+    #[test]
+    fn selection_superseded_event() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let handoffs = Rc::new(RefCell::new(Vec::new()));
+        let handoffs2 = handoffs.clone();
+        create_data_device_with_callback(&seat, token, &client, 3, move |event| {
+            if let DataDeviceEvent::SelectionSuperseded { previous, source } = event {
+                handoffs2.borrow_mut().push((previous, source));
+            }
+        });
+        let previous = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(previous.clone()));
+        let (other_client, _other_socket) = create_client(&mut display);
+        let source = create_data_source(&other_client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+
+        // the source losing the selection is cancelled
+        // (wl_data_source.cancelled is the third event of the interface)
+        assert!(received_events(&mut display, &mut socket).contains(&(previous.as_ref().id(), 2)));
+        let handoffs = handoffs.borrow();
+        assert_eq!(handoffs.len(), 1);
+        assert!(handoffs[0].0.as_ref().equals(previous.as_ref()));
+        assert!(handoffs[0].1.as_ref().equals(source.as_ref()));
+    }
+
+    #[test]
+    fn selection_cleared_event() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let cleared = Rc::new(Cell::new(0));
+        let cleared2 = cleared.clone();
+        create_data_device_with_callback(&seat, token, &client, 3, move |event| {
+            if let DataDeviceEvent::SelectionCleared = event {
+                cleared2.set(cleared2.get() + 1);
+            }
+        });
+
+        copy_bytes(&seat, strings(&["text/plain"]), b"Hello".to_vec());
+        assert_eq!(cleared.get(), 0);
+        clear_data_device_selection(&seat);
+        assert_eq!(cleared.get(), 1);
+        // the selection was already empty
+        clear_data_device_selection(&seat);
+        assert_eq!(cleared.get(), 1);
+
+        // the source of the selection is destroyed by its client
+        // (wl_data_source.destroy is the second request of the interface)
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+        send_int_request(&mut display, &mut socket, source.as_ref().id(), 1, &[]);
+        assert!(selection_mime_types(&seat).is_empty());
+        assert_eq!(cleared.get(), 2);
+    }
+
+    #[test]
+    fn selection_notifications_reenter() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, _socket) = create_client(&mut display);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen2 = seen.clone();
+        let seat2 = seat.clone();
+        create_data_device_with_callback(&seat, token, &client, 3, move |event| {
+            if let DataDeviceEvent::SelectionCleared = event {
+                // the compositor can use the data device functions from its callback
+                copy_bytes(&seat2, strings(&["text/html"]), b"<p>Hi</p>".to_vec());
+            }
+        });
+        let seat3 = seat.clone();
+        set_selection_change_callback(&seat, move |_| {
+            seen2.borrow_mut().push(selection_mime_types(&seat3));
+        });
+
+        copy_bytes(&seat, strings(&["text/plain"]), b"Hello".to_vec());
+        clear_data_device_selection(&seat);
+        assert_eq!(selection_mime_types(&seat), strings(&["text/html"]));
+        assert_eq!(
+            *seen.borrow(),
+            vec![strings(&["text/plain"]), Vec::new(), strings(&["text/html"])]
+        );
+    }
+
+    #[test]
+    fn dead_devices_pruned() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, _socket) = create_client(&mut display);
+        let (other, mut other_socket) = create_client(&mut display);
+        create_data_device(&seat, token, &client, 3);
+        let other_device = create_data_device(&seat, token, &other, 3);
+        set_data_device_focus(&seat, Some(other.clone()));
+        let _ = received_events(&mut display, &mut other_socket);
+
+        // the client disconnects without releasing its data device
+        client.kill();
+        display
+            .dispatch(::std::time::Duration::from_millis(0), &mut ())
+            .unwrap();
+        display.flush_clients(&mut ());
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        let seat_data = seat_data(&seat);
+        let known_ids = seat_data
+            .borrow()
+            .known_devices
+            .iter()
+            .map(|dd| dd.as_ref().id())
+            .collect::<Vec<_>>();
+        assert_eq!(known_ids, vec![other_device.as_ref().id()]);
+
+        // the focused client still receives the selection
+        // (wl_data_device.selection is the sixth event of the interface)
+        let events = received_events(&mut display, &mut other_socket);
+        assert!(events.contains(&(other_device.as_ref().id(), 5)));
+    }
+
+    #[test]
+    fn request_on_released_data_device() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let keyboard = seat.add_keyboard(Default::default(), 200, 25, |_, _| {}).unwrap();
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        keyboard.set_focus(Some(&surface), Serial::from(1));
+
+        // wl_data_device.release is the third request of the interface, and
+        // wl_data_device.set_selection the second one
+        send_int_request(&mut display, &mut socket, device.as_ref().id(), 2, &[]);
+        send_int_request(
+            &mut display,
+            &mut socket,
+            device.as_ref().id(),
+            1,
+            &[source.as_ref().id(), 1],
+        );
+
+        assert!(seat_data(&seat).borrow().known_devices.is_empty());
+        assert!(selection_snapshot(&seat).is_empty());
+    }
+
+    #[test]
+    fn forced_client_selection() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        create_data_device(&seat, token, &client, 3);
+        let previous = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(previous.clone()));
+
+        // the source is adopted although its client has no keyboard focus, and the previous
+        // one is told it lost the selection
+        // (wl_data_source.cancelled is the third event of the interface)
+        let (other_client, _other_socket) = create_client(&mut display);
+        let source = create_data_source(&other_client, &["text/html"]);
+        assert!(force_set_client_selection(&seat, source.clone()));
+        assert!(selection_owner_client(&seat).unwrap().equals(&other_client));
+        assert_eq!(selection_mime_types(&seat), strings(&["text/html"]));
+        assert!(received_events(&mut display, &mut socket).contains(&(previous.as_ref().id(), 2)));
+
+        // a source offering nothing is refused
+        let empty = create_data_source(&client, &[]);
+        assert!(!force_set_client_selection(&seat, empty));
+        assert_eq!(selection_mime_types(&seat), strings(&["text/html"]));
+    }
+
+    #[test]
+    fn destroy_data_device() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let handle = DataDeviceBuilder::new().init(&mut display, token);
+        // the seat is known to the global once a client created a data device for it
+        handle.known_seats.borrow_mut().push(seat.clone());
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source.clone()));
+        set_data_device_focus(&seat, Some(client));
+        let _ = received_events(&mut display, &mut socket);
+
+        // the source is cancelled and the client told the selection is gone
+        // (wl_data_source.cancelled and wl_data_device.selection are the third and sixth events
+        // of their interfaces)
+        handle.destroy();
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(source.as_ref().id(), 2)));
+        assert!(events.contains(&(device.as_ref().id(), 5)));
+        assert!(selection_snapshot(&seat).is_empty());
+        assert!(seat_data(&seat).borrow().known_devices.is_empty());
+    }
+
+    #[test]
+    fn selections_isolated_between_seats() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let (other_seat, _) = Seat::new(&mut display, "seat-1".into(), token, None);
+        // a client with a data device on both seats, and one only on the other seat
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        let shared_device = create_data_device(&other_seat, token, &client, 3);
+        let (other_client, mut other_socket) = create_client(&mut display);
+        let other_device = create_data_device(&other_seat, token, &other_client, 3);
+
+        set_data_device_selection(&seat, strings(&["text/plain"]));
+        set_data_device_focus(&seat, Some(client.clone()));
+        set_data_device_focus(&other_seat, Some(client.clone()));
+        // only the data device of the first seat is offered its selection
+        // (wl_data_device.data_offer is the first event of the interface)
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(!events.contains(&(shared_device.as_ref().id(), 0)));
+        set_data_device_focus(&other_seat, Some(other_client));
+        assert!(!received_events(&mut display, &mut other_socket).contains(&(other_device.as_ref().id(), 0)));
+
+        // a client focused on the first seat cannot set the selection of the other one
+        // (wl_data_device.set_selection is the second request of the interface)
+        let keyboard = seat.add_keyboard(Default::default(), 200, 25, |_, _| {}).unwrap();
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        keyboard.set_focus(Some(&surface), Serial::from(1));
+        let source = create_data_source(&client, &["text/html"]);
+        send_int_request(
+            &mut display,
+            &mut socket,
+            shared_device.as_ref().id(),
+            1,
+            &[source.as_ref().id(), 1],
+        );
+        assert!(selection_snapshot(&other_seat).is_empty());
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain"]));
+    }
+
+    #[test]
+    fn dnd_icon_follows_pointer() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let pointer = seat.add_pointer(token, |_| {});
+        let (client, _socket) = create_client(&mut display);
+        let origin = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let icon = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        assert_eq!(dnd_icon_position(&seat), None);
+        token.give_role::<DnDIconRole>(&icon).unwrap();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (2.0, 2.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                None,
+                origin.clone(),
+                seat.clone(),
+                Some(icon.clone()),
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        seat_data(&seat).borrow_mut().current_dnd = Some(ActiveDnD {
+            source: None,
+            origin,
+            icon: Some(icon),
+            icon_hotspot: (0, 0),
+            location: (2.0, 2.0),
+            touch: false,
+        });
+        assert_eq!(dnd_icon_position(&seat), Some((2.0, 2.0)));
+        // the grab intercepts the motions of the pointer
+        pointer.motion((10.0, 10.0), None, Serial::from(2), 0);
+        assert_eq!(dnd_icon_position(&seat), Some((10.0, 10.0)));
+
+        // the deltas of the buffers attached to the icon add up
+        move_dnd_icon(&seat, (-4, 2));
+        move_dnd_icon(&seat, (1, 1));
+        assert_eq!(dnd_icon_position(&seat), Some((7.0, 13.0)));
+    }
+
+    #[test]
+    fn cancel_drags_on_all_seats() {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let (mut other_seat, _) = Seat::new(&mut display, "seat-1".into(), token, None);
+        let (idle_seat, _) = Seat::new(&mut display, "seat-2".into(), token, None);
+        let (client, mut socket) = create_client(&mut display);
+
+        // a client drag'n'drop on the first seat
+        let pointer = seat.add_pointer(token, |_| {});
+        create_data_device(&seat, token, &client, 3);
+        let source = create_data_source(&client, &["text/plain"]);
+        let origin = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .unwrap()
+            .deref()
+            .clone();
+        let start_data = GrabStartData {
+            focus: None,
+            button: 0x110,
+            location: (0.0, 0.0),
+        };
+        pointer.set_grab(
+            dnd_grab::DnDGrab::new(
+                start_data,
+                Some(source.clone()),
+                origin.clone(),
+                seat.clone(),
+                None,
+                token,
+                Rc::new(RefCell::new(|_: DataDeviceEvent| {})),
+            ),
+            Serial::from(1),
+        );
+        seat_data(&seat).borrow_mut().current_dnd = Some(ActiveDnD {
+            source: Some(source.clone()),
+            origin,
+            icon: None,
+            icon_hotspot: (0, 0),
+            location: (0.0, 0.0),
+            touch: false,
+        });
+        // a compositor drag'n'drop on the second seat
+        let (_, events) =
+            start_server_dnd_over_surface(&mut display, &mut other_seat, token, &client, &mut socket, false);
+
+        assert_eq!(active_dnd(&seat), Some(DndKind::Client));
+        assert_eq!(active_dnd(&other_seat), Some(DndKind::Compositor));
+        assert!(!is_dnd_active(&idle_seat));
+
+        let seats = [seat.clone(), other_seat.clone(), idle_seat];
+        cancel_all_drags(&seats);
+        assert!(seats.iter().all(|seat| !is_dnd_active(seat)));
+
+        assert!(!seat.get_pointer().unwrap().is_grabbed());
+        assert!(!other_seat.get_pointer().unwrap().is_grabbed());
+        // wl_data_source.cancelled is the third event of the interface
+        assert!(received_events(&mut display, &mut socket).contains(&(source.as_ref().id(), 2)));
+        assert_eq!(*events.borrow(), vec!["cancelled"]);
+        // nothing happens once the drag'n'drops are over
+        cancel_all_drags(&seats);
+        assert_eq!(*events.borrow(), vec!["cancelled"]);
+    }
+
+    #[test]
+    fn selection_kept_without_focus() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        set_data_device_focus(&seat, Some(client.clone()));
+        copy_bytes(&seat, strings(&["text/plain"]), b"Hello".to_vec());
+        let _ = received_events(&mut display, &mut socket);
+
+        // the window of the client is minimized, and restored
+        set_data_device_focus(&seat, None);
+        assert!(received_events(&mut display, &mut socket).is_empty());
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain"]));
+        set_data_device_focus(&seat, Some(client));
+
+        // the client still has its offer for this selection, no new one is created
+        assert!(received_events(&mut display, &mut socket).is_empty());
+        assert_eq!(selection_mime_types(&seat), strings(&["text/plain"]));
+        let offer = sent_offer(&device).unwrap();
+        assert!(offer.as_ref().is_alive());
+    }
+
+    // The offer of the last selection sent to a data device
+    fn sent_offer(dd: &wl_data_device::WlDataDevice) -> Option<wl_data_offer::WlDataOffer> {
+        let dd_data = dd.as_ref().user_data().get::<DataDeviceData>().unwrap();
+        let sent_selection = dd_data.sent_selection.borrow();
+        sent_selection.as_ref().and_then(|&(_, ref offer)| offer.clone())
+    }
+
+    #[test]
+    fn selection_resent_after_offer_destroyed() {
+        let mut display = Display::new();
+        let (seat, token) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let (other, _other_socket) = create_client(&mut display);
+        let device = create_data_device(&seat, token, &client, 3);
+        set_data_device_focus(&seat, Some(client.clone()));
+        copy_bytes(&seat, strings(&["text/plain"]), b"Hello".to_vec());
+        let _ = received_events(&mut display, &mut socket);
+
+        // the client destroys its offer while an other client has the focus
+        // (wl_data_offer.destroy is the third request of the interface)
+        set_data_device_focus(&seat, Some(other));
+        let offer = sent_offer(&device).unwrap();
+        send_int_request(&mut display, &mut socket, offer.as_ref().id(), 2, &[]);
+        set_data_device_focus(&seat, Some(client.clone()));
+
+        // wl_data_device.data_offer is the first event of the interface, selection the sixth
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 5)));
+
+        // a new selection is always sent
+        copy_bytes(&seat, strings(&["text/plain"]), b"World".to_vec());
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 5)));
     }
 }
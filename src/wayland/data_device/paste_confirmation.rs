@@ -0,0 +1,151 @@
+use std::{cell::RefCell, os::unix::io::RawFd, rc::Rc};
+
+use wayland_server::Client;
+
+/// The shared storage of the paste confirmation hook of a seat
+pub(crate) type PasteConfirmation = Rc<RefCell<Option<Rc<RefCell<dyn FnMut(PasteRequest)>>>>>;
+
+/// A request from a client to read the selection, pending the approval of the compositor
+///
+/// The requesting client waits for the data until you call [`approve`](PasteRequest::approve)
+/// or [`deny`](PasteRequest::deny). Dropping the request denies it.
+///
+/// See [`set_paste_confirmation`](super::set_paste_confirmation).
+pub struct PasteRequest {
+    client: Option<Client>,
+    mime_type: String,
+    transfer: Option<(RawFd, Box<dyn FnOnce(String, RawFd)>)>,
+}
+
+impl PasteRequest {
+    /// The client requesting to read the selection
+    pub fn client(&self) -> Option<&Client> {
+        self.client.as_ref()
+    }
+
+    /// The mime type requested by the client
+    pub fn mime_type(&self) -> &str {
+        &self.mime_type
+    }
+
+    /// Approve the request, the selection data is sent to the client
+    pub fn approve(mut self) {
+        if let Some((fd, transfer)) = self.transfer.take() {
+            let mime_type = ::std::mem::replace(&mut self.mime_type, String::new());
+            transfer(mime_type, fd);
+        }
+    }
+
+    /// Deny the request, the client will not receive any data
+    pub fn deny(self) {}
+}
+
+impl Drop for PasteRequest {
+    fn drop(&mut self) {
+        if let Some((fd, _)) = self.transfer.take() {
+            super::close_fd(fd);
+        }
+    }
+}
+
+// Submit a request to read the selection to the paste confirmation hook, if any
+//
+// The transfer is run directly if there is no hook.
+pub(crate) fn confirm_paste<F>(
+    confirmation: &PasteConfirmation,
+    client: Option<Client>,
+    mime_type: String,
+    fd: RawFd,
+    transfer: F,
+) where
+    F: FnOnce(String, RawFd) + 'static,
+{
+    // release the storage while the hook runs, so that it can change the hook of the seat
+    let hook = confirmation.borrow().clone();
+    match hook {
+        Some(hook) => (&mut *hook.borrow_mut())(PasteRequest {
+            client,
+            mime_type,
+            transfer: Some((fd, Box::new(transfer))),
+        }),
+        None => transfer(mime_type, fd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        fs::File,
+        io::{Read, Write},
+        os::unix::io::FromRawFd,
+    };
+
+    fn write_mime_type(mime_type: String, fd: RawFd) {
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(mime_type.as_bytes()).unwrap();
+    }
+
+    // Submit a paste to the hook and read what was sent to the client
+    fn paste_with<F: FnMut(PasteRequest) + 'static>(hook: F) -> Vec<u8> {
+        let confirmation: PasteConfirmation = Rc::new(RefCell::new(Some(Rc::new(RefCell::new(hook)))));
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        confirm_paste(
+            &confirmation,
+            None,
+            "text/plain".into(),
+            write_fd,
+            write_mime_type,
+        );
+        // the hook is still set after use
+        assert!(confirmation.borrow().is_some());
+        let mut data = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn approved_paste() {
+        assert_eq!(
+            paste_with(|request| {
+                assert_eq!(request.mime_type(), "text/plain");
+                request.approve()
+            }),
+            b"text/plain"
+        );
+    }
+
+    #[test]
+    fn denied_paste() {
+        assert!(paste_with(|request| request.deny()).is_empty());
+    }
+
+    #[test]
+    fn deferred_paste() {
+        let pending = Rc::new(RefCell::new(None));
+        let pending2 = pending.clone();
+        let confirmation: PasteConfirmation =
+            Rc::new(RefCell::new(Some(Rc::new(RefCell::new(move |request| {
+                *pending2.borrow_mut() = Some(request);
+            })))));
+        let (read_fd, write_fd) = ::nix::unistd::pipe().unwrap();
+        confirm_paste(
+            &confirmation,
+            None,
+            "text/plain".into(),
+            write_fd,
+            write_mime_type,
+        );
+        // the decision can be taken later on
+        let request: PasteRequest = pending.borrow_mut().take().unwrap();
+        request.approve();
+        let mut data = Vec::new();
+        unsafe { File::from_raw_fd(read_fd) }
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"text/plain");
+    }
+}
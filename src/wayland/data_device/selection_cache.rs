@@ -0,0 +1,107 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    os::unix::io::{FromRawFd, RawFd},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use nix::fcntl::OFlag;
+use wayland_server::protocol::{wl_data_device_manager::DndAction, wl_data_source::WlDataSource};
+
+use super::{compositor_selection::SelectionContent, with_source_metadata, SourceMetadata};
+
+/// Maximum size of the contents cached for a single mime type
+const MAX_CACHED_SIZE: u64 = 1024 * 1024;
+
+pub(crate) type CachedContents = Arc<Mutex<HashMap<String, Arc<Vec<u8>>>>>;
+
+// A copy of the contents of a client selection, kept to be able to still serve it for
+// a grace period once the client is gone
+pub(crate) struct SelectionCache {
+    source: WlDataSource,
+    period: Duration,
+    contents: CachedContents,
+}
+
+impl SelectionCache {
+    // Start reading all the mime types of this source into a new cache
+    //
+    // The reads happen in worker threads, the contents of a mime type are only available
+    // once the source finished writing them.
+    pub(crate) fn start(source: &WlDataSource, period: Duration, log: &::slog::Logger) -> SelectionCache {
+        let contents = CachedContents::default();
+        let mime_types = with_source_metadata(source, |meta| meta.mime_types.clone()).unwrap_or_default();
+        for mime_type in mime_types {
+            let (read_fd, write_fd) = match ::nix::unistd::pipe2(OFlag::O_CLOEXEC) {
+                Ok(fds) => fds,
+                Err(err) => {
+                    debug!(log, "Failed to create a pipe to cache the selection"; "error" => format!("{:?}", err));
+                    break;
+                }
+            };
+            source.send(mime_type.clone(), write_fd);
+            super::close_fd(write_fd);
+            spawn_cache_reader(read_fd, mime_type, contents.clone(), log.clone());
+        }
+        SelectionCache {
+            source: source.clone(),
+            period,
+            contents,
+        }
+    }
+
+    // Turn the cache into a compositor selection expiring at the end of the grace period
+    //
+    // Returns `None` if nothing could be cached.
+    pub(crate) fn into_selection(self) -> Option<(SourceMetadata, SelectionContent)> {
+        let contents = self.contents.lock().unwrap().clone();
+        if contents.is_empty() {
+            return None;
+        }
+        // keep the order in which the source advertised the mime types
+        let mime_types = with_source_metadata(&self.source, |meta| meta.mime_types.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|mime_type| contents.contains_key(mime_type))
+            .collect();
+        Some((
+            SourceMetadata {
+                mime_types,
+                dnd_action: DndAction::empty(),
+            },
+            SelectionContent::Cached {
+                contents,
+                expires: Instant::now() + self.period,
+            },
+        ))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn contents(&self) -> &CachedContents {
+        &self.contents
+    }
+}
+
+// Read the contents of a mime type from this fd in a worker thread, keeping them if they
+// are not too large
+pub(crate) fn spawn_cache_reader(
+    fd: RawFd,
+    mime_type: String,
+    contents: CachedContents,
+    log: ::slog::Logger,
+) {
+    ::std::thread::spawn(move || {
+        let mut data = Vec::new();
+        let file = unsafe { File::from_raw_fd(fd) };
+        match file.take(MAX_CACHED_SIZE + 1).read_to_end(&mut data) {
+            Ok(_) if data.len() as u64 <= MAX_CACHED_SIZE => {
+                contents.lock().unwrap().insert(mime_type, Arc::new(data));
+            }
+            Ok(_) => debug!(log, "Selection contents too large to be cached"; "mime_type" => mime_type),
+            Err(err) => debug!(log, "Failed to cache the selection contents";
+                "mime_type" => mime_type, "error" => format!("{:?}", err)),
+        }
+    });
+}
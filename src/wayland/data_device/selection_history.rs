@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+
+use nix::fcntl::OFlag;
+use wayland_server::protocol::wl_data_source::WlDataSource;
+
+use super::{
+    compositor_selection::SelectionContent,
+    selection_cache::{spawn_cache_reader, CachedContents},
+    with_source_metadata, SourceMetadata,
+};
+
+// A copy of a past selection
+struct HistoryEntry {
+    // the mime types of the selection, in the order they were advertised
+    mime_types: Vec<String>,
+    contents: CachedContents,
+}
+
+// The last selections of a seat, the most recent first
+//
+// Nothing is recorded while the capacity is zero.
+#[derive(Default)]
+pub(crate) struct SelectionHistory {
+    capacity: usize,
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl SelectionHistory {
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.entries.truncate(capacity);
+    }
+
+    // Record the selection of a client, reading all its mime types in worker threads
+    pub(crate) fn record_client(&mut self, source: &WlDataSource, log: &::slog::Logger) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mime_types = with_source_metadata(source, |meta| meta.mime_types.clone()).unwrap_or_default();
+        let contents = CachedContents::default();
+        for mime_type in &mime_types {
+            let (read_fd, write_fd) = match ::nix::unistd::pipe2(OFlag::O_CLOEXEC) {
+                Ok(fds) => fds,
+                Err(err) => {
+                    debug!(log, "Failed to create a pipe to record the selection"; "error" => format!("{:?}", err));
+                    break;
+                }
+            };
+            source.send(mime_type.clone(), write_fd);
+            super::close_fd(write_fd);
+            spawn_cache_reader(read_fd, mime_type.clone(), contents.clone(), log.clone());
+        }
+        self.push(mime_types, contents);
+    }
+
+    // Record a selection provided by the compositor
+    //
+    // Bytes are shared with the selection, the other contents are read as if a client pasted
+    // them. The contents provided through the data device callback cannot be recorded.
+    pub(crate) fn record_compositor(
+        &mut self,
+        metadata: &SourceMetadata,
+        content: &SelectionContent,
+        log: &::slog::Logger,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let contents = CachedContents::default();
+        match *content {
+            SelectionContent::Callback | SelectionContent::Cached { .. } => return,
+            SelectionContent::Bytes(ref data) => {
+                let mut contents = contents.lock().unwrap();
+                for mime_type in &metadata.mime_types {
+                    contents.insert(mime_type.clone(), data.clone());
+                }
+            }
+            SelectionContent::Buffers(ref buffers) => {
+                contents.lock().unwrap().extend(
+                    buffers
+                        .iter()
+                        .map(|(mime_type, data)| (mime_type.clone(), data.clone())),
+                );
+            }
+            _ => {
+                for mime_type in &metadata.mime_types {
+                    let (read_fd, write_fd) = match ::nix::unistd::pipe2(OFlag::O_CLOEXEC) {
+                        Ok(fds) => fds,
+                        Err(err) => {
+                            debug!(log, "Failed to create a pipe to record the selection"; "error" => format!("{:?}", err));
+                            break;
+                        }
+                    };
+                    content.serve(mime_type, write_fd, None, log);
+                    spawn_cache_reader(read_fd, mime_type.clone(), contents.clone(), log.clone());
+                }
+            }
+        }
+        self.push(metadata.mime_types.clone(), contents);
+    }
+
+    fn push(&mut self, mime_types: Vec<String>, contents: CachedContents) {
+        self.entries.push_front(HistoryEntry { mime_types, contents });
+        // the oldest entries are evicted first
+        self.entries.truncate(self.capacity);
+    }
+
+    // The mime types of each entry, the most recent first
+    pub(crate) fn mime_types(&self) -> Vec<Vec<String>> {
+        self.entries
+            .iter()
+            .map(|entry| entry.mime_types.clone())
+            .collect()
+    }
+
+    // Remove an entry, as the selection it can be turned into
+    //
+    // Only the mime types whose contents were fully read are kept, returns `None` if there
+    // is no such entry or none of its contents could be read.
+    pub(crate) fn take(&mut self, index: usize) -> Option<(SourceMetadata, SelectionContent)> {
+        let entry = self.entries.remove(index)?;
+        let contents = entry.contents.lock().unwrap().clone();
+        let metadata = SourceMetadata::new(
+            entry
+                .mime_types
+                .into_iter()
+                .filter(|mime_type| contents.contains_key(mime_type))
+                .collect(),
+        );
+        if metadata.mime_types.is_empty() {
+            return None;
+        }
+        Some((metadata, SelectionContent::Buffers(contents)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn oldest_entries_evicted() {
+        let log = ::slog::Logger::root(::slog::Discard, o!());
+        let mut history = SelectionHistory::default();
+        history.set_capacity(2);
+        for data in &[&b"first"[..], b"second", b"\x00\xff binary"] {
+            history.record_compositor(
+                &SourceMetadata::new(strings(&["application/octet-stream"])),
+                &SelectionContent::Bytes(Arc::new(data.to_vec())),
+                &log,
+            );
+        }
+        assert_eq!(history.mime_types().len(), 2);
+
+        // the most recent entry comes first, with its binary contents intact
+        match history.take(0) {
+            Some((metadata, SelectionContent::Buffers(contents))) => {
+                assert_eq!(metadata.mime_types, strings(&["application/octet-stream"]));
+                assert_eq!(
+                    &**contents.get("application/octet-stream").unwrap(),
+                    b"\x00\xff binary"
+                );
+            }
+            _ => panic!("the entry was not restored"),
+        }
+        match history.take(0) {
+            Some((_, SelectionContent::Buffers(contents))) => {
+                assert_eq!(&**contents.get("application/octet-stream").unwrap(), b"second");
+            }
+            _ => panic!("the entry was not restored"),
+        }
+        assert!(history.take(0).is_none());
+    }
+}
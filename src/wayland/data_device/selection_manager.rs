@@ -0,0 +1,116 @@
+use std::{cell::RefCell, os::unix::io::RawFd, rc::Rc};
+
+use crate::wayland::{primary_selection, seat::Seat};
+
+/// The different kinds of selections a seat can hold
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SelectionKind {
+    /// The clipboard, used for explicit copy/paste actions
+    ///
+    /// It is the selection of the `wl_data_device` protocol.
+    Clipboard,
+    /// The primary selection, typically holding the text the user last highlighted
+    ///
+    /// It is the selection of the `zwp_primary_selection_device_v1` protocol, see the
+    /// [`primary_selection`](::wayland::primary_selection) module.
+    Primary,
+}
+
+/// An handle to manipulate the selections of a seat uniformly
+///
+/// Each method takes the [`SelectionKind`] it applies to, and delegates to the freestanding
+/// functions of this module or of the [`primary_selection`](::wayland::primary_selection) module:
+///
+/// - [`SelectionKind::Clipboard`] maps to [`set_data_device_selection`](super::set_data_device_selection),
+///   [`copy_bytes`](super::copy_bytes), [`selection_mime_types`](super::selection_mime_types),
+///   [`clear_data_device_selection`](super::clear_data_device_selection) and
+///   [`request_selection`](super::request_selection).
+/// - [`SelectionKind::Primary`] maps to
+///   [`set_primary_selection`](::wayland::primary_selection::set_primary_selection) and to the
+///   equivalent primitives of the primary selection, which smithay uses to serve bytes, list the
+///   mime types, clear it and write it into an fd.
+///
+/// These functions remain available if you only need to handle a single kind of selection.
+#[derive(Clone)]
+pub struct SelectionManager {
+    seat: Seat,
+}
+
+impl SelectionManager {
+    /// Create a selection manager for this seat
+    pub fn new(seat: &Seat) -> SelectionManager {
+        SelectionManager { seat: seat.clone() }
+    }
+
+    /// Set a compositor-provided selection
+    ///
+    /// Your data device callback, or your primary selection callback for
+    /// [`SelectionKind::Primary`], will be requested to provide the data for it.
+    pub fn set(&self, kind: SelectionKind, mime_types: Vec<String>) {
+        match kind {
+            SelectionKind::Clipboard => super::set_data_device_selection(&self.seat, mime_types),
+            SelectionKind::Primary => primary_selection::set_primary_selection(&self.seat, mime_types),
+        }
+    }
+
+    /// Set the selection to some bytes provided by the compositor
+    ///
+    /// Smithay will serve the data to the clients by itself.
+    pub fn set_bytes(&self, kind: SelectionKind, mime_types: Vec<String>, data: Vec<u8>) {
+        match kind {
+            SelectionKind::Clipboard => super::copy_bytes(&self.seat, mime_types, data),
+            SelectionKind::Primary => {
+                let log = self.seat.arc.log.clone();
+                let forward = move |_mime_type: String, fd: RawFd| super::serve_bytes(fd, data.clone(), &log);
+                primary_selection::set_forwarded_primary_selection(
+                    &self.seat,
+                    mime_types,
+                    Rc::new(RefCell::new(forward)),
+                );
+            }
+        }
+    }
+
+    /// Retrieve the mime types offered by the current selection
+    pub fn get(&self, kind: SelectionKind) -> Vec<String> {
+        match kind {
+            SelectionKind::Clipboard => super::selection_mime_types(&self.seat),
+            SelectionKind::Primary => {
+                primary_selection::primary_selection_mime_types(&self.seat).unwrap_or_default()
+            }
+        }
+    }
+
+    /// Clear the selection
+    pub fn clear(&self, kind: SelectionKind) {
+        match kind {
+            SelectionKind::Clipboard => super::clear_data_device_selection(&self.seat),
+            SelectionKind::Primary => primary_selection::clear_primary_selection(&self.seat),
+        }
+    }
+
+    /// Write the current selection into an fd, in a given mime type
+    ///
+    /// This takes ownership of the fd. Returns `false` and closes the fd if the selection is
+    /// empty, does not offer this mime type, or was set with [`set`](SelectionManager::set), as
+    /// you provide its contents yourself.
+    pub fn read(&self, kind: SelectionKind, mime_type: &str, fd: RawFd) -> bool {
+        match kind {
+            SelectionKind::Clipboard => super::request_selection(&self.seat, mime_type, fd),
+            SelectionKind::Primary => {
+                if !self.get(kind).iter().any(|offered| offered == mime_type) {
+                    super::close_fd(fd);
+                    return false;
+                }
+                match primary_selection::serve_primary_selection(&self.seat, mime_type, fd) {
+                    Some(fd) => {
+                        // the compositor provides the contents of this selection by itself
+                        super::close_fd(fd);
+                        false
+                    }
+                    None => true,
+                }
+            }
+        }
+    }
+}
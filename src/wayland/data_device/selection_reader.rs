@@ -0,0 +1,76 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+};
+
+use nix::{fcntl::OFlag, unistd::pipe2};
+
+use crate::wayland::seat::Seat;
+
+use super::{seat_data, DataDeviceEvent};
+
+/// A reader for the contents of the selection of a seat
+///
+/// See [`read_selection`].
+#[derive(Debug)]
+pub struct SelectionReader {
+    file: File,
+}
+
+impl Read for SelectionReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl AsRawFd for SelectionReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Read the current selection of this seat, in a given mime type
+///
+/// This works whoever provides the selection: a client source is asked to write its contents
+/// with `wl_data_source.send`, contents served by smithay are written by it, and for a selection
+/// set with [`set_data_device_selection`](super::set_data_device_selection) your data device
+/// callback receives a [`DataDeviceEvent::SendSelection`] to write them.
+///
+/// The reader reaches the end of file once the contents were fully written. A client can only
+/// write them after your compositor dispatched its events, so you must not read them blocking
+/// from your event loop: either move the reader to an other thread, or insert it into your
+/// event loop as a readable source, using its fd. If no client has a data device for this
+/// seat, there is no callback to write the contents of a selection set with
+/// `set_data_device_selection`, and the reader immediately reaches the end of file.
+///
+/// Returns an error of kind `NotFound` if the selection is empty or does not offer this mime type.
+pub fn read_selection(seat: &Seat, mime_type: &str) -> io::Result<SelectionReader> {
+    let seat_data = seat_data(seat);
+    if !seat_data.borrow_mut().has_mime_type(mime_type) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the selection does not offer this mime type",
+        ));
+    }
+    let (read_fd, write_fd) =
+        pipe2(OFlag::O_CLOEXEC).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let reader = SelectionReader {
+        file: unsafe { File::from_raw_fd(read_fd) },
+    };
+    let pending = seat_data.borrow_mut().serve_selection(mime_type, write_fd);
+    if let Some(fd) = pending {
+        // the compositor provides the contents, through the callback of the data devices
+        // of this seat
+        let callback = seat_data.borrow().device_callback();
+        match callback {
+            // the seat data is released, so that the callback can change the selection
+            Some(callback) => (&mut *callback.borrow_mut())(DataDeviceEvent::SendSelection {
+                mime_type: mime_type.into(),
+                fd,
+            }),
+            None => super::close_fd(fd),
+        }
+    }
+    Ok(reader)
+}
@@ -0,0 +1,185 @@
+use std::{os::unix::io::RawFd, rc::Rc};
+
+use wayland_server::protocol::wl_data_source::WlDataSource;
+
+use super::compositor_selection::SelectionContent;
+
+/// A snapshot of the selection of a seat at a given time
+///
+/// It can be obtained with [`selection_snapshot`](super::selection_snapshot) and compared
+/// to another one with [`selections_equal`], for example to detect that a selection change
+/// did not actually change anything.
+#[derive(Clone)]
+pub struct SelectionSnapshot {
+    mime_types: Vec<String>,
+    owner: SnapshotOwner,
+}
+
+#[derive(Clone)]
+enum SnapshotOwner {
+    Empty,
+    Client(WlDataSource),
+    Compositor(Rc<SelectionContent>),
+}
+
+impl SelectionSnapshot {
+    pub(crate) fn empty() -> SelectionSnapshot {
+        SelectionSnapshot {
+            mime_types: Vec::new(),
+            owner: SnapshotOwner::Empty,
+        }
+    }
+
+    pub(crate) fn client(source: WlDataSource, mime_types: Vec<String>) -> SelectionSnapshot {
+        SelectionSnapshot {
+            mime_types,
+            owner: SnapshotOwner::Client(source),
+        }
+    }
+
+    pub(crate) fn compositor(content: Rc<SelectionContent>, mime_types: Vec<String>) -> SelectionSnapshot {
+        SelectionSnapshot {
+            mime_types,
+            owner: SnapshotOwner::Compositor(content),
+        }
+    }
+
+    /// Whether there was no selection
+    pub fn is_empty(&self) -> bool {
+        match self.owner {
+            SnapshotOwner::Empty => true,
+            _ => false,
+        }
+    }
+
+    /// The mime types offered by the selection
+    pub fn mime_types(&self) -> &[String] {
+        &self.mime_types
+    }
+
+    /// The data source of the selection, if it was provided by a client
+    pub fn client_source(&self) -> Option<&WlDataSource> {
+        match self.owner {
+            SnapshotOwner::Client(ref source) => Some(source),
+            _ => None,
+        }
+    }
+
+    // Write the selection into an fd provided by a client, even once it was replaced, the way
+    // the offers of the data device do. The fd is given back if the compositor callback must
+    // provide the contents.
+    pub(crate) fn serve(&self, mime_type: &str, fd: RawFd, log: &::slog::Logger) -> Option<RawFd> {
+        if self.mime_types.iter().any(|m| m == mime_type) {
+            match self.owner {
+                SnapshotOwner::Empty => {}
+                SnapshotOwner::Client(ref source) => {
+                    if source.as_ref().is_alive() {
+                        source.send(mime_type.into(), fd);
+                    }
+                }
+                SnapshotOwner::Compositor(ref content) => {
+                    if let SelectionContent::Callback = **content {
+                        return Some(fd);
+                    }
+                    content.serve(mime_type, fd, None, log);
+                    return None;
+                }
+            }
+        }
+        super::close_fd(fd);
+        None
+    }
+}
+
+/// Check whether two selection snapshots represent the same selection
+///
+/// Two selections are equal if they offer the same set of mime types, regardless of their
+/// order, and:
+///
+/// - they are both empty, or
+/// - they are both provided by the same client data source, as their contents cannot be known
+///   without reading them, or
+/// - they are both provided by the compositor, and are either the same selection or both made
+///   of the same bytes (see [`copy_bytes`](super::copy_bytes) and
+///   [`set_data_device_selection_buffer`](super::set_data_device_selection_buffer)).
+pub fn selections_equal(a: &SelectionSnapshot, b: &SelectionSnapshot) -> bool {
+    let same_mime_types = a.mime_types.len() == b.mime_types.len()
+        && a.mime_types
+            .iter()
+            .all(|mime_type| b.mime_types.contains(mime_type))
+        && b.mime_types
+            .iter()
+            .all(|mime_type| a.mime_types.contains(mime_type));
+    if !same_mime_types {
+        return false;
+    }
+    match (&a.owner, &b.owner) {
+        (SnapshotOwner::Empty, SnapshotOwner::Empty) => true,
+        (SnapshotOwner::Client(a), SnapshotOwner::Client(b)) => a.as_ref().equals(b.as_ref()),
+        (SnapshotOwner::Compositor(a), SnapshotOwner::Compositor(b)) => {
+            if Rc::ptr_eq(a, b) {
+                return true;
+            }
+            match (&**a, &**b) {
+                (SelectionContent::Bytes(a), SelectionContent::Bytes(b)) => a == b,
+                (SelectionContent::Buffers(a), SelectionContent::Buffers(b)) => a == b,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn bytes(data: &[u8], mime_types: &[&str]) -> SelectionSnapshot {
+        SelectionSnapshot::compositor(
+            Rc::new(SelectionContent::Bytes(Arc::new(data.to_vec()))),
+            strings(mime_types),
+        )
+    }
+
+    #[test]
+    fn equal_selections() {
+        assert!(selections_equal(
+            &SelectionSnapshot::empty(),
+            &SelectionSnapshot::empty()
+        ));
+        // the order of the mime types does not matter
+        assert!(selections_equal(
+            &bytes(b"Hello", &["text/plain", "UTF8_STRING"]),
+            &bytes(b"Hello", &["UTF8_STRING", "text/plain"])
+        ));
+        // the same selection is equal to itself, even if its contents are not known
+        let snapshot =
+            SelectionSnapshot::compositor(Rc::new(SelectionContent::Callback), strings(&["text/plain"]));
+        assert!(selections_equal(&snapshot, &snapshot.clone()));
+    }
+
+    #[test]
+    fn differing_selections() {
+        assert!(!selections_equal(
+            &bytes(b"Hello", &["text/plain"]),
+            &bytes(b"World", &["text/plain"])
+        ));
+        assert!(!selections_equal(
+            &bytes(b"Hello", &["text/plain"]),
+            &bytes(b"Hello", &["text/plain", "UTF8_STRING"])
+        ));
+        assert!(!selections_equal(&bytes(b"", &[]), &SelectionSnapshot::empty()));
+        // selections with unknown contents cannot be considered equal
+        let first =
+            SelectionSnapshot::compositor(Rc::new(SelectionContent::Callback), strings(&["text/plain"]));
+        let second =
+            SelectionSnapshot::compositor(Rc::new(SelectionContent::Callback), strings(&["text/plain"]));
+        assert!(!selections_equal(&first, &second));
+    }
+}
@@ -1,26 +1,52 @@
-use std::{cell::RefCell, ops::Deref as _, os::unix::io::RawFd, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    ops::Deref as _,
+    os::unix::io::RawFd,
+    rc::Rc,
+};
 
 use wayland_server::{
-    protocol::{wl_data_device_manager::DndAction, wl_data_offer, wl_pointer, wl_surface},
-    Main,
+    protocol::{wl_data_device, wl_data_device_manager::DndAction, wl_data_offer, wl_pointer, wl_surface},
+    Filter, Main,
 };
 
 use crate::wayland::seat::{AxisFrame, GrabStartData, PointerGrab, PointerInnerHandle, Seat};
 use crate::wayland::Serial;
 
-use super::{DataDeviceData, SeatData};
+use super::{
+    forget_dead_target, notify, DataDeviceData, DndActionContext, DndAuditRecord, Notification, SeatDataCell,
+};
 
 /// Event generated by the interactions of clients with a server initiated drag'n'drop
 pub enum ServerDndEvent {
-    /// The client chose an action
+    /// The action negotiated with the target changed
+    ///
+    /// This is generated each time the target negotiates a different action, allowing you to
+    /// update the feedback given to the user, and with an empty action when the pointer leaves
+    /// a target which negotiated one.
     Action(DndAction),
     /// The DnD resource was dropped by the user
     ///
     /// After that, the client can still interract with your ressource
-    Dropped,
+    Dropped {
+        /// The surface the resource was dropped on, if any
+        target: Option<wl_surface::WlSurface>,
+        /// The location of the drop, relative to the target surface
+        ///
+        /// It is `(0.0, 0.0)` if there is no target.
+        location: (f64, f64),
+        /// The action negotiated with the target
+        ///
+        /// It is empty if the target did not accept the drop, in which case
+        /// [`ServerDndEvent::Cancelled`] follows.
+        action: DndAction,
+    },
     /// The Dnd was cancelled
     ///
-    /// The client can no longer interact
+    /// This happens if the user dropped it on no surface, if the target did not accept it or
+    /// negotiate an action, or if the target destroyed its offer after the drop without
+    /// finishing the transfer. The client can no longer interact, and you can release the
+    /// data of the drag'n'drop.
     Cancelled,
     /// The client requested for data to be sent
     Send {
@@ -31,35 +57,204 @@ pub enum ServerDndEvent {
     },
     /// The client has finished interacting with the resource
     ///
-    /// This can only happen after the resource was dropped.
+    /// This can only happen after the resource was dropped, when the target declares the
+    /// transfer finished, or for targets older than version 3 of the protocol, which cannot do
+    /// so, when they destroy their offer. You can release the data of the drag'n'drop.
     Finished,
+    /// The user dropped a dry-run drag'n'drop, see [`start_dnd_dry_run`](super::start_dnd_dry_run)
+    ///
+    /// No data is transferred, and the drag'n'drop is cancelled right after this event.
+    DryRunDrop {
+        /// The surface the resource would have been dropped on, if any
+        target: Option<wl_surface::WlSurface>,
+        /// The location of the drop, relative to the target surface
+        ///
+        /// It is `(0.0, 0.0)` if there is no target.
+        location: (f64, f64),
+    },
+}
+
+// The callback of a drag'n'drop started by the compositor, queued with its events as the
+// other notifications of the seat
+pub(crate) trait ServerDndCallback {
+    // invoke the callback, giving the event back if it is already running
+    fn try_call(&self, event: ServerDndEvent) -> Result<(), ServerDndEvent>;
 }
 
-pub(crate) struct ServerDnDGrab<C: 'static> {
+impl<C: FnMut(ServerDndEvent)> ServerDndCallback for RefCell<C> {
+    fn try_call(&self, event: ServerDndEvent) -> Result<(), ServerDndEvent> {
+        match self.try_borrow_mut() {
+            Ok(mut callback) => {
+                (&mut *callback)(event);
+                Ok(())
+            }
+            Err(_) => Err(event),
+        }
+    }
+}
+
+pub(crate) struct ServerDnDGrab<C: FnMut(ServerDndEvent) + 'static> {
     start_data: GrabStartData,
     metadata: super::SourceMetadata,
     current_focus: Option<wl_surface::WlSurface>,
+    // the last location of the pointer, relative to the current target
+    target_location: (f64, f64),
     pending_offers: Vec<wl_data_offer::WlDataOffer>,
+    // the data devices that were sent an `enter` for the current target, devices
+    // created afterwards must not receive the events of this drag'n'drop
+    entered_devices: Vec<wl_data_device::WlDataDevice>,
     offer_data: Option<Rc<RefCell<OfferData>>>,
     seat: Seat,
     callback: Rc<RefCell<C>>,
+    // set when the compositor requested to complete the drop programmatically
+    finish_requested: Rc<Cell<bool>>,
+    finished: bool,
+    // a dry-run drag'n'drop gives feedback to the targets but never transfers any data
+    dry_run: bool,
 }
 
-impl<C: 'static> ServerDnDGrab<C> {
+impl<C: FnMut(ServerDndEvent) + 'static> ServerDnDGrab<C> {
     pub(crate) fn new(
         start_data: GrabStartData,
         metadata: super::SourceMetadata,
         seat: Seat,
         callback: Rc<RefCell<C>>,
+        finish_requested: Rc<Cell<bool>>,
+        dry_run: bool,
     ) -> ServerDnDGrab<C> {
         ServerDnDGrab {
             start_data,
             metadata,
             current_focus: None,
+            target_location: (0.0, 0.0),
             pending_offers: Vec::with_capacity(1),
+            entered_devices: Vec::new(),
             offer_data: None,
             seat,
             callback,
+            finish_requested,
+            finished: false,
+            dry_run,
+        }
+    }
+
+    // If the surface currently targeted by the drag was destroyed, forget about it
+    // and notify its client as if the pointer left it
+    fn check_target_alive(&mut self) {
+        if forget_dead_target(&mut self.current_focus, &mut self.entered_devices) {
+            self.disable_offers();
+        }
+    }
+
+    // Disable the offers made to the current target, which no longer has any action
+    fn disable_offers(&mut self) {
+        self.pending_offers.clear();
+        if let Some(offer_data) = self.offer_data.take() {
+            let had_action = {
+                let mut data = offer_data.borrow_mut();
+                data.active = false;
+                !data.chosen_action.is_empty()
+            };
+            if had_action {
+                self.notify(ServerDndEvent::Action(DndAction::empty()));
+            }
+        }
+    }
+
+    // Send an event to the callback, once it is not running anymore if the compositor ended
+    // the drag'n'drop from it
+    fn notify(&self, event: ServerDndEvent) {
+        notify(&self.seat, Notification::ServerDnd(self.callback.clone(), event));
+    }
+
+    // The location of a drop on the current target, if any
+    fn drop_location(&self) -> (f64, f64) {
+        match self.current_focus {
+            Some(_) => self.target_location,
+            None => (0.0, 0.0),
+        }
+    }
+
+    // End the drag'n'drop, with a drop on the current target if `drop` is true and the
+    // target negotiated an action, cancelling it otherwise
+    fn drop_or_cancel(&mut self, mut drop: bool) {
+        // a drop on a destroyed surface is a drop on empty space
+        self.check_target_alive();
+        if drop && self.dry_run {
+            // report where the drop would have happened, and cancel instead
+            self.notify(ServerDndEvent::DryRunDrop {
+                target: self.current_focus.clone(),
+                location: self.drop_location(),
+            });
+            drop = false;
+        }
+        let seat_data = self.seat.user_data().get::<SeatDataCell>().unwrap().borrow_mut();
+        let validated = match self.offer_data {
+            Some(ref data) if drop => {
+                let data = data.borrow();
+                data.accepted && !(data.negotiates_actions && data.chosen_action.is_empty())
+            }
+            _ => false,
+        };
+        for device in self.entered_devices.drain(..) {
+            if validated {
+                device.drop();
+            } else {
+                device.leave();
+            }
+        }
+        let dnd_audit = seat_data.dnd_audit.clone();
+        ::std::mem::drop(seat_data);
+        let action = match self.offer_data {
+            Some(ref data) if validated => data.borrow().chosen_action,
+            _ => DndAction::empty(),
+        };
+        if let (true, Some(dnd_audit)) = (drop, dnd_audit) {
+            (&mut *dnd_audit.borrow_mut())(DndAuditRecord {
+                source_client: None,
+                target_client: self.current_focus.as_ref().and_then(|s| s.as_ref().client()),
+                mime_types: self.metadata.mime_types.clone(),
+                action,
+            });
+        }
+        if let Some(ref offer_data) = self.offer_data {
+            let mut data = offer_data.borrow_mut();
+            if validated {
+                data.dropped = true;
+            } else {
+                data.active = false;
+            }
+        }
+        if drop {
+            self.notify(ServerDndEvent::Dropped {
+                target: self.current_focus.clone(),
+                location: self.drop_location(),
+                action,
+            });
+        }
+        if !validated {
+            self.notify(ServerDndEvent::Cancelled);
+        }
+    }
+}
+
+impl<C: FnMut(ServerDndEvent) + 'static> Drop for ServerDnDGrab<C> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // the grab was removed before the user dropped: either the compositor ended the
+            // drag'n'drop programmatically, or another pointer grab replaced this one
+            self.drop_or_cancel(self.finish_requested.get());
+        }
+        if let Some(seat_data) = self.seat.user_data().get::<SeatDataCell>() {
+            let mut seat_data = seat_data.borrow_mut();
+            let is_current = seat_data
+                .server_dnd
+                .as_ref()
+                .map(|finish_requested| Rc::ptr_eq(finish_requested, &self.finish_requested))
+                .unwrap_or(false);
+            if is_current {
+                seat_data.server_dnd = None;
+            }
         }
     }
 }
@@ -76,84 +271,90 @@ where
         serial: Serial,
         time: u32,
     ) {
+        self.check_target_alive();
         let (x, y) = location;
-        let seat_data = self
-            .seat
-            .user_data()
-            .get::<RefCell<SeatData>>()
-            .unwrap()
-            .borrow_mut();
         if focus.as_ref().map(|&(ref s, _)| s) != self.current_focus.as_ref() {
             // focus changed, we need to make a leave if appropriate
-            if let Some(surface) = self.current_focus.take() {
-                for device in &seat_data.known_devices {
-                    if device.as_ref().same_client_as(&surface.as_ref()) {
-                        device.leave();
-                    }
-                }
-                // disable the offers
-                self.pending_offers.clear();
-                if let Some(offer_data) = self.offer_data.take() {
-                    offer_data.borrow_mut().active = false;
+            if self.current_focus.take().is_some() {
+                for device in self.entered_devices.drain(..) {
+                    device.leave();
                 }
+                self.disable_offers();
             }
         }
+        let seat_data = self.seat.user_data().get::<SeatDataCell>().unwrap().borrow_mut();
         if let Some((surface, (sx, sy))) = focus {
             // early return if the surface is no longer valid
             let client = match surface.as_ref().client() {
                 Some(c) => c,
                 None => return,
             };
+            self.target_location = (x - sx, y - sy);
             if self.current_focus.is_none() {
                 // We entered a new surface, send the data offer
                 let offer_data = Rc::new(RefCell::new(OfferData {
                     active: true,
                     dropped: false,
-                    accepted: true,
+                    finished: false,
+                    // the target must accept a mime type for the drop to proceed
+                    accepted: false,
+                    accepted_mime_type: None,
                     chosen_action: DndAction::empty(),
+                    negotiates_actions: false,
+                    dry_run: self.dry_run,
                 }));
                 for device in seat_data
                     .known_devices
                     .iter()
                     .filter(|d| d.as_ref().same_client_as(&surface.as_ref()))
                 {
-                    let action_choice = device
-                        .as_ref()
-                        .user_data()
-                        .get::<DataDeviceData>()
-                        .unwrap()
-                        .action_choice
-                        .clone();
+                    // the chooser of the seat takes precedence over the one of the global
+                    let action_choice = seat_data.action_chooser.clone().unwrap_or_else(|| {
+                        device
+                            .as_ref()
+                            .user_data()
+                            .get::<DataDeviceData>()
+                            .unwrap()
+                            .action_choice
+                            .clone()
+                    });
+                    if device.as_ref().version() >= 3 {
+                        offer_data.borrow_mut().negotiates_actions = true;
+                    }
                     // create a data offer
-                    let offer = client
+                    let offer = match client
                         .create_resource::<wl_data_offer::WlDataOffer>(device.as_ref().version())
-                        .map(|offer| {
-                            implement_dnd_data_offer(
-                                offer,
-                                self.metadata.clone(),
-                                offer_data.clone(),
-                                self.callback.clone(),
-                                action_choice,
-                            )
-                        })
-                        .unwrap();
+                    {
+                        Some(offer) => implement_dnd_data_offer(
+                            offer,
+                            self.metadata.clone(),
+                            offer_data.clone(),
+                            self.callback.clone(),
+                            action_choice,
+                            self.seat.clone(),
+                            seat_data.log.clone(),
+                        ),
+                        None => continue,
+                    };
                     // advertize the offer to the client
                     device.data_offer(&offer);
                     for mime_type in self.metadata.mime_types.iter().cloned() {
                         offer.offer(mime_type);
                     }
-                    offer.source_actions(self.metadata.dnd_action);
+                    // `source_actions` only exists since version 3
+                    if offer.as_ref().version() >= 3 {
+                        offer.source_actions(self.metadata.dnd_action);
+                    }
                     device.enter(serial.into(), &surface, x - sx, y - sy, Some(&offer));
+                    self.entered_devices.push(device.clone());
                     self.pending_offers.push(offer);
                 }
                 self.offer_data = Some(offer_data);
                 self.current_focus = Some(surface);
             } else {
                 // make a move
-                for device in &seat_data.known_devices {
-                    if device.as_ref().same_client_as(&surface.as_ref()) {
-                        device.motion(time, x - sx, y - sy);
-                    }
+                for device in &self.entered_devices {
+                    device.motion(time, x - sx, y - sy);
                 }
             }
         }
@@ -169,44 +370,10 @@ where
     ) {
         if handle.current_pressed().is_empty() {
             // the user dropped, proceed to the drop
-            let seat_data = self
-                .seat
-                .user_data()
-                .get::<RefCell<SeatData>>()
-                .unwrap()
-                .borrow_mut();
-            let validated = if let Some(ref data) = self.offer_data {
-                let data = data.borrow();
-                data.accepted && (!data.chosen_action.is_empty())
-            } else {
-                false
-            };
-            if let Some(ref surface) = self.current_focus {
-                for device in &seat_data.known_devices {
-                    if device.as_ref().same_client_as(surface.as_ref()) {
-                        if validated {
-                            device.drop();
-                        } else {
-                            device.leave();
-                        }
-                    }
-                }
-            }
-            if let Some(ref offer_data) = self.offer_data {
-                let mut data = offer_data.borrow_mut();
-                if validated {
-                    data.dropped = true;
-                } else {
-                    data.active = false;
-                }
-            }
-            let mut callback = self.callback.borrow_mut();
-            (&mut *callback)(ServerDndEvent::Dropped);
-            if !validated {
-                (&mut *callback)(ServerDndEvent::Cancelled);
-            }
+            self.drop_or_cancel(true);
             // in all cases abandon the drop
             // no more buttons are pressed, release the grab
+            self.finished = true;
             handle.unset_grab(serial, time);
         }
     }
@@ -224,8 +391,15 @@ where
 struct OfferData {
     active: bool,
     dropped: bool,
+    finished: bool,
     accepted: bool,
+    // the last mime type accepted by the target, the only one it can receive
+    accepted_mime_type: Option<String>,
     chosen_action: DndAction,
+    // whether both sides negotiate an action (version 3 and later), only
+    // then a drop without chosen action is meaningless
+    negotiates_actions: bool,
+    dry_run: bool,
 }
 
 fn implement_dnd_data_offer<C>(
@@ -233,56 +407,73 @@ fn implement_dnd_data_offer<C>(
     metadata: super::SourceMetadata,
     offer_data: Rc<RefCell<OfferData>>,
     callback: Rc<RefCell<C>>,
-    action_choice: Rc<RefCell<dyn FnMut(DndAction, DndAction) -> DndAction + 'static>>,
+    action_choice: Rc<RefCell<dyn FnMut(&DndActionContext<'_>) -> DndAction + 'static>>,
+    seat: Seat,
+    log: ::slog::Logger,
 ) -> wl_data_offer::WlDataOffer
 where
     C: FnMut(ServerDndEvent) + 'static,
 {
+    // the compositor may end the drag'n'drop from its callback, the offer data must be
+    // released before notifying it
+    let send = move |event| notify(&seat, Notification::ServerDnd(callback.clone(), event));
+    let destructor_send = send.clone();
     use self::wl_data_offer::Request;
     offer.quick_assign(move |offer, req, _| {
         let mut data = offer_data.borrow_mut();
         match req {
             Request::Accept { mime_type, .. } => {
-                if let Some(mtype) = mime_type {
-                    data.accepted = metadata.mime_types.contains(&mtype);
-                } else {
-                    data.accepted = false;
-                }
+                data.accepted = match mime_type {
+                    Some(ref mtype) => metadata.mime_types.contains(mtype),
+                    None => false,
+                };
+                data.accepted_mime_type = mime_type.filter(|_| data.accepted);
             }
             Request::Receive { mime_type, fd } => {
-                // check if the source and associated mime type is still valid
-                if metadata.mime_types.contains(&mime_type) && data.active {
-                    (&mut *callback.borrow_mut())(ServerDndEvent::Send { mime_type, fd });
+                // the target can only receive the mime type it accepted
+                if data.accepted_mime_type.as_ref() != Some(&mime_type) {
+                    debug!(
+                        log,
+                        "Denying a wl_data_offer.receive for a mime type that was not accepted."
+                    );
+                    super::close_fd(fd);
+                    return;
+                }
+                // check if the source and associated mime type is still valid, the
+                // target must also not request data anymore once it declared the
+                // transfer finished, and no data is ever transferred for a dry-run
+                if metadata.mime_types.contains(&mime_type) && data.active && !data.finished && !data.dry_run
+                {
+                    ::std::mem::drop(data);
+                    send(ServerDndEvent::Send { mime_type, fd });
+                } else {
+                    super::close_fd(fd);
                 }
             }
             Request::Destroy => {}
             Request::Finish => {
-                if !data.active {
-                    offer.as_ref().post_error(
-                        wl_data_offer::Error::InvalidFinish as u32,
-                        "Cannot finish a data offer that is no longer active.".into(),
-                    );
-                }
-                if !data.accepted {
-                    offer.as_ref().post_error(
-                        wl_data_offer::Error::InvalidFinish as u32,
-                        "Cannot finish a data offer that has not been accepted.".into(),
-                    );
-                }
-                if !data.dropped {
-                    offer.as_ref().post_error(
-                        wl_data_offer::Error::InvalidFinish as u32,
-                        "Cannot finish a data offer that has not been dropped.".into(),
-                    );
-                }
-                if data.chosen_action.is_empty() {
-                    offer.as_ref().post_error(
-                        wl_data_offer::Error::InvalidFinish as u32,
-                        "Cannot finish a data offer with no valid action.".into(),
-                    );
+                let error = if !data.active {
+                    Some("Cannot finish a data offer that is no longer active.")
+                } else if !data.accepted {
+                    Some("Cannot finish a data offer that has not been accepted.")
+                } else if !data.dropped {
+                    Some("Cannot finish a data offer that has not been dropped.")
+                } else if data.chosen_action.is_empty() {
+                    Some("Cannot finish a data offer with no valid action.")
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    // the protocol error disconnects the target, the transfer must not be reported as done
+                    offer
+                        .as_ref()
+                        .post_error(wl_data_offer::Error::InvalidFinish as u32, error.into());
+                    return;
                 }
-                (&mut *callback.borrow_mut())(ServerDndEvent::Finished);
                 data.active = false;
+                data.finished = true;
+                ::std::mem::drop(data);
+                send(ServerDndEvent::Finished);
             }
             Request::SetActions {
                 dnd_actions,
@@ -294,19 +485,56 @@ where
                         wl_data_offer::Error::InvalidAction as u32,
                         "Invalid preferred action.".into(),
                     );
+                    return;
                 }
                 let possible_actions = metadata.dnd_action & dnd_actions;
-                data.chosen_action = (&mut *action_choice.borrow_mut())(possible_actions, preferred_action);
-                // check that the user provided callback respects that one precise action should be chosen
-                debug_assert!(
-                    [DndAction::Move, DndAction::Copy, DndAction::Ask].contains(&data.chosen_action)
-                );
+                let context = DndActionContext {
+                    available: possible_actions,
+                    preferred: preferred_action,
+                    source_client: None,
+                    target_client: offer.as_ref().client(),
+                    mime_types: &metadata.mime_types,
+                };
+                let previous_action = data.chosen_action;
+                data.chosen_action = (&mut *action_choice.borrow_mut())(&context);
+                // check that the user provided callback respects that one precise action should be chosen,
+                // or none if the target has no action in common with the drag'n'drop
+                debug_assert!([
+                    DndAction::empty(),
+                    DndAction::Move,
+                    DndAction::Copy,
+                    DndAction::Ask
+                ]
+                .contains(&data.chosen_action));
                 offer.action(data.chosen_action);
-                (&mut *callback.borrow_mut())(ServerDndEvent::Action(data.chosen_action));
+                if data.chosen_action != previous_action {
+                    let action = data.chosen_action;
+                    ::std::mem::drop(data);
+                    send(ServerDndEvent::Action(action));
+                }
             }
             _ => unreachable!(),
         }
     });
+    // a target destroying the offer (or disconnecting) after the drop ends the transfer: it is
+    // aborted if the target could have finished it, and complete before version 3
+    let destructor_data = offer_data.clone();
+    offer.assign_destructor(Filter::new(move |_offer: wl_data_offer::WlDataOffer, _, _| {
+        let event = {
+            let mut data = destructor_data.borrow_mut();
+            if !(data.active && data.dropped && !data.finished) {
+                return;
+            }
+            data.active = false;
+            if data.negotiates_actions {
+                ServerDndEvent::Cancelled
+            } else {
+                data.finished = true;
+                ServerDndEvent::Finished
+            }
+        };
+        destructor_send(event);
+    }));
 
     offer.deref().clone()
 }
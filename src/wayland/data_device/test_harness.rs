@@ -0,0 +1,865 @@
+//! Utilities to test the data device logic against mock clients
+//!
+//! This module is only available with the `test_utils` cargo feature. It provides a
+//! [`DataDeviceHarness`] creating a [`Display`] with a seat, and mock clients speaking the
+//! wire protocol over a socket, so that your tests can set and paste selections or drive a
+//! drag'n'drop from the point of view of the clients:
+//!
+//! ```no_run
+//! # use smithay::wayland::data_device::test_harness::DataDeviceHarness;
+//! let mut harness = DataDeviceHarness::new();
+//! let copier = harness.add_client();
+//! let paster = harness.add_client();
+//!
+//! harness.focus(Some(copier));
+//! harness.set_selection(copier, &[("text/plain", b"Hello world!")]);
+//! harness.focus(Some(paster));
+//! assert_eq!(harness.paste(paster, "text/plain").unwrap(), b"Hello world!");
+//! ```
+//!
+//! The mock clients bind the data device manager global of the harness, created with the
+//! configuration given to it, and the events their data devices generate are available through
+//! [`take_events`](DataDeviceHarness::take_events).
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Write},
+    ops::Deref as _,
+    os::unix::{
+        io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+        net::UnixStream,
+    },
+    rc::Rc,
+    time::Duration,
+};
+
+use nix::{
+    fcntl::{fcntl, FcntlArg, OFlag},
+    sys::{
+        socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags},
+        uio::IoVec,
+    },
+};
+use wayland_server::{
+    protocol::{wl_data_device_manager::DndAction, wl_pointer::ButtonState, wl_surface},
+    Client, Display,
+};
+
+use crate::wayland::{
+    compositor::{compositor_init, CompositorToken},
+    seat::{KeyboardHandle, PointerHandle, Seat},
+    Serial,
+};
+
+use super::{
+    default_action_chooser, init_data_device_with_config, set_data_device_focus,
+    set_data_device_follows_keyboard_focus, set_data_device_selection, DataDeviceConfig, DataDeviceEvent,
+};
+
+mod roles {
+    use crate::define_roles;
+
+    define_roles!(HarnessRoles =>
+        [DnDIcon, crate::wayland::data_device::DnDIconRole]
+        [CursorImage, crate::wayland::seat::CursorImageRole]
+    );
+}
+
+pub(crate) use self::roles::HarnessRoles;
+
+// the button pressed to start a drag'n'drop, `BTN_LEFT`
+const DRAG_BUTTON: u32 = 0x110;
+
+/// A handle to a mock client of a [`DataDeviceHarness`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockClientId(usize);
+
+// The state of a mock client, as tracked from the events it receives
+//
+// The objects the client creates are only known by their ids, the next one being allocated
+// for each new object like a real client would.
+struct MockClient {
+    client: Client,
+    socket: UnixStream,
+    surface: wl_surface::WlSurface,
+    next_id: u32,
+    // the globals advertised to the client, as (name, interface, version)
+    globals: Vec<(u32, String, u32)>,
+    registry: u32,
+    manager: u32,
+    seat: u32,
+    data_device: u32,
+    // the sources it created, with the contents it serves for each mime type
+    sources: Vec<(u32, HashMap<String, Vec<u8>>)>,
+    // the mime types advertised by each offer it was sent
+    offers: HashMap<u32, Vec<String>>,
+    selection: Option<u32>,
+    // the offer of the drag'n'drop over its surface, with the serial of its enter event
+    dnd_offer: Option<(u32, u32)>,
+    dropped_offer: Option<u32>,
+    events: Vec<(u32, u16)>,
+}
+
+/// A display with a seat and mock clients, to test the data device logic
+///
+/// Each mock client has a surface, and a data device for the seat of the harness it gets
+/// from the data device manager global, like a real client would. The harness dispatches the
+/// display and processes the events of the clients after each interaction. The contents of
+/// the selections and drag'n'drops are written by the harness on behalf of their client, and
+/// the contents of the selections set with
+/// [`set_compositor_selection`](DataDeviceHarness::set_compositor_selection) on behalf of your
+/// compositor.
+pub struct DataDeviceHarness {
+    display: Display,
+    seat: Seat,
+    keyboard: KeyboardHandle,
+    pointer: PointerHandle,
+    events: Rc<RefCell<Vec<DataDeviceEvent>>>,
+    compositor_contents: Rc<RefCell<HashMap<String, Vec<u8>>>>,
+    clients: Vec<MockClient>,
+    serial: u32,
+}
+
+impl DataDeviceHarness {
+    /// Create a harness with the default data device configuration
+    pub fn new() -> DataDeviceHarness {
+        DataDeviceHarness::with_config(DataDeviceConfig::default())
+    }
+
+    /// Create a harness whose data devices use this configuration
+    pub fn with_config(config: DataDeviceConfig) -> DataDeviceHarness {
+        let mut display = Display::new();
+        let (mut seat, token) = create_seat(&mut display);
+        let keyboard = seat
+            .add_keyboard(Default::default(), 200, 25, |_, _| {})
+            .expect("Failed to initialize the keyboard");
+        let pointer = seat.add_pointer(token, |_| {});
+        if config.follow_keyboard_focus {
+            set_data_device_follows_keyboard_focus(&seat, true);
+        }
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let compositor_contents = Rc::new(RefCell::new(HashMap::<String, Vec<u8>>::new()));
+        let (events2, compositor_contents2) = (events.clone(), compositor_contents.clone());
+        init_data_device_with_config(
+            &mut display,
+            move |event| match event {
+                DataDeviceEvent::SendSelection { mime_type, fd } => {
+                    let contents = compositor_contents2
+                        .borrow()
+                        .get(&mime_type)
+                        .cloned()
+                        .unwrap_or_default();
+                    write_in_background(fd, contents);
+                }
+                event => events2.borrow_mut().push(event),
+            },
+            default_action_chooser,
+            token,
+            config,
+            seat.arc.log.clone(),
+        );
+        DataDeviceHarness {
+            display,
+            seat,
+            keyboard,
+            pointer,
+            events,
+            compositor_contents,
+            clients: Vec::new(),
+            serial: 0,
+        }
+    }
+
+    /// Access the display of the harness
+    pub fn display(&mut self) -> &mut Display {
+        &mut self.display
+    }
+
+    /// Access the seat of the harness
+    pub fn seat(&self) -> &Seat {
+        &self.seat
+    }
+
+    /// Access the server side of a mock client
+    pub fn client(&self, id: MockClientId) -> &Client {
+        &self.clients[id.0].client
+    }
+
+    /// Access the surface of a mock client
+    pub fn surface(&self, id: MockClientId) -> &wl_surface::WlSurface {
+        &self.clients[id.0].surface
+    }
+
+    /// Connect a new mock client, with a surface and a data device for the seat
+    ///
+    /// The client binds the seat and the data device manager globals, and gets its data
+    /// device from the manager.
+    pub fn add_client(&mut self) -> MockClientId {
+        let (client, socket) = create_client(&mut self.display);
+        let surface = client
+            .create_resource::<wl_surface::WlSurface>(4)
+            .expect("The mock client is not alive")
+            .deref()
+            .clone();
+        let mut mock = MockClient {
+            client,
+            socket,
+            surface,
+            next_id: 2,
+            globals: Vec::new(),
+            registry: 0,
+            manager: 0,
+            seat: 0,
+            data_device: 0,
+            sources: Vec::new(),
+            offers: HashMap::new(),
+            selection: None,
+            dnd_offer: None,
+            dropped_offer: None,
+            events: Vec::new(),
+        };
+        mock.registry = mock.new_id();
+        // (wl_display.get_registry is the second request of the interface)
+        send_int_request(&mut self.display, &mut mock.socket, 1, 1, &[mock.registry]);
+        let (messages, fds) = read_messages(&mut self.display, &mut mock.socket);
+        mock.process(messages, fds);
+        mock.seat = mock.bind(&mut self.display, "wl_seat");
+        mock.manager = mock.bind(&mut self.display, "wl_data_device_manager");
+        mock.data_device = mock.new_id();
+        // (wl_data_device_manager.get_data_device is the second request of the interface)
+        send_int_request(
+            &mut self.display,
+            &mut mock.socket,
+            mock.manager,
+            1,
+            &[mock.data_device, mock.seat],
+        );
+        self.clients.push(mock);
+        let id = MockClientId(self.clients.len() - 1);
+        self.roundtrip();
+        id
+    }
+
+    /// Give the keyboard and data device focus to a mock client, or to nobody
+    pub fn focus(&mut self, id: Option<MockClientId>) {
+        let serial = self.next_serial();
+        let surface = id.map(|id| self.clients[id.0].surface.clone());
+        self.keyboard.set_focus(surface.as_ref(), serial);
+        set_data_device_focus(&self.seat, id.map(|id| self.clients[id.0].client.clone()));
+        self.roundtrip();
+    }
+
+    /// Make a mock client set the selection to these contents, by mime type
+    ///
+    /// This goes through `wl_data_device.set_selection`, using the serial of the last focus
+    /// change, so it is only accepted if the client has the keyboard focus.
+    ///
+    /// Returns the id of the source of the client, as used by
+    /// [`take_client_events`](DataDeviceHarness::take_client_events).
+    pub fn set_selection(&mut self, id: MockClientId, contents: &[(&str, &[u8])]) -> u32 {
+        let source = self.create_source(id, contents);
+        let client = &mut self.clients[id.0];
+        // (wl_data_device.set_selection is the second request of the interface)
+        send_int_request(
+            &mut self.display,
+            &mut client.socket,
+            client.data_device,
+            1,
+            &[source, self.serial],
+        );
+        self.roundtrip();
+        source
+    }
+
+    /// Set a compositor selection with these contents, by mime type
+    ///
+    /// This uses [`set_data_device_selection`], the harness writing the contents when your
+    /// compositor would receive a [`DataDeviceEvent::SendSelection`].
+    pub fn set_compositor_selection(&mut self, contents: &[(&str, &[u8])]) {
+        *self.compositor_contents.borrow_mut() = contents
+            .iter()
+            .map(|&(mime_type, data)| (mime_type.to_owned(), data.to_vec()))
+            .collect();
+        set_data_device_selection(
+            &self.seat,
+            contents
+                .iter()
+                .map(|&(mime_type, _)| mime_type.to_owned())
+                .collect(),
+        );
+        self.roundtrip();
+    }
+
+    /// The mime types of the selection last advertised to a mock client
+    ///
+    /// Returns `None` if the client was last advertised an empty selection.
+    pub fn selection_mime_types(&mut self, id: MockClientId) -> Option<Vec<String>> {
+        self.roundtrip();
+        let client = &self.clients[id.0];
+        client
+            .selection
+            .and_then(|offer| client.offers.get(&offer).cloned())
+    }
+
+    /// Make a mock client paste its selection in a given mime type
+    ///
+    /// Returns an error of kind `NotFound` if the client was not advertised any selection, and
+    /// of kind `TimedOut` if the contents were not fully written after a second.
+    pub fn paste(&mut self, id: MockClientId, mime_type: &str) -> io::Result<Vec<u8>> {
+        self.roundtrip();
+        match self.clients[id.0].selection {
+            Some(offer) => self.receive(id, offer, mime_type),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the client has no selection",
+            )),
+        }
+    }
+
+    /// Make a mock client start a drag'n'drop of these contents, by mime type, from its surface
+    ///
+    /// The pointer is moved over the surface of the client and its button pressed, and the
+    /// client requests the drag'n'drop with the serial of this press.
+    pub fn start_drag(&mut self, id: MockClientId, contents: &[(&str, &[u8])], actions: DndAction) {
+        let source = self.create_source(id, contents);
+        let client = &mut self.clients[id.0];
+        // (wl_data_source.set_actions is the third request of the interface)
+        send_int_request(
+            &mut self.display,
+            &mut client.socket,
+            source,
+            2,
+            &[actions.bits()],
+        );
+        let surface = client.surface.clone();
+        let serial = self.next_serial();
+        self.pointer.motion(
+            (0.0, 0.0),
+            Some((surface.clone(), (0.0, 0.0))),
+            serial,
+            self.serial,
+        );
+        let serial = self.next_serial();
+        self.pointer
+            .button(DRAG_BUTTON, ButtonState::Pressed, serial, self.serial);
+        let client = &mut self.clients[id.0];
+        // (wl_data_device.start_drag is the first request of the interface)
+        send_int_request(
+            &mut self.display,
+            &mut client.socket,
+            client.data_device,
+            0,
+            &[source, surface.as_ref().id(), 0, serial.into()],
+        );
+        self.roundtrip();
+    }
+
+    /// Move the pointer to a location of the surface of a mock client, or out of every surface
+    pub fn move_pointer(&mut self, target: Option<MockClientId>, location: (f64, f64)) {
+        let focus = target.map(|id| (self.clients[id.0].surface.clone(), (0.0, 0.0)));
+        let serial = self.next_serial();
+        self.pointer.motion(location, focus, serial, self.serial);
+        self.roundtrip();
+    }
+
+    /// The mime types of the drag'n'drop currently over the surface of a mock client
+    ///
+    /// Returns `None` if no drag'n'drop entered its surface.
+    pub fn dnd_mime_types(&mut self, id: MockClientId) -> Option<Vec<String>> {
+        self.roundtrip();
+        let client = &self.clients[id.0];
+        client
+            .dnd_offer
+            .and_then(|(offer, _)| client.offers.get(&offer).cloned())
+    }
+
+    /// Make a mock client accept the drag'n'drop over its surface with a mime type and an action
+    ///
+    /// Accepting `None` tells that the client would not accept a drop.
+    pub fn accept_dnd(&mut self, id: MockClientId, mime_type: Option<&str>, action: DndAction) {
+        let client = &mut self.clients[id.0];
+        let (offer, serial) = match client.dnd_offer {
+            Some(offer) => offer,
+            None => return,
+        };
+        // (wl_data_offer.accept and set_actions are the first and fifth requests of the interface)
+        let mut accept = vec![serial];
+        match mime_type {
+            Some(mime_type) => accept.extend(string_arg(mime_type)),
+            None => accept.push(0),
+        }
+        send_int_request(&mut self.display, &mut client.socket, offer, 0, &accept);
+        send_int_request(
+            &mut self.display,
+            &mut client.socket,
+            offer,
+            4,
+            &[action.bits(), action.bits()],
+        );
+        self.roundtrip();
+    }
+
+    /// Release the pointer button, dropping the current drag'n'drop
+    pub fn release_pointer(&mut self) {
+        let serial = self.next_serial();
+        self.pointer
+            .button(DRAG_BUTTON, ButtonState::Released, serial, self.serial);
+        self.roundtrip();
+    }
+
+    /// Make a mock client read the drag'n'drop dropped on its surface in a given mime type
+    ///
+    /// This fails like [`paste`](DataDeviceHarness::paste), with an error of kind `NotFound`
+    /// if nothing was dropped on its surface.
+    pub fn receive_drop(&mut self, id: MockClientId, mime_type: &str) -> io::Result<Vec<u8>> {
+        self.roundtrip();
+        match self.clients[id.0].dropped_offer {
+            Some(offer) => self.receive(id, offer, mime_type),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "nothing was dropped")),
+        }
+    }
+
+    /// Make a mock client finish the drag'n'drop dropped on its surface
+    pub fn finish_drop(&mut self, id: MockClientId) {
+        let client = &mut self.clients[id.0];
+        if let Some(offer) = client.dropped_offer.take() {
+            // (wl_data_offer.finish is the fourth request of the interface)
+            send_int_request(&mut self.display, &mut client.socket, offer, 3, &[]);
+        }
+        self.roundtrip();
+    }
+
+    /// Take the events sent to a mock client so far, as a list of (object id, opcode)
+    pub fn take_client_events(&mut self, id: MockClientId) -> Vec<(u32, u16)> {
+        self.roundtrip();
+        ::std::mem::replace(&mut self.clients[id.0].events, Vec::new())
+    }
+
+    /// Take the events the data devices generated so far
+    ///
+    /// The [`DataDeviceEvent::SendSelection`] events are handled by the harness, and not
+    /// reported.
+    pub fn take_events(&mut self) -> Vec<DataDeviceEvent> {
+        ::std::mem::replace(&mut *self.events.borrow_mut(), Vec::new())
+    }
+
+    fn next_serial(&mut self) -> Serial {
+        self.serial += 1;
+        Serial::from(self.serial)
+    }
+
+    // Create a data source from the data device manager on behalf of a mock client, offering
+    // these contents, and return its id
+    fn create_source(&mut self, id: MockClientId, contents: &[(&str, &[u8])]) -> u32 {
+        let client = &mut self.clients[id.0];
+        let source = client.new_id();
+        // (wl_data_device_manager.create_data_source is the first request of the interface)
+        send_int_request(
+            &mut self.display,
+            &mut client.socket,
+            client.manager,
+            0,
+            &[source],
+        );
+        for &(mime_type, _) in contents {
+            // (wl_data_source.offer is the first request of the interface)
+            send_int_request(
+                &mut self.display,
+                &mut client.socket,
+                source,
+                0,
+                &string_arg(mime_type),
+            );
+        }
+        let contents = contents
+            .iter()
+            .map(|&(mime_type, data)| (mime_type.to_owned(), data.to_vec()))
+            .collect();
+        client.sources.push((source, contents));
+        source
+    }
+
+    // Read an offer on behalf of a mock client
+    fn receive(&mut self, id: MockClientId, offer: u32, mime_type: &str) -> io::Result<Vec<u8>> {
+        let (read_fd, write_fd) = ::nix::unistd::pipe2(OFlag::O_CLOEXEC).map_err(nix_error)?;
+        let mut reader = unsafe { File::from_raw_fd(read_fd) };
+        fcntl(read_fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).map_err(nix_error)?;
+        let client = &mut self.clients[id.0];
+        // (wl_data_offer.receive is the second request of the interface)
+        send_request(&mut self.display, &client.socket, offer, 1, mime_type, write_fd);
+        let _ = ::nix::unistd::close(write_fd);
+
+        let mut contents = Vec::new();
+        let mut buffer = [0u8; 4096];
+        for _ in 0..1000 {
+            self.roundtrip();
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => return Ok(contents),
+                    Ok(n) => contents.extend_from_slice(&buffer[..n]),
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                }
+            }
+            ::std::thread::sleep(Duration::from_millis(1));
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the contents were not fully written",
+        ))
+    }
+
+    // Dispatch the requests of the mock clients, and process the events they were sent
+    fn roundtrip(&mut self) {
+        self.display
+            .dispatch(Duration::from_millis(0), &mut ())
+            .expect("Failed to dispatch the display");
+        let (display, clients) = (&mut self.display, &mut self.clients);
+        for client in clients.iter_mut() {
+            let (messages, fds) = read_messages(display, &mut client.socket);
+            client.process(messages, fds);
+        }
+    }
+}
+
+impl Default for DataDeviceHarness {
+    fn default() -> DataDeviceHarness {
+        DataDeviceHarness::new()
+    }
+}
+
+impl MockClient {
+    // Allocate the id of a new object of the client
+    fn new_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    // Bind a global advertised to the client at the version it advertised, and return the id
+    // of the new object
+    fn bind(&mut self, display: &mut Display, interface: &str) -> u32 {
+        let (name, version) = self
+            .globals
+            .iter()
+            .find(|&&(_, ref global, _)| global == interface)
+            .map(|&(name, _, version)| (name, version))
+            .expect("The global is not advertised");
+        let id = self.new_id();
+        // (wl_registry.bind is the first request of the interface, its new id argument of
+        // unspecified interface is preceded by the interface and version)
+        let mut args = vec![name];
+        args.extend(string_arg(interface));
+        args.extend(&[version, id]);
+        send_int_request(display, &mut self.socket, self.registry, 0, &args);
+        id
+    }
+
+    fn process(&mut self, messages: Vec<Message>, fds: Vec<RawFd>) {
+        let mut fds = fds.into_iter();
+        let device = self.data_device;
+        for message in messages {
+            self.events.push((message.sender, message.opcode));
+            let source = self.sources.iter().find(|&&(source, _)| source == message.sender);
+            if message.sender == self.registry {
+                // global, with the name, interface and version of the global
+                if message.opcode == 0 && message.args.len() >= 3 {
+                    let version = message.args[message.args.len() - 1];
+                    let interface = string_from(&message.args[1..]);
+                    self.globals.push((message.args[0], interface, version));
+                }
+            } else if message.sender == device {
+                match message.opcode {
+                    // data_offer
+                    0 => {
+                        self.offers.insert(message.args[0], Vec::new());
+                    }
+                    // enter
+                    1 => self.dnd_offer = Some((message.args[4], message.args[0])).filter(|&(id, _)| id != 0),
+                    // leave
+                    2 => self.dnd_offer = None,
+                    // drop
+                    4 => self.dropped_offer = self.dnd_offer.map(|(offer, _)| offer),
+                    // selection
+                    5 => self.selection = Some(message.args[0]).filter(|&id| id != 0),
+                    _ => {}
+                }
+            } else if let Some(&(_, ref contents)) = source {
+                // send, the only event carrying an fd
+                if message.opcode == 1 {
+                    if let Some(fd) = fds.next() {
+                        let data = contents.get(&string_from(&message.args)).cloned();
+                        write_in_background(fd, data.unwrap_or_default());
+                    }
+                }
+            } else if message.opcode == 0 {
+                // the offer event of a data offer
+                if let Some(offer) = self.offers.get_mut(&message.sender) {
+                    offer.push(string_from(&message.args));
+                }
+            }
+        }
+        for fd in fds {
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
+}
+
+// Write some contents into an fd and close it, without blocking
+fn write_in_background(fd: RawFd, contents: Vec<u8>) {
+    ::std::thread::spawn(move || {
+        // the reader may not be interested anymore
+        let _ = unsafe { File::from_raw_fd(fd) }.write_all(&contents);
+    });
+}
+
+fn nix_error(err: ::nix::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+// Create a seat on the display, along with the compositor global its clients need
+pub(crate) fn create_seat(display: &mut Display) -> (Seat, CompositorToken<HarnessRoles>) {
+    let (token, _, _) = compositor_init::<HarnessRoles, _, _>(display, |_, _, _| {}, None);
+    let (seat, _) = Seat::new(display, "seat-0".into(), token, None);
+    (seat, token)
+}
+
+// Create a client connected to the display, the other end of its socket is returned
+// as well to keep it alive
+pub(crate) fn create_client(display: &mut Display) -> (Client, UnixStream) {
+    let (server, socket) = UnixStream::pair().expect("Failed to create the client socket");
+    let client = unsafe { display.create_client(server.into_raw_fd(), &mut ()) };
+    (client, socket)
+}
+
+// A message read from the socket of a client
+pub(crate) struct Message {
+    pub(crate) sender: u32,
+    pub(crate) opcode: u16,
+    // the arguments, as words
+    pub(crate) args: Vec<u32>,
+}
+
+// Read the events sent to a client, along with the fds they carry
+pub(crate) fn read_messages(display: &mut Display, socket: &mut UnixStream) -> (Vec<Message>, Vec<RawFd>) {
+    display.flush_clients(&mut ());
+    let mut data = Vec::new();
+    let mut fds = Vec::new();
+    loop {
+        let mut buffer = [0u8; 4096];
+        let mut cmsg_buffer = ::nix::cmsg_space!([RawFd; 28]);
+        let iov = [IoVec::from_mut_slice(&mut buffer)];
+        let msg = match recvmsg(
+            socket.as_raw_fd(),
+            &iov,
+            Some(&mut cmsg_buffer),
+            MsgFlags::MSG_DONTWAIT,
+        ) {
+            Ok(msg) if msg.bytes > 0 => msg,
+            _ => break,
+        };
+        for cmsg in msg.cmsgs() {
+            if let ControlMessageOwned::ScmRights(received) = cmsg {
+                fds.extend(received);
+            }
+        }
+        let bytes = msg.bytes;
+        data.extend_from_slice(&buffer[..bytes]);
+    }
+    let words = data
+        .chunks(4)
+        .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+        .collect::<Vec<u32>>();
+    let mut messages = Vec::new();
+    let mut remaining = &words[..];
+    while remaining.len() >= 2 {
+        let size = ((remaining[1] >> 16) as usize / 4).max(2).min(remaining.len());
+        messages.push(Message {
+            sender: remaining[0],
+            opcode: (remaining[1] & 0xffff) as u16,
+            args: remaining[2..size].to_vec(),
+        });
+        remaining = &remaining[size..];
+    }
+    (messages, fds)
+}
+
+// Read the events sent to a client, as a list of (object id, opcode)
+pub(crate) fn received_events(display: &mut Display, socket: &mut UnixStream) -> Vec<(u32, u16)> {
+    let (messages, fds) = read_messages(display, socket);
+    for fd in fds {
+        let _ = ::nix::unistd::close(fd);
+    }
+    messages
+        .into_iter()
+        .map(|message| (message.sender, message.opcode))
+        .collect()
+}
+
+// The ids of the offers introduced by these devices, in the arguments of their `data_offer`
+// events, the first event of the data devices of every selection protocol
+pub(crate) fn offers_of(messages: &[Message], devices: &[u32]) -> Vec<u32> {
+    messages
+        .iter()
+        .filter(|message| message.opcode == 0 && devices.contains(&message.sender))
+        .filter_map(|message| message.args.first().cloned())
+        .collect()
+}
+
+// Read the events sent to a client, and return the ids of the offers these devices introduced
+pub(crate) fn received_offers(display: &mut Display, socket: &mut UnixStream, devices: &[u32]) -> Vec<u32> {
+    let (messages, fds) = read_messages(display, socket);
+    for fd in fds {
+        let _ = ::nix::unistd::close(fd);
+    }
+    offers_of(&messages, devices)
+}
+
+// Send a request with only integer arguments on behalf of a client
+pub(crate) fn send_int_request(
+    display: &mut Display,
+    socket: &mut UnixStream,
+    id: u32,
+    opcode: u16,
+    args: &[u32],
+) {
+    let size = 8 + 4 * args.len() as u32;
+    let mut message = Vec::new();
+    message.extend_from_slice(&id.to_ne_bytes());
+    message.extend_from_slice(&((size << 16) | u32::from(opcode)).to_ne_bytes());
+    for arg in args {
+        message.extend_from_slice(&arg.to_ne_bytes());
+    }
+    socket.write_all(&message).unwrap();
+    display.dispatch(Duration::from_millis(0), &mut ()).unwrap();
+}
+
+// Send a request with a single string argument and an fd on behalf of a client
+pub(crate) fn send_request(
+    display: &mut Display,
+    socket: &UnixStream,
+    id: u32,
+    opcode: u16,
+    arg: &str,
+    fd: RawFd,
+) {
+    let mut message = Vec::new();
+    let string = string_arg(arg);
+    let size = 8 + 4 * string.len() as u32;
+    message.extend_from_slice(&id.to_ne_bytes());
+    message.extend_from_slice(&((size << 16) | u32::from(opcode)).to_ne_bytes());
+    for word in string {
+        message.extend_from_slice(&word.to_ne_bytes());
+    }
+    sendmsg(
+        socket.as_raw_fd(),
+        &[IoVec::from_slice(&message)],
+        &[ControlMessage::ScmRights(&[fd])],
+        MsgFlags::empty(),
+        None,
+    )
+    .unwrap();
+    display.dispatch(Duration::from_millis(0), &mut ()).unwrap();
+}
+
+// Encode a string as the integer arguments of a request
+pub(crate) fn string_arg(arg: &str) -> Vec<u32> {
+    let mut string = arg.as_bytes().to_vec();
+    string.push(0);
+    let mut args = vec![string.len() as u32];
+    while string.len() % 4 != 0 {
+        string.push(0);
+    }
+    args.extend(
+        string
+            .chunks(4)
+            .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]])),
+    );
+    args
+}
+
+// Decode the string starting the arguments of a message
+fn string_from(args: &[u32]) -> String {
+    let len = match args.first() {
+        Some(&len) => len as usize,
+        None => return String::new(),
+    };
+    let bytes = args[1..]
+        .iter()
+        .flat_map(|word| word.to_ne_bytes().to_vec())
+        .take(len.saturating_sub(1))
+        .collect::<Vec<u8>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_pasted_by_other_client() {
+        let mut harness = DataDeviceHarness::new();
+        let copier = harness.add_client();
+        let paster = harness.add_client();
+
+        harness.focus(Some(copier));
+        harness.set_selection(
+            copier,
+            &[("text/plain", b"Hello world!"), ("text/html", b"<p>Hi</p>")],
+        );
+        // the other client is only advertised the selection once focused
+        assert_eq!(harness.selection_mime_types(paster), None);
+        harness.focus(Some(paster));
+        assert_eq!(
+            harness.selection_mime_types(paster),
+            Some(vec!["text/plain".to_string(), "text/html".to_string()])
+        );
+        assert_eq!(harness.paste(paster, "text/html").unwrap(), b"<p>Hi</p>");
+        assert!(harness
+            .take_events()
+            .iter()
+            .any(|event| matches!(event, DataDeviceEvent::NewSelection(Some(_)))));
+    }
+
+    #[test]
+    fn compositor_selection_pasted() {
+        let mut harness = DataDeviceHarness::new();
+        let paster = harness.add_client();
+        harness.set_compositor_selection(&[("text/plain", b"From the compositor")]);
+        harness.focus(Some(paster));
+        assert_eq!(
+            harness.paste(paster, "text/plain").unwrap(),
+            b"From the compositor"
+        );
+        // an unknown mime type is not served
+        assert!(harness.paste(paster, "image/png").unwrap().is_empty());
+    }
+
+    #[test]
+    fn dropped_between_clients() {
+        let mut harness = DataDeviceHarness::new();
+        let origin = harness.add_client();
+        let target = harness.add_client();
+
+        harness.start_drag(origin, &[("text/plain", b"Dragged")], DndAction::Copy);
+        harness.move_pointer(Some(target), (5.0, 5.0));
+        assert_eq!(
+            harness.dnd_mime_types(target),
+            Some(vec!["text/plain".to_string()])
+        );
+        harness.accept_dnd(target, Some("text/plain"), DndAction::Copy);
+        harness.release_pointer();
+        assert_eq!(harness.receive_drop(target, "text/plain").unwrap(), b"Dragged");
+        harness.finish_drop(target);
+
+        let events = harness.take_events();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, DataDeviceEvent::DnDStarted { .. })));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, DataDeviceEvent::DnDDropped)));
+    }
+}
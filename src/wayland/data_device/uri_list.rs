@@ -0,0 +1,112 @@
+use std::{
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use wayland_server::protocol::wl_data_device_manager::DndAction;
+
+use super::{serve_bytes, ServerDndEvent, SourceMetadata};
+
+const URI_LIST_MIME_TYPE: &str = "text/uri-list";
+
+/// The metadata of a drag'n'drop of some files
+///
+/// It advertises the `text/uri-list` mime type with the copy action, and is meant to be given
+/// to [`start_dnd`](super::start_dnd) along with the callback returned by
+/// [`uri_list_provider`] for the same paths:
+///
+/// ```no_run
+/// # use std::path::PathBuf;
+/// # use smithay::wayland::{data_device::*, seat::{GrabStartData, Seat}, Serial};
+/// # fn drag(seat: &Seat, serial: Serial, start_data: GrabStartData) {
+/// let paths = vec![PathBuf::from("/home/user/Pictures/holidays.png")];
+/// start_dnd(
+///     seat,
+///     serial,
+///     start_data,
+///     uri_list_metadata(&paths),
+///     uri_list_provider(&paths, |event| { /* react to the other events */ }),
+/// );
+/// # }
+/// ```
+///
+/// The metadata does not depend on the paths, which are only taken to mirror
+/// [`uri_list_provider`].
+pub fn uri_list_metadata(_paths: &[PathBuf]) -> SourceMetadata {
+    SourceMetadata {
+        mime_types: vec![URI_LIST_MIME_TYPE.into()],
+        dnd_action: DndAction::Copy,
+    }
+}
+
+/// A callback for [`start_dnd`](super::start_dnd) serving a list of files
+///
+/// The [`ServerDndEvent::Send`] events are handled by writing the `file://` URIs of the
+/// paths, as a `text/uri-list`, without blocking your compositor. All the other events are
+/// forwarded to the provided callback.
+///
+/// The paths are expected to be absolute, as relative paths cannot be expressed as `file://`
+/// URIs.
+pub fn uri_list_provider<C>(paths: &[PathBuf], mut callback: C) -> impl FnMut(ServerDndEvent)
+where
+    C: FnMut(ServerDndEvent) + 'static,
+{
+    let log = crate::slog_or_fallback(None::<::slog::Logger>).new(o!("smithay_module" => "data_device_mgr"));
+    let contents = uri_list(paths);
+    move |event| match event {
+        ServerDndEvent::Send { mime_type, fd } => {
+            if mime_type == URI_LIST_MIME_TYPE {
+                serve_bytes(fd, contents.clone(), &log);
+            } else {
+                super::close_fd(fd);
+            }
+        }
+        event => callback(event),
+    }
+}
+
+// The contents of a `text/uri-list`, with one URI per line terminated by CRLF
+fn uri_list(paths: &[PathBuf]) -> Vec<u8> {
+    let mut contents = Vec::new();
+    for path in paths {
+        contents.extend_from_slice(file_uri(path).as_bytes());
+        contents.extend_from_slice(b"\r\n");
+    }
+    contents
+}
+
+// The `file://` URI of a path, every byte but the unreserved characters and the separators
+// being percent-encoded
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_encoded_uris() {
+        let paths = vec![
+            PathBuf::from("/home/user/notes.txt"),
+            PathBuf::from("/tmp/with space/100%/été#1.png"),
+        ];
+        assert_eq!(
+            ::std::str::from_utf8(&uri_list(&paths)).unwrap(),
+            "file:///home/user/notes.txt\r\nfile:///tmp/with%20space/100%25/%C3%A9t%C3%A9%231.png\r\n"
+        );
+        assert_eq!(
+            uri_list_metadata(&paths).mime_types,
+            vec!["text/uri-list".to_string()]
+        );
+    }
+}
@@ -15,11 +15,13 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub mod compositor;
+pub mod data_control;
 pub mod data_device;
 #[cfg(feature = "backend_drm")]
 pub mod dmabuf;
 pub mod explicit_synchronization;
 pub mod output;
+pub mod primary_selection;
 pub mod seat;
 pub mod shell;
 pub mod shm;
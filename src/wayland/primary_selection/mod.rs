@@ -0,0 +1,748 @@
+//! Utilities for manipulating the primary selection
+//!
+//! The primary selection is a second selection, independent from the clipboard handled by the
+//! [`data_device`](::wayland::data_device) module. It typically holds the text the user last
+//! highlighted, and is pasted with a middle click. This module handles the
+//! `zwp_primary_selection_device_manager_v1` protocol extension.
+//!
+//! As the clipboard, the primary selection is a per-seat notion, and this module provides 2 main
+//! freestanding functions:
+//!
+//! - [`init_primary_selection`](::wayland::primary_selection::init_primary_selection): this function
+//!   must be called during the compositor startup to initialize the primary selection logic
+//! - [`set_primary_focus`](::wayland::primary_selection::set_primary_focus): this function sets
+//!   the primary selection focus for a given seat; you'd typically call it whenever the keyboard
+//!   focus changes, alongside [`set_data_device_focus`](::wayland::data_device::set_data_device_focus)
+//!
+//! Your compositor can also own the primary selection, for example to reflect the text highlighted
+//! in one of its own widgets, using
+//! [`set_primary_selection`](::wayland::primary_selection::set_primary_selection).
+//!
+//! ## Initialization
+//!
+//! ```
+//! # extern crate wayland_server;
+//! # extern crate smithay;
+//! use smithay::wayland::primary_selection::init_primary_selection;
+//!
+//! # let mut display = wayland_server::Display::new();
+//! // init the primary selection:
+//! init_primary_selection(
+//!     &mut display, // the display
+//!     |event| { /* a callback to react to client primary selection actions */ },
+//!     None          // insert a logger here
+//! );
+//! ```
+
+use std::{cell::RefCell, ops::Deref as _, os::unix::io::RawFd, rc::Rc};
+
+use wayland_protocols::unstable::primary_selection::v1::server::{
+    zwp_primary_selection_device_manager_v1::{self, ZwpPrimarySelectionDeviceManagerV1},
+    zwp_primary_selection_device_v1::{self, ZwpPrimarySelectionDeviceV1},
+    zwp_primary_selection_offer_v1::{self, ZwpPrimarySelectionOfferV1},
+    zwp_primary_selection_source_v1::{self, ZwpPrimarySelectionSourceV1},
+};
+use wayland_server::{Client, Display, Filter, Global, Main};
+
+use crate::wayland::{
+    data_device::{accept_offered_mime_type, selection_serial_valid, DataDeviceConfig},
+    seat::Seat,
+    Serial,
+};
+
+/// Events that are generated by interactions of the clients with the primary selection
+pub enum PrimarySelectionEvent {
+    /// A client has set the primary selection
+    NewSelection(Option<ZwpPrimarySelectionSourceV1>),
+    /// A client requested to read the compositor-set primary selection
+    SendSelection {
+        /// the requested mime type
+        mime_type: String,
+        /// the fd to write into
+        fd: RawFd,
+    },
+}
+
+// Serves the requests for a compositor primary selection provided by an other protocol
+// instead of the callback of `init_primary_selection`, it takes ownership of the fd
+pub(crate) type PrimaryForward = RefCell<dyn FnMut(String, RawFd)>;
+
+// Notified of every change of the primary selection
+pub(crate) type PrimaryListener = dyn Fn(&PrimarySnapshot);
+
+#[derive(Clone)]
+enum Selection {
+    Empty,
+    Client(ZwpPrimarySelectionSourceV1),
+    Compositor {
+        mime_types: Vec<String>,
+        forward: Option<Rc<PrimaryForward>>,
+    },
+}
+
+// The primary selection state of a seat, stored separately from the one of the data device,
+// so that the clipboard and the primary selection never affect each other
+struct SeatData {
+    known_devices: Vec<ZwpPrimarySelectionDeviceV1>,
+    selection: Selection,
+    log: ::slog::Logger,
+    current_focus: Option<Client>,
+    listeners: Vec<Rc<PrimaryListener>>,
+}
+
+impl SeatData {
+    fn new(log: ::slog::Logger) -> SeatData {
+        SeatData {
+            known_devices: Vec::new(),
+            selection: Selection::Empty,
+            log,
+            current_focus: None,
+            listeners: Vec::new(),
+        }
+    }
+
+    fn set_selection(&mut self, new_selection: Selection) {
+        if let Selection::Client(ref previous) = self.selection {
+            let superseded = match new_selection {
+                Selection::Client(ref source) => !previous.as_ref().equals(source.as_ref()),
+                Selection::Empty | Selection::Compositor { .. } => true,
+            };
+            // the client losing the primary selection must stop serving it
+            if superseded && previous.as_ref().is_alive() {
+                previous.cancelled();
+            }
+        }
+        self.selection = new_selection;
+        self.selection_changed();
+        self.send_selection();
+    }
+
+    fn selection_changed(&self) {
+        let snapshot = PrimarySnapshot(self.selection.clone());
+        for listener in &self.listeners {
+            listener(&snapshot);
+        }
+    }
+
+    // reset the selection to null if the client holding it dropped it
+    fn sanitize_selection(&mut self) {
+        let cleanup = match self.selection {
+            Selection::Client(ref source) => !source.as_ref().is_alive(),
+            Selection::Empty | Selection::Compositor { .. } => false,
+        };
+        if cleanup {
+            self.selection = Selection::Empty;
+            self.selection_changed();
+        }
+    }
+
+    // write the primary selection into an fd on behalf of an other protocol, the fd is
+    // given back if the callback of `init_primary_selection` must provide the contents
+    fn serve_selection(&mut self, mime_type: &str, fd: RawFd) -> Option<RawFd> {
+        self.sanitize_selection();
+        PrimarySnapshot(self.selection.clone()).serve(mime_type, fd)
+    }
+
+    // the number of primary selection offers this client did not destroy yet
+    fn live_offers(&self, client: &Client) -> usize {
+        self.known_devices
+            .iter()
+            .filter(|device| {
+                device
+                    .as_ref()
+                    .client()
+                    .map(|c| c.equals(client))
+                    .unwrap_or(false)
+            })
+            .filter_map(|device| device.as_ref().user_data().get::<DeviceData>())
+            .map(|device_data| {
+                let mut offers = device_data.offers.borrow_mut();
+                offers.retain(|offer| offer.as_ref().is_alive());
+                offers.len()
+            })
+            .sum()
+    }
+
+    fn set_focus(&mut self, new_focus: Option<Client>) {
+        self.current_focus = new_focus;
+        self.send_selection();
+    }
+
+    fn send_selection(&mut self) {
+        // first sanitize the selection
+        self.sanitize_selection();
+        let client = match self.current_focus.as_ref() {
+            Some(c) => c,
+            None => return,
+        };
+        // then send it to the devices of the focused client
+        for device in &self.known_devices {
+            // skip devices not belonging to our client
+            if !device.as_ref().is_alive()
+                || !device
+                    .as_ref()
+                    .client()
+                    .map(|c| c.equals(client))
+                    .unwrap_or(false)
+            {
+                continue;
+            }
+            let device_data = match device.as_ref().user_data().get::<DeviceData>() {
+                Some(device_data) => device_data,
+                // the device is not initialized, which only happens while its client is
+                // being torn down
+                None => continue,
+            };
+            let creates_offer = match self.selection {
+                Selection::Empty => false,
+                _ => true,
+            };
+            if creates_offer && self.live_offers(client) >= device_data.config.max_selection_offers {
+                // it is sent the primary selection again on the next change or focus, if it
+                // destroyed its previous offers in the meantime
+                debug!(
+                    self.log,
+                    "Not sending the primary selection to a client holding too many offers."
+                );
+                continue;
+            }
+            match self.selection {
+                Selection::Empty => device.selection(None),
+                Selection::Client(ref source) => {
+                    // create a corresponding offer
+                    let offer = match client
+                        .create_resource::<ZwpPrimarySelectionOfferV1>(device.as_ref().version())
+                    {
+                        Some(offer) => offer,
+                        None => {
+                            // the client is disconnecting, nobody is left to send the selection to
+                            debug!(
+                                self.log,
+                                "Not sending the primary selection to a disconnected client."
+                            );
+                            continue;
+                        }
+                    };
+                    let offer_source = source.clone();
+                    let log = self.log.clone();
+                    offer.quick_assign(move |_offer, req, _| {
+                        // selection offers only care about the `receive` request
+                        if let zwp_primary_selection_offer_v1::Request::Receive { mime_type, fd } = req {
+                            // check if the source and associated mime type is still valid
+                            if offer_source.as_ref().is_alive()
+                                && source_mime_types(&offer_source).contains(&mime_type)
+                            {
+                                offer_source.send(mime_type, fd);
+                            } else {
+                                debug!(
+                                    log,
+                                    "Denying a zwp_primary_selection_offer_v1.receive with invalid source."
+                                );
+                            }
+                            let _ = ::nix::unistd::close(fd);
+                        }
+                    });
+                    device_data.offers.borrow_mut().push(offer.deref().clone());
+                    // advertize the offer to the client
+                    device.data_offer(&offer);
+                    for mime_type in source_mime_types(source) {
+                        offer.offer(mime_type);
+                    }
+                    device.selection(Some(&offer));
+                }
+                Selection::Compositor {
+                    ref mime_types,
+                    ref forward,
+                } => {
+                    let offer_mime_types = mime_types.clone();
+                    let offer_forward = forward.clone();
+                    let callback = device_data.callback.clone();
+                    // create a corresponding offer
+                    let offer = match client
+                        .create_resource::<ZwpPrimarySelectionOfferV1>(device.as_ref().version())
+                    {
+                        Some(offer) => offer,
+                        None => {
+                            // the client is disconnecting, nobody is left to send the selection to
+                            debug!(
+                                self.log,
+                                "Not sending the primary selection to a disconnected client."
+                            );
+                            continue;
+                        }
+                    };
+                    let log = self.log.clone();
+                    offer.quick_assign(move |_offer, req, _| {
+                        // selection offers only care about the `receive` request
+                        if let zwp_primary_selection_offer_v1::Request::Receive { mime_type, fd } = req {
+                            // check if the associated mime type is valid
+                            if offer_mime_types.contains(&mime_type) {
+                                match offer_forward {
+                                    Some(ref forward) => (&mut *forward.borrow_mut())(mime_type, fd),
+                                    None => {
+                                        (&mut *callback.borrow_mut())(PrimarySelectionEvent::SendSelection {
+                                            mime_type,
+                                            fd,
+                                        })
+                                    }
+                                }
+                            } else {
+                                debug!(
+                                    log,
+                                    "Denying a zwp_primary_selection_offer_v1.receive with invalid mime type."
+                                );
+                                let _ = ::nix::unistd::close(fd);
+                            }
+                        }
+                    });
+                    device_data.offers.borrow_mut().push(offer.deref().clone());
+                    // advertize the offer to the client
+                    device.data_offer(&offer);
+                    for mime_type in mime_types.iter().cloned() {
+                        offer.offer(mime_type);
+                    }
+                    device.selection(Some(&offer));
+                }
+            }
+        }
+    }
+}
+
+// Access the primary selection state of a seat, initializing it if needed
+fn seat_data(seat: &Seat) -> &RefCell<SeatData> {
+    seat.user_data().insert_if_missing(|| {
+        RefCell::new(SeatData::new(
+            seat.arc.log.new(o!("smithay_module" => "primary_selection_mgr")),
+        ))
+    });
+    seat.user_data().get::<RefCell<SeatData>>().unwrap()
+}
+
+/// Initialize the primary selection global
+///
+/// You need to provide a callback closure, which will receive notifications about the
+/// actions of your clients on the primary selection. See the [`PrimarySelectionEvent`]
+/// type for details about what notifications you can receive.
+pub fn init_primary_selection<C, L>(
+    display: &mut Display,
+    callback: C,
+    logger: L,
+) -> Global<ZwpPrimarySelectionDeviceManagerV1>
+where
+    C: FnMut(PrimarySelectionEvent) + 'static,
+    L: Into<Option<::slog::Logger>>,
+{
+    init_primary_selection_with_config(display, callback, DataDeviceConfig::default(), logger)
+}
+
+/// Initialize the primary selection global with a custom configuration
+///
+/// The primary selection shares the validation of the clipboard, so this uses the
+/// [`DataDeviceConfig`] of the [`data_device`](::wayland::data_device) module. Only its
+/// `max_mime_types`, `strict_selection_serial` and `max_selection_offers` fields apply to the
+/// primary selection, the other ones are ignored.
+pub fn init_primary_selection_with_config<C, L>(
+    display: &mut Display,
+    callback: C,
+    config: DataDeviceConfig,
+    logger: L,
+) -> Global<ZwpPrimarySelectionDeviceManagerV1>
+where
+    C: FnMut(PrimarySelectionEvent) + 'static,
+    L: Into<Option<::slog::Logger>>,
+{
+    let log = crate::slog_or_fallback(logger).new(o!("smithay_module" => "primary_selection_mgr"));
+    let callback = Rc::new(RefCell::new(callback));
+    let config = Rc::new(config);
+    display.create_global(
+        1,
+        Filter::new(move |(manager, _version), _, _| {
+            implement_manager(manager, callback.clone(), config.clone(), log.clone());
+        }),
+    )
+}
+
+/// Set the primary selection focus to a certain client for a given seat
+///
+/// The focused client is sent the current primary selection. Setting the focus to `None`
+/// only stops delivering it, the primary selection itself is kept.
+pub fn set_primary_focus(seat: &Seat, client: Option<Client>) {
+    seat_data(seat).borrow_mut().set_focus(client);
+}
+
+/// Set a compositor-provided primary selection for this seat
+///
+/// You need to provide the available mime types for this selection.
+///
+/// Whenever a client requests to read the primary selection, your callback will
+/// receive a [`PrimarySelectionEvent::SendSelection`] event.
+pub fn set_primary_selection(seat: &Seat, mime_types: Vec<String>) {
+    seat_data(seat).borrow_mut().set_selection(Selection::Compositor {
+        mime_types,
+        forward: None,
+    });
+}
+
+// The primary selection of a seat at a given time, that the offers of the other protocols keep
+// serving once it is replaced, as the offers of this protocol do
+#[derive(Clone)]
+pub(crate) struct PrimarySnapshot(Selection);
+
+impl PrimarySnapshot {
+    // the mime types of the primary selection, `None` if it is empty
+    pub(crate) fn mime_types(&self) -> Option<Vec<String>> {
+        match self.0 {
+            Selection::Empty => None,
+            Selection::Client(ref source) => Some(source_mime_types(source)),
+            Selection::Compositor { ref mime_types, .. } => Some(mime_types.clone()),
+        }
+    }
+
+    // the forward serving the primary selection, if it has one
+    pub(crate) fn forward(&self) -> Option<&Rc<PrimaryForward>> {
+        match self.0 {
+            Selection::Compositor { ref forward, .. } => forward.as_ref(),
+            _ => None,
+        }
+    }
+
+    // write the primary selection into an fd, which is given back if the callback of
+    // `init_primary_selection` must provide the contents
+    pub(crate) fn serve(&self, mime_type: &str, fd: RawFd) -> Option<RawFd> {
+        match self.0 {
+            Selection::Client(ref source)
+                if source.as_ref().is_alive() && source_mime_types(source).iter().any(|m| m == mime_type) =>
+            {
+                source.send(mime_type.into(), fd);
+            }
+            Selection::Compositor {
+                ref mime_types,
+                ref forward,
+            } if mime_types.iter().any(|m| m == mime_type) => match *forward {
+                Some(ref forward) => {
+                    (&mut *forward.borrow_mut())(mime_type.into(), fd);
+                    return None;
+                }
+                None => return Some(fd),
+            },
+            _ => {}
+        }
+        let _ = ::nix::unistd::close(fd);
+        None
+    }
+}
+
+// Set a compositor primary selection whose contents are provided by `forward`, for the
+// protocols setting the primary selection other than the primary selection protocol itself
+pub(crate) fn set_forwarded_primary_selection(
+    seat: &Seat,
+    mime_types: Vec<String>,
+    forward: Rc<PrimaryForward>,
+) {
+    seat_data(seat).borrow_mut().set_selection(Selection::Compositor {
+        mime_types,
+        forward: Some(forward),
+    });
+}
+
+pub(crate) fn clear_primary_selection(seat: &Seat) {
+    seat_data(seat).borrow_mut().set_selection(Selection::Empty);
+}
+
+// The mime types of the primary selection of this seat, `None` if it is empty
+pub(crate) fn primary_selection_mime_types(seat: &Seat) -> Option<Vec<String>> {
+    primary_selection_snapshot(seat).mime_types()
+}
+
+// The current primary selection of this seat
+pub(crate) fn primary_selection_snapshot(seat: &Seat) -> PrimarySnapshot {
+    let mut seat_data = seat_data(seat).borrow_mut();
+    seat_data.sanitize_selection();
+    PrimarySnapshot(seat_data.selection.clone())
+}
+
+// Write the primary selection into an fd whatever the focus, the fd is given back if the
+// compositor provides the contents of the selection through its callback
+pub(crate) fn serve_primary_selection(seat: &Seat, mime_type: &str, fd: RawFd) -> Option<RawFd> {
+    seat_data(seat).borrow_mut().serve_selection(mime_type, fd)
+}
+
+pub(crate) fn add_primary_selection_listener(seat: &Seat, listener: Rc<PrimaryListener>) {
+    seat_data(seat).borrow_mut().listeners.push(listener);
+}
+
+fn implement_manager<C>(
+    manager: Main<ZwpPrimarySelectionDeviceManagerV1>,
+    callback: Rc<RefCell<C>>,
+    config: Rc<DataDeviceConfig>,
+    log: ::slog::Logger,
+) -> ZwpPrimarySelectionDeviceManagerV1
+where
+    C: FnMut(PrimarySelectionEvent) + 'static,
+{
+    use self::zwp_primary_selection_device_manager_v1::Request;
+    manager.quick_assign(move |_manager, req, _| match req {
+        Request::CreateSource { id } => {
+            implement_source(id, config.max_mime_types, log.clone());
+        }
+        Request::GetDevice { id, seat } => match Seat::from_resource(&seat) {
+            Some(seat) => {
+                // ensure the seat user_data is ready
+                seat.user_data()
+                    .insert_if_missing(|| RefCell::new(SeatData::new(log.clone())));
+                let device =
+                    implement_device(id, seat.clone(), callback.clone(), config.clone(), log.clone());
+                seat.user_data()
+                    .get::<RefCell<SeatData>>()
+                    .unwrap()
+                    .borrow_mut()
+                    .known_devices
+                    .push(device);
+            }
+            None => {
+                error!(log, "Unmanaged seat given to a primary selection device.");
+            }
+        },
+        Request::Destroy => {}
+        _ => unreachable!(),
+    });
+
+    manager.deref().clone()
+}
+
+struct DeviceData {
+    callback: Rc<RefCell<dyn FnMut(PrimarySelectionEvent) + 'static>>,
+    config: Rc<DataDeviceConfig>,
+    // the offers sent to this device, which may still be alive
+    offers: RefCell<Vec<ZwpPrimarySelectionOfferV1>>,
+}
+
+fn implement_device<C>(
+    device: Main<ZwpPrimarySelectionDeviceV1>,
+    seat: Seat,
+    callback: Rc<RefCell<C>>,
+    config: Rc<DataDeviceConfig>,
+    log: ::slog::Logger,
+) -> ZwpPrimarySelectionDeviceV1
+where
+    C: FnMut(PrimarySelectionEvent) + 'static,
+{
+    use self::zwp_primary_selection_device_v1::Request;
+    let device_data = DeviceData {
+        callback: callback.clone(),
+        config: config.clone(),
+        offers: RefCell::new(Vec::new()),
+    };
+    device.quick_assign(move |device, req, _| match req {
+        Request::SetSelection { source, serial } => {
+            if let Some(keyboard) = seat.get_keyboard() {
+                if device
+                    .as_ref()
+                    .client()
+                    .as_ref()
+                    .map(|c| keyboard.has_focus(c))
+                    .unwrap_or(false)
+                {
+                    if config.strict_selection_serial && !selection_serial_valid(&seat, Serial::from(serial))
+                    {
+                        debug!(
+                            log,
+                            "denying setting the primary selection with an invalid serial"
+                        );
+                        if let Some(source) = source {
+                            source.cancelled();
+                        }
+                        return;
+                    }
+                    (&mut *callback.borrow_mut())(PrimarySelectionEvent::NewSelection(source.clone()));
+                    // The client has kbd focus, it can set the selection
+                    seat_data(&seat)
+                        .borrow_mut()
+                        .set_selection(source.map(Selection::Client).unwrap_or(Selection::Empty));
+                    return;
+                }
+            }
+            debug!(
+                log,
+                "denying setting the primary selection by a non-focused client"
+            );
+        }
+        Request::Destroy => {
+            // Clean up the known devices
+            seat_data(&seat)
+                .borrow_mut()
+                .known_devices
+                .retain(|ndd| ndd.as_ref().is_alive() && (!ndd.as_ref().equals(&device.as_ref())))
+        }
+        _ => unreachable!(),
+    });
+    device.as_ref().user_data().set(|| device_data);
+
+    device.deref().clone()
+}
+
+fn implement_source(
+    source: Main<ZwpPrimarySelectionSourceV1>,
+    max_mime_types: usize,
+    log: ::slog::Logger,
+) -> ZwpPrimarySelectionSourceV1 {
+    use self::zwp_primary_selection_source_v1::Request;
+    source.quick_assign(move |me, req, _| {
+        let mime_types = me.as_ref().user_data().get::<RefCell<Vec<String>>>().unwrap();
+        match req {
+            Request::Offer { mime_type } => {
+                let mut mime_types = mime_types.borrow_mut();
+                if accept_offered_mime_type(&mime_types, &mime_type, max_mime_types, &log) {
+                    mime_types.push(mime_type);
+                }
+            }
+            Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    });
+    source
+        .as_ref()
+        .user_data()
+        .set(|| RefCell::new(Vec::<String>::new()));
+
+    source.deref().clone()
+}
+
+// The mime types offered by a client source
+fn source_mime_types(source: &ZwpPrimarySelectionSourceV1) -> Vec<String> {
+    source
+        .as_ref()
+        .user_data()
+        .get::<RefCell<Vec<String>>>()
+        .map(|mime_types| mime_types.borrow().clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::wayland::data_device::test_harness::{
+        create_client, create_seat, received_events, received_offers, send_request,
+    };
+    use crate::wayland::data_device::{selection_mime_types, set_data_device_selection};
+
+    // Create a primary selection device for the seat on behalf of the client
+    fn create_device<C>(seat: &Seat, client: &Client, callback: C) -> ZwpPrimarySelectionDeviceV1
+    where
+        C: FnMut(PrimarySelectionEvent) + 'static,
+    {
+        let device = client.create_resource::<ZwpPrimarySelectionDeviceV1>(1).unwrap();
+        let device = implement_device(
+            device,
+            seat.clone(),
+            Rc::new(RefCell::new(callback)),
+            Rc::new(DataDeviceConfig::default()),
+            ::slog::Logger::root(::slog::Discard, o!()),
+        );
+        seat_data(seat).borrow_mut().known_devices.push(device.clone());
+        device
+    }
+
+    // Create a source on behalf of the client, offering a mime type
+    fn create_source(client: &Client, mime_type: &str) -> ZwpPrimarySelectionSourceV1 {
+        let source = implement_source(
+            client.create_resource::<ZwpPrimarySelectionSourceV1>(1).unwrap(),
+            DataDeviceConfig::default().max_mime_types,
+            ::slog::Logger::root(::slog::Discard, o!()),
+        );
+        source
+            .as_ref()
+            .user_data()
+            .get::<RefCell<Vec<String>>>()
+            .unwrap()
+            .borrow_mut()
+            .push(mime_type.into());
+        source
+    }
+
+    #[test]
+    fn selection_sent_on_focus() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let device = create_device(&seat, &client, |_| {});
+        let source = create_source(&client, "text/plain");
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source));
+
+        set_primary_focus(&seat, Some(client));
+
+        // data_offer is the first event of zwp_primary_selection_device_v1, selection the second
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(device.as_ref().id(), 0)));
+        assert!(events.contains(&(device.as_ref().id(), 1)));
+    }
+
+    #[test]
+    fn superseded_source_cancelled() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let first = create_source(&client, "text/plain");
+        let second = create_source(&client, "text/plain");
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(first.clone()));
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(second.clone()));
+
+        // cancelled is the second event of zwp_primary_selection_source_v1
+        let events = received_events(&mut display, &mut socket);
+        assert!(events.contains(&(first.as_ref().id(), 1)));
+        assert!(!events.contains(&(second.as_ref().id(), 1)));
+    }
+
+    #[test]
+    fn independent_from_clipboard() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, _socket) = create_client(&mut display);
+        let source = create_source(&client, "text/plain");
+        seat_data(&seat)
+            .borrow_mut()
+            .set_selection(Selection::Client(source));
+
+        set_data_device_selection(&seat, vec!["image/png".into()]);
+
+        assert!(matches!(
+            seat_data(&seat).borrow().selection,
+            Selection::Client(_)
+        ));
+        assert_eq!(selection_mime_types(&seat), vec!["image/png".to_string()]);
+    }
+
+    #[test]
+    fn compositor_selection() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let (client, mut socket) = create_client(&mut display);
+        let requested = Rc::new(RefCell::new(Vec::new()));
+        let requested2 = requested.clone();
+        let device = create_device(&seat, &client, move |event| {
+            if let PrimarySelectionEvent::SendSelection { mime_type, fd } = event {
+                requested2.borrow_mut().push(mime_type);
+                let _ = ::nix::unistd::close(fd);
+            }
+        });
+        set_primary_selection(&seat, vec!["text/plain".into()]);
+        set_primary_focus(&seat, Some(client));
+
+        // receive is the first request of zwp_primary_selection_offer_v1
+        let offer = received_offers(&mut display, &mut socket, &[device.as_ref().id()])[0];
+        let (read, write) = ::nix::unistd::pipe().unwrap();
+        send_request(&mut display, &socket, offer, 0, "image/png", write);
+        send_request(&mut display, &socket, offer, 0, "text/plain", write);
+        let _ = ::nix::unistd::close(write);
+        let _ = ::nix::unistd::close(read);
+
+        assert_eq!(*requested.borrow(), vec!["text/plain".to_string()]);
+    }
+}
@@ -2,6 +2,7 @@ use crate::backend::input::KeyState;
 use crate::wayland::Serial;
 use std::{
     cell::RefCell,
+    collections::VecDeque,
     default::Default,
     io::{Error as IoError, Write},
     ops::Deref as _,
@@ -117,6 +118,8 @@ struct KbdInternal {
     repeat_rate: i32,
     repeat_delay: i32,
     focus_hook: Box<dyn FnMut(Option<&WlSurface>)>,
+    // serials of the last key events sent to the focused client
+    key_serials: VecDeque<Serial>,
 }
 
 // This is OK because all parts of `xkb` will remain on the
@@ -158,6 +161,7 @@ impl KbdInternal {
             repeat_rate,
             repeat_delay,
             focus_hook,
+            key_serials: VecDeque::new(),
         })
     }
 
@@ -335,6 +339,9 @@ impl KeyboardHandle {
             KeyState::Pressed => WlKeyState::Pressed,
             KeyState::Released => WlKeyState::Released,
         };
+        if guard.focus.is_some() {
+            super::remember_serial(&mut guard.key_serials, serial);
+        }
         guard.with_focused_kbds(|kbd, _| {
             // key event must be sent before modifers event for libxkbcommon
             // to process them correctly
@@ -373,6 +380,7 @@ impl KeyboardHandle {
 
             // set new focus
             guard.focus = focus.cloned();
+            guard.key_serials.clear();
             let (dep, la, lo, gr) = guard.serialize_modifiers();
             let keys = guard.serialize_pressed_keys();
             guard.with_focused_kbds(|kbd, surface| {
@@ -410,6 +418,19 @@ impl KeyboardHandle {
             .unwrap_or(false)
     }
 
+    /// Check if given serial is the one of a recent key event sent to the currently focused client
+    ///
+    /// Only the last few key events sent since the focus was last set are remembered. This can
+    /// be used to validate that a client request was triggered by a recent user interaction.
+    pub fn has_focus_serial(&self, serial: Serial) -> bool {
+        self.arc.internal.borrow().key_serials.contains(&serial)
+    }
+
+    // The surface currently having the focus of this keyboard
+    pub(crate) fn current_focus(&self) -> Option<WlSurface> {
+        self.arc.internal.borrow().focus.clone()
+    }
+
     /// Register a new keyboard to this handler
     ///
     /// The keymap will automatically be sent to it
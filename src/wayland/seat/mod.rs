@@ -32,18 +32,20 @@
 //!
 //! Once the seat is initialized, you can add capabilities to it.
 //!
-//! Currently, only pointer and keyboard capabilities are supported by
+//! Currently, the pointer, keyboard and touch capabilities are supported by
 //! smithay.
 //!
 //! You can add these capabilities via methods of the [`Seat`](::wayland::seat::Seat) struct:
-//! [`add_keyboard`](::wayland::seat::Seat::add_keyboard), [`add_pointer`](::wayland::seat::Seat::add_pointer).
+//! [`add_keyboard`](::wayland::seat::Seat::add_keyboard), [`add_pointer`](::wayland::seat::Seat::add_pointer),
+//! [`add_touch`](::wayland::seat::Seat::add_touch).
 //! These methods return handles that can be cloned and sent across thread, so you can keep one around
 //! in your event-handling code to forward inputs to your clients.
 
-use std::{cell::RefCell, ops::Deref as _, rc::Rc};
+use std::{cell::RefCell, collections::VecDeque, ops::Deref as _, rc::Rc};
 
 mod keyboard;
 mod pointer;
+mod touch;
 
 pub use self::{
     keyboard::{keysyms, Error as KeyboardError, KeyboardHandle, Keysym, ModifiersState, XkbConfig},
@@ -51,9 +53,13 @@ pub use self::{
         AxisFrame, CursorImageRole, CursorImageStatus, GrabStartData, PointerGrab, PointerHandle,
         PointerInnerHandle,
     },
+    touch::{TouchGrab, TouchGrabStartData, TouchHandle, TouchInnerHandle},
 };
 
-use crate::wayland::compositor::{roles::Role, CompositorToken};
+use crate::wayland::{
+    compositor::{roles::Role, CompositorToken},
+    Serial,
+};
 
 use wayland_server::{
     protocol::{wl_seat, wl_surface},
@@ -63,14 +69,28 @@ use wayland_server::{
 struct Inner {
     pointer: Option<PointerHandle>,
     keyboard: Option<KeyboardHandle>,
+    touch: Option<TouchHandle>,
     known_seats: Vec<wl_seat::WlSeat>,
 }
 
+// How many serials of the input events sent to their focused client the devices remember
+const RECENT_SERIALS: usize = 16;
+
+// Remember the serial of an input event sent to the focused client, forgetting the oldest ones
+fn remember_serial(serials: &mut VecDeque<Serial>, serial: Serial) {
+    if serials.len() == RECENT_SERIALS {
+        serials.pop_front();
+    }
+    serials.push_back(serial);
+}
+
 pub(crate) struct SeatRc {
     inner: RefCell<Inner>,
     user_data: UserDataMap,
+    // closures of smithay tracking the keyboard focus, called after the focus hook
+    keyboard_focus_listeners: RefCell<Vec<Box<dyn FnMut(&Seat, Option<&wl_surface::WlSurface>)>>>,
     pub(crate) log: ::slog::Logger,
-    name: String,
+    pub(crate) name: String,
 }
 
 impl Inner {
@@ -82,6 +102,9 @@ impl Inner {
         if self.keyboard.is_some() {
             caps |= wl_seat::Capability::Keyboard;
         }
+        if self.touch.is_some() {
+            caps |= wl_seat::Capability::Touch;
+        }
         caps
     }
 
@@ -132,11 +155,13 @@ impl Seat {
             inner: RefCell::new(Inner {
                 pointer: None,
                 keyboard: None,
+                touch: None,
                 known_seats: Vec::new(),
             }),
             log: log.new(o!("smithay_module" => "seat_handler", "seat_name" => name.clone())),
             name,
             user_data: UserDataMap::new(),
+            keyboard_focus_listeners: RefCell::new(Vec::new()),
         });
         let seat = Seat { arc: arc.clone() };
         let global = display.create_global(
@@ -289,7 +314,12 @@ impl Seat {
             repeat_delay,
             repeat_rate,
             &self.arc.log,
-            move |focus| focus_hook(&me, focus),
+            move |focus| {
+                focus_hook(&me, focus);
+                for listener in me.arc.keyboard_focus_listeners.borrow_mut().iter_mut() {
+                    listener(&me, focus);
+                }
+            },
         )?;
         if inner.keyboard.is_some() {
             // there is already a keyboard, remove it and notify the clients
@@ -307,6 +337,21 @@ impl Seat {
         self.arc.inner.borrow_mut().keyboard.clone()
     }
 
+    // Register a closure called each time the focus of the keyboard of this seat changes,
+    // after the focus hook provided to `add_keyboard`
+    //
+    // This applies to the keyboards added later as well, and is meant for the other modules
+    // of smithay tracking the keyboard focus.
+    pub(crate) fn add_keyboard_focus_listener<F>(&self, listener: F)
+    where
+        F: FnMut(&Seat, Option<&wl_surface::WlSurface>) + 'static,
+    {
+        self.arc
+            .keyboard_focus_listeners
+            .borrow_mut()
+            .push(Box::new(listener));
+    }
+
     /// Remove the keyboard capability from this seat
     ///
     /// Clients will be appropriately notified.
@@ -318,6 +363,44 @@ impl Seat {
         }
     }
 
+    /// Adds the touch capability to this seat
+    ///
+    /// You are provided a [`TouchHandle`], which allows you to send input events
+    /// to this touch device. This handle can be cloned.
+    ///
+    /// Calling this method on a seat that already has a touch capability
+    /// will overwrite it, and will be seen by the clients as if the
+    /// touchscreen was unplugged and a new one was plugged.
+    pub fn add_touch(&mut self) -> TouchHandle {
+        let mut inner = self.arc.inner.borrow_mut();
+        let touch = self::touch::create_touch_handler();
+        if inner.touch.is_some() {
+            // there is already a touch device, remove it and notify the clients
+            // of the change
+            inner.touch = None;
+            inner.send_all_caps();
+        }
+        inner.touch = Some(touch.clone());
+        inner.send_all_caps();
+        touch
+    }
+
+    /// Access the touch device of this seat if any
+    pub fn get_touch(&self) -> Option<TouchHandle> {
+        self.arc.inner.borrow_mut().touch.clone()
+    }
+
+    /// Remove the touch capability from this seat
+    ///
+    /// Clients will be appropriately notified.
+    pub fn remove_touch(&mut self) {
+        let mut inner = self.arc.inner.borrow_mut();
+        if inner.touch.is_some() {
+            inner.touch = None;
+            inner.send_all_caps();
+        }
+    }
+
     /// Checks whether a given [`WlSeat`](wl_seat::WlSeat) is associated with this [`Seat`]
     pub fn owns(&self, seat: &wl_seat::WlSeat) -> bool {
         let inner = self.arc.inner.borrow_mut();
@@ -361,8 +444,13 @@ where
                     // same as pointer, should error but cannot
                 }
             }
-            wl_seat::Request::GetTouch { .. } => {
-                // TODO
+            wl_seat::Request::GetTouch { id } => {
+                let touch = self::touch::implement_touch(id, inner.touch.as_ref());
+                if let Some(ref touch_handle) = inner.touch {
+                    touch_handle.new_touch(touch);
+                } else {
+                    // same as pointer, should error but cannot
+                }
             }
             wl_seat::Request::Release => {
                 // Our destructors already handle it
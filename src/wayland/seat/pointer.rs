@@ -1,4 +1,4 @@
-use std::{cell::RefCell, ops::Deref as _, rc::Rc};
+use std::{cell::RefCell, collections::VecDeque, ops::Deref as _, rc::Rc};
 
 use wayland_server::{
     protocol::{
@@ -42,6 +42,8 @@ struct PointerInternal {
     location: (f64, f64),
     grab: GrabStatus,
     pressed_buttons: Vec<u32>,
+    // serials of the last button events sent to the focused client, recorded by the grabs
+    button_serials: RefCell<VecDeque<Serial>>,
     image_callback: Box<dyn FnMut(CursorImageStatus)>,
 }
 
@@ -77,6 +79,7 @@ impl PointerInternal {
             location: (0.0, 0.0),
             grab: GrabStatus::None,
             pressed_buttons: Vec::new(),
+            button_serials: RefCell::new(VecDeque::new()),
             image_callback: Box::new(wrapper) as Box<_>,
         }
     }
@@ -94,7 +97,10 @@ impl PointerInternal {
         }
     }
 
-    fn with_grab<F>(&mut self, f: F)
+    // Run the current grab, returning it if it was ended or replaced, so that it can be
+    // dropped once the pointer is released: its destructor may access the pointer
+    #[must_use]
+    fn with_grab<F>(&mut self, f: F) -> Option<GrabStatus>
     where
         F: FnOnce(PointerInnerHandle<'_>, &mut dyn PointerGrab),
     {
@@ -112,6 +118,9 @@ impl PointerInternal {
         if let GrabStatus::Borrowed = self.grab {
             // the grab has not been ended nor replaced, put it back in place
             self.grab = grab;
+            None
+        } else {
+            Some(grab)
         }
     }
 }
@@ -140,12 +149,19 @@ impl PointerHandle {
     ///
     /// Overwrites any current grab.
     pub fn set_grab<G: PointerGrab + 'static>(&self, grab: G, serial: Serial) {
-        self.inner.borrow_mut().grab = GrabStatus::Active(serial, Box::new(grab));
+        let previous = ::std::mem::replace(
+            &mut self.inner.borrow_mut().grab,
+            GrabStatus::Active(serial, Box::new(grab)),
+        );
+        // the previous grab may access the pointer when it is dropped
+        ::std::mem::drop(previous);
     }
 
     /// Remove any current grab on this pointer, reseting it to the default behavior
     pub fn unset_grab(&self) {
-        self.inner.borrow_mut().grab = GrabStatus::None;
+        let previous = ::std::mem::replace(&mut self.inner.borrow_mut().grab, GrabStatus::None);
+        // the previous grab may access the pointer when it is dropped
+        ::std::mem::drop(previous);
     }
 
     /// Check if this pointer is currently grabbed with this serial
@@ -157,6 +173,15 @@ impl PointerHandle {
         }
     }
 
+    /// Check if given serial is the one of a recent button event sent to the currently focused client
+    ///
+    /// Only the last few button events sent since the pointer entered its current focus are
+    /// remembered. This can be used to validate that a client request was triggered by a recent
+    /// user interaction.
+    pub fn has_focus_serial(&self, serial: Serial) -> bool {
+        self.inner.borrow().button_serials.borrow().contains(&serial)
+    }
+
     /// Check if this pointer is currently being grabbed
     pub fn is_grabbed(&self) -> bool {
         let guard = self.inner.borrow_mut();
@@ -193,11 +218,14 @@ impl PointerHandle {
         serial: Serial,
         time: u32,
     ) {
-        let mut inner = self.inner.borrow_mut();
-        inner.pending_focus = focus.clone();
-        inner.with_grab(move |mut handle, grab| {
-            grab.motion(&mut handle, location, focus, serial, time);
-        });
+        let ended = {
+            let mut inner = self.inner.borrow_mut();
+            inner.pending_focus = focus.clone();
+            inner.with_grab(move |mut handle, grab| {
+                grab.motion(&mut handle, location, focus, serial, time);
+            })
+        };
+        ::std::mem::drop(ended);
     }
 
     /// Notify that a button was pressed
@@ -205,28 +233,32 @@ impl PointerHandle {
     /// This will internally send the appropriate button event to the client
     /// objects matching with the currently focused surface.
     pub fn button(&self, button: u32, state: ButtonState, serial: Serial, time: u32) {
-        let mut inner = self.inner.borrow_mut();
-        match state {
-            ButtonState::Pressed => {
-                inner.pressed_buttons.push(button);
-            }
-            ButtonState::Released => {
-                inner.pressed_buttons.retain(|b| *b != button);
+        let ended = {
+            let mut inner = self.inner.borrow_mut();
+            match state {
+                ButtonState::Pressed => {
+                    inner.pressed_buttons.push(button);
+                }
+                ButtonState::Released => {
+                    inner.pressed_buttons.retain(|b| *b != button);
+                }
+                _ => unreachable!(),
             }
-            _ => unreachable!(),
-        }
-        inner.with_grab(|mut handle, grab| {
-            grab.button(&mut handle, button, state, serial, time);
-        });
+            inner.with_grab(|mut handle, grab| {
+                grab.button(&mut handle, button, state, serial, time);
+            })
+        };
+        ::std::mem::drop(ended);
     }
 
     /// Start an axis frame
     ///
     /// A single frame will group multiple scroll events as if they happened in the same instance.
     pub fn axis(&self, details: AxisFrame) {
-        self.inner.borrow_mut().with_grab(|mut handle, grab| {
+        let ended = self.inner.borrow_mut().with_grab(|mut handle, grab| {
             grab.axis(&mut handle, details);
         });
+        ::std::mem::drop(ended);
     }
 
     /// Access the current location of this pointer in the global space
@@ -367,6 +399,7 @@ impl<'a> PointerInnerHandle<'a> {
                 }
             });
             self.inner.focus = None;
+            self.inner.button_serials.borrow_mut().clear();
             (self.inner.image_callback)(CursorImageStatus::Default);
         }
 
@@ -400,6 +433,9 @@ impl<'a> PointerInnerHandle<'a> {
     /// This will internally send the appropriate button event to the client
     /// objects matching with the currently focused surface.
     pub fn button(&self, button: u32, state: ButtonState, serial: Serial, time: u32) {
+        if self.inner.focus.is_some() {
+            super::remember_serial(&mut self.inner.button_serials.borrow_mut(), serial);
+        }
         self.inner.with_focused_pointers(|pointer, _| {
             pointer.button(serial.into(), time, button, state);
             if pointer.as_ref().version() >= 5 {
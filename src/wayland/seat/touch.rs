@@ -0,0 +1,550 @@
+use std::{cell::RefCell, ops::Deref as _, rc::Rc};
+
+use wayland_server::{
+    protocol::{
+        wl_surface::WlSurface,
+        wl_touch::{Request, WlTouch},
+    },
+    Filter, Main,
+};
+
+use crate::wayland::Serial;
+
+enum GrabStatus {
+    None,
+    Active(Serial, Box<dyn TouchGrab>),
+    Borrowed,
+}
+
+// A touch point currently down
+struct TouchPoint {
+    slot: i32,
+    // the surface the point went down on, and the location of its origin
+    focus: Option<(WlSurface, (f64, f64))>,
+    location: (f64, f64),
+}
+
+struct TouchInternal {
+    known_touches: Vec<WlTouch>,
+    points: Vec<TouchPoint>,
+    // the touch objects sent events since the last frame
+    pending_frame: Vec<WlTouch>,
+    grab: GrabStatus,
+}
+
+impl TouchInternal {
+    fn new() -> TouchInternal {
+        TouchInternal {
+            known_touches: Vec::new(),
+            points: Vec::new(),
+            pending_frame: Vec::new(),
+            grab: GrabStatus::None,
+        }
+    }
+
+    fn point(&self, slot: i32) -> Option<&TouchPoint> {
+        self.points.iter().find(|point| point.slot == slot)
+    }
+
+    fn with_focused_touches<F>(&mut self, slot: i32, mut f: F)
+    where
+        F: FnMut(&WlTouch, &WlSurface, (f64, f64)),
+    {
+        let TouchInternal {
+            ref known_touches,
+            ref points,
+            ref mut pending_frame,
+            ..
+        } = *self;
+        let point = match points.iter().find(|point| point.slot == slot) {
+            Some(point) => point,
+            None => return,
+        };
+        if let Some((ref focus, (sx, sy))) = point.focus {
+            let (x, y) = point.location;
+            for touch in known_touches {
+                if touch.as_ref().same_client_as(focus.as_ref()) {
+                    f(touch, focus, (x - sx, y - sy));
+                    if !pending_frame.iter().any(|t| t.as_ref().equals(touch.as_ref())) {
+                        pending_frame.push(touch.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // Run the current grab, returning it if it was ended or replaced, so that it can be
+    // dropped once the touch is released: its destructor may access the touch
+    #[must_use]
+    fn with_grab<F>(&mut self, f: F) -> Option<GrabStatus>
+    where
+        F: FnOnce(TouchInnerHandle<'_>, &mut dyn TouchGrab),
+    {
+        let mut grab = ::std::mem::replace(&mut self.grab, GrabStatus::Borrowed);
+        match grab {
+            GrabStatus::Borrowed => panic!("Accessed a touch grab from within a touch grab access."),
+            GrabStatus::Active(_, ref mut handler) => {
+                f(TouchInnerHandle { inner: self }, &mut **handler);
+            }
+            GrabStatus::None => {
+                f(TouchInnerHandle { inner: self }, &mut DefaultGrab);
+            }
+        }
+
+        if let GrabStatus::Borrowed = self.grab {
+            // the grab has not been ended nor replaced, put it back in place
+            self.grab = grab;
+            None
+        } else {
+            Some(grab)
+        }
+    }
+}
+
+/// An handle to a touch handler
+///
+/// It can be cloned and all clones manipulate the same internal state.
+///
+/// This handle gives you access to an interface to send touch events to your
+/// clients.
+///
+/// When sending events using this handle, they will be intercepted by a touch
+/// grab if any is active. See the [`TouchGrab`] trait for details.
+#[derive(Clone)]
+pub struct TouchHandle {
+    inner: Rc<RefCell<TouchInternal>>,
+}
+
+impl TouchHandle {
+    pub(crate) fn new_touch(&self, touch: WlTouch) {
+        let mut guard = self.inner.borrow_mut();
+        guard.known_touches.push(touch);
+    }
+
+    /// Change the current grab on this touch to the provided grab
+    ///
+    /// Overwrites any current grab.
+    pub fn set_grab<G: TouchGrab + 'static>(&self, grab: G, serial: Serial) {
+        let previous = ::std::mem::replace(
+            &mut self.inner.borrow_mut().grab,
+            GrabStatus::Active(serial, Box::new(grab)),
+        );
+        // the previous grab may access the touch when it is dropped
+        ::std::mem::drop(previous);
+    }
+
+    /// Remove any current grab on this touch, reseting it to the default behavior
+    pub fn unset_grab(&self) {
+        let previous = ::std::mem::replace(&mut self.inner.borrow_mut().grab, GrabStatus::None);
+        // the previous grab may access the touch when it is dropped
+        ::std::mem::drop(previous);
+    }
+
+    /// Check if this touch is currently grabbed with this serial
+    pub fn has_grab(&self, serial: Serial) -> bool {
+        let guard = self.inner.borrow_mut();
+        match guard.grab {
+            GrabStatus::Active(s, _) => s == serial,
+            _ => false,
+        }
+    }
+
+    /// Check if this touch is currently being grabbed
+    pub fn is_grabbed(&self) -> bool {
+        let guard = self.inner.borrow_mut();
+        match guard.grab {
+            GrabStatus::None => false,
+            _ => true,
+        }
+    }
+
+    /// Returns the start data for the grab, if any.
+    pub fn grab_start_data(&self) -> Option<TouchGrabStartData> {
+        let guard = self.inner.borrow();
+        match &guard.grab {
+            GrabStatus::Active(_, g) => Some(g.start_data().clone()),
+            _ => None,
+        }
+    }
+
+    /// Notify that a new touch point went down
+    ///
+    /// You provide the id of the touch point, its location in the global compositor space, and
+    /// the surface under it along with the coordinates of its origin in the global compositor
+    /// space (or `None` if the point is not on top of a client surface).
+    ///
+    /// The surface keeps receiving the events of this touch point until it goes up.
+    pub fn down(
+        &self,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        let ended = self.inner.borrow_mut().with_grab(move |mut handle, grab| {
+            grab.down(&mut handle, slot, location, focus, serial, time);
+        });
+        ::std::mem::drop(ended);
+    }
+
+    /// Notify that a touch point went up
+    pub fn up(&self, slot: i32, serial: Serial, time: u32) {
+        let ended = self.inner.borrow_mut().with_grab(move |mut handle, grab| {
+            grab.up(&mut handle, slot, serial, time);
+        });
+        ::std::mem::drop(ended);
+    }
+
+    /// Notify that a touch point moved
+    ///
+    /// You provide the new location of the touch point in the global compositor space, and the
+    /// surface under it along with the coordinates of its origin, as for [`down`](TouchHandle::down).
+    ///
+    /// The motion is sent to the surface the point went down on, the surface under the point is
+    /// only used by grabs, such as drag'n'drops.
+    pub fn motion(
+        &self,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        let ended = self.inner.borrow_mut().with_grab(move |mut handle, grab| {
+            grab.motion(&mut handle, slot, location, focus, serial, time);
+        });
+        ::std::mem::drop(ended);
+    }
+
+    /// Notify the end of a set of touch events that belong together
+    pub fn frame(&self) {
+        let ended = self.inner.borrow_mut().with_grab(|mut handle, grab| {
+            grab.frame(&mut handle);
+        });
+        ::std::mem::drop(ended);
+    }
+
+    /// Notify that the compositor took over the touch points, which are all cancelled
+    pub fn cancel(&self) {
+        let ended = self.inner.borrow_mut().with_grab(|mut handle, grab| {
+            grab.cancel(&mut handle);
+        });
+        ::std::mem::drop(ended);
+    }
+
+    /// Access the current location of a touch point in the global space
+    ///
+    /// Returns `None` if this touch point is not down.
+    pub fn current_location(&self, slot: i32) -> Option<(f64, f64)> {
+        self.inner.borrow().point(slot).map(|point| point.location)
+    }
+}
+
+/// Data about the event that started the grab.
+#[derive(Clone)]
+pub struct TouchGrabStartData {
+    /// The focused surface and its location, if any, at the start of the grab.
+    ///
+    /// The location coordinates are in the global compositor space.
+    pub focus: Option<(WlSurface, (f64, f64))>,
+    /// The id of the touch point that initiated the grab.
+    pub slot: i32,
+    /// The location of the touch point that initiated the grab, in the global compositor space.
+    pub location: (f64, f64),
+}
+
+/// A trait to implement a touch grab
+///
+/// In some context, it is necessary to temporarily change the behavior of the touch. This is
+/// typically known as a touch grab. A typical example would be, during a drag'n'drop operation,
+/// the underlying surfaces will no longer receive classic touch event, but rather special events.
+///
+/// This trait is the interface to intercept regular touch events and change them as needed, its
+/// interface mimics the [`TouchHandle`] interface.
+///
+/// If your logic decides that the grab should end, both [`TouchInnerHandle`] and [`TouchHandle`] have
+/// a method to change it.
+///
+/// When your grab ends (either as you requested it or if it was forcefully cancelled by the server),
+/// the struct implementing this trait will be dropped. As such you should put clean-up logic in the destructor,
+/// rather than trying to guess when the grab will end.
+pub trait TouchGrab {
+    /// A touch point went down
+    fn down(
+        &mut self,
+        handle: &mut TouchInnerHandle<'_>,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    );
+    /// A touch point went up
+    fn up(&mut self, handle: &mut TouchInnerHandle<'_>, slot: i32, serial: Serial, time: u32);
+    /// A touch point moved
+    fn motion(
+        &mut self,
+        handle: &mut TouchInnerHandle<'_>,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    );
+    /// A set of touch events ended
+    fn frame(&mut self, handle: &mut TouchInnerHandle<'_>);
+    /// The touch points were cancelled
+    fn cancel(&mut self, handle: &mut TouchInnerHandle<'_>);
+    /// The data about the event that started the grab.
+    fn start_data(&self) -> &TouchGrabStartData;
+}
+
+/// This inner handle is accessed from inside a touch grab logic, and directly
+/// sends event to the client
+pub struct TouchInnerHandle<'a> {
+    inner: &'a mut TouchInternal,
+}
+
+impl<'a> TouchInnerHandle<'a> {
+    /// Change the current grab on this touch to the provided grab
+    ///
+    /// Overwrites any current grab.
+    pub fn set_grab<G: TouchGrab + 'static>(&mut self, serial: Serial, grab: G) {
+        self.inner.grab = GrabStatus::Active(serial, Box::new(grab));
+    }
+
+    /// Remove any current grab on this touch, resetting it to the default behavior
+    pub fn unset_grab(&mut self) {
+        self.inner.grab = GrabStatus::None;
+    }
+
+    /// Access the focus of a touch point, if it is down
+    pub fn current_focus(&self, slot: i32) -> Option<&(WlSurface, (f64, f64))> {
+        self.inner.point(slot).and_then(|point| point.focus.as_ref())
+    }
+
+    /// Access the current location of a touch point in the global space, if it is down
+    pub fn current_location(&self, slot: i32) -> Option<(f64, f64)> {
+        self.inner.point(slot).map(|point| point.location)
+    }
+
+    /// Whether no touch point is down anymore
+    pub fn is_released(&self) -> bool {
+        self.inner.points.is_empty()
+    }
+
+    /// Notify that a touch point went down
+    ///
+    /// The point is tracked even without focus, so that grabs can follow it.
+    pub fn down(
+        &mut self,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        self.inner.points.retain(|point| point.slot != slot);
+        self.inner.points.push(TouchPoint {
+            slot,
+            focus,
+            location,
+        });
+        self.inner.with_focused_touches(slot, |touch, surface, (x, y)| {
+            touch.down(serial.into(), time, surface, slot, x, y);
+        });
+    }
+
+    /// Notify that a touch point went up
+    pub fn up(&mut self, slot: i32, serial: Serial, time: u32) {
+        self.inner.with_focused_touches(slot, |touch, _, _| {
+            touch.up(serial.into(), time, slot);
+        });
+        self.inner.points.retain(|point| point.slot != slot);
+    }
+
+    /// Notify that a touch point moved
+    pub fn motion(&mut self, slot: i32, location: (f64, f64), time: u32) {
+        match self.inner.points.iter_mut().find(|point| point.slot == slot) {
+            Some(point) => point.location = location,
+            None => return,
+        }
+        self.inner.with_focused_touches(slot, |touch, _, (x, y)| {
+            touch.motion(time, slot, x, y);
+        });
+    }
+
+    /// Notify the end of a set of touch events to the clients that received them
+    pub fn frame(&mut self) {
+        for touch in self.inner.pending_frame.drain(..) {
+            if touch.as_ref().is_alive() {
+                touch.frame();
+            }
+        }
+    }
+
+    /// Notify the clients that their touch points were cancelled, and forget them
+    pub fn cancel(&mut self) {
+        let TouchInternal {
+            ref known_touches,
+            ref mut points,
+            ref mut pending_frame,
+            ..
+        } = *self.inner;
+        for touch in known_touches {
+            let focused = points.iter().any(|point| match point.focus {
+                Some((ref focus, _)) => touch.as_ref().same_client_as(focus.as_ref()),
+                None => false,
+            });
+            if focused {
+                touch.cancel();
+            }
+        }
+        points.clear();
+        pending_frame.clear();
+    }
+
+    /// Forget a touch point, without sending anything to the clients
+    ///
+    /// This is meant for grabs that intercept the events of a touch point, and do not want the
+    /// grabs that follow to see it as still down after it went up.
+    pub fn forget(&mut self, slot: i32) {
+        self.inner.points.retain(|point| point.slot != slot);
+    }
+}
+
+pub(crate) fn create_touch_handler() -> TouchHandle {
+    TouchHandle {
+        inner: Rc::new(RefCell::new(TouchInternal::new())),
+    }
+}
+
+pub(crate) fn implement_touch(touch: Main<WlTouch>, handle: Option<&TouchHandle>) -> WlTouch {
+    touch.quick_assign(|_touch, request, _data| match request {
+        Request::Release => {
+            // Our destructors already handle it
+        }
+        _ => unreachable!(),
+    });
+
+    if let Some(h) = handle {
+        let inner = h.inner.clone();
+        touch.assign_destructor(Filter::new(move |touch: WlTouch, _, _| {
+            let mut guard = inner.borrow_mut();
+            guard
+                .known_touches
+                .retain(|t| !t.as_ref().equals(&touch.as_ref()));
+            guard
+                .pending_frame
+                .retain(|t| !t.as_ref().equals(&touch.as_ref()));
+        }))
+    }
+
+    touch.deref().clone()
+}
+
+/*
+ * Grabs definition
+ */
+
+// The default grab, the behavior when no particular grab is in progress
+struct DefaultGrab;
+
+impl TouchGrab for DefaultGrab {
+    fn down(
+        &mut self,
+        handle: &mut TouchInnerHandle<'_>,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        handle.down(slot, location, focus.clone(), serial, time);
+        handle.set_grab(
+            serial,
+            TouchDownGrab {
+                start_data: TouchGrabStartData {
+                    focus,
+                    slot,
+                    location,
+                },
+            },
+        );
+    }
+    fn up(&mut self, handle: &mut TouchInnerHandle<'_>, slot: i32, serial: Serial, time: u32) {
+        handle.up(slot, serial, time);
+    }
+    fn motion(
+        &mut self,
+        handle: &mut TouchInnerHandle<'_>,
+        slot: i32,
+        location: (f64, f64),
+        _focus: Option<(WlSurface, (f64, f64))>,
+        _serial: Serial,
+        time: u32,
+    ) {
+        handle.motion(slot, location, time);
+    }
+    fn frame(&mut self, handle: &mut TouchInnerHandle<'_>) {
+        handle.frame();
+    }
+    fn cancel(&mut self, handle: &mut TouchInnerHandle<'_>) {
+        handle.cancel();
+    }
+    fn start_data(&self) -> &TouchGrabStartData {
+        unreachable!()
+    }
+}
+
+// A touch down grab, basic grab started when an user touches a surface, lasting until all
+// the touch points are up
+//
+// Its serial is the one of the first touch point going down, which clients use to start
+// interactions such as drag'n'drops.
+struct TouchDownGrab {
+    start_data: TouchGrabStartData,
+}
+
+impl TouchGrab for TouchDownGrab {
+    fn down(
+        &mut self,
+        handle: &mut TouchInnerHandle<'_>,
+        slot: i32,
+        location: (f64, f64),
+        focus: Option<(WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        handle.down(slot, location, focus, serial, time);
+    }
+    fn up(&mut self, handle: &mut TouchInnerHandle<'_>, slot: i32, serial: Serial, time: u32) {
+        handle.up(slot, serial, time);
+        if handle.is_released() {
+            // no more touch points are down, release the grab
+            handle.unset_grab();
+        }
+    }
+    fn motion(
+        &mut self,
+        handle: &mut TouchInnerHandle<'_>,
+        slot: i32,
+        location: (f64, f64),
+        _focus: Option<(WlSurface, (f64, f64))>,
+        _serial: Serial,
+        time: u32,
+    ) {
+        handle.motion(slot, location, time);
+    }
+    fn frame(&mut self, handle: &mut TouchInnerHandle<'_>) {
+        handle.frame();
+    }
+    fn cancel(&mut self, handle: &mut TouchInnerHandle<'_>) {
+        handle.cancel();
+        handle.unset_grab();
+    }
+    fn start_data(&self) -> &TouchGrabStartData {
+        &self.start_data
+    }
+}
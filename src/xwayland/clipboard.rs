@@ -0,0 +1,467 @@
+//! Sharing the clipboard between Wayland and X11 clients
+//!
+//! X11 clients do not use the data device, but the `CLIPBOARD` selection of the X server.
+//! Your window manager has to bridge both, and the helpers of this module handle the Wayland
+//! side of it, independently of the X11 library you use for your window manager connection.
+//! Selection targets are thus identified by the names of their atoms, that you need to
+//! intern and resolve with your connection.
+//!
+//! To export the Wayland selection to X11 clients, create a [`ClipboardExport`]. It notifies
+//! you when your window manager must take ownership of the `CLIPBOARD` selection, or let it
+//! go. Then answer the `SelectionRequest` events your window manager receives with
+//! [`ClipboardExport::handle_request`]. It provides you with what to store in the requested
+//! property of the requestor window before sending it the `SelectionNotify` event.
+//!
+//! To import the `CLIPBOARD` selection of X11 clients into the Wayland selection, create a
+//! [`ClipboardImport`], and notify it when an X11 client takes ownership of the `CLIPBOARD`
+//! selection, which your window manager can track using the `XFIXES` extension. The targets it
+//! offers are advertised to your Wayland clients, and each time they are requested, you are asked
+//! to convert the selection. Selections imported this way are not exported back by the
+//! [`ClipboardExport`] of the same seat.
+//!
+//! Both use a [`TargetMapping`] to translate between X11 targets and mime types.
+
+use std::{
+    cell::{Cell, RefCell},
+    os::unix::io::RawFd,
+    rc::Rc,
+};
+
+use crate::wayland::{
+    data_device::{
+        add_selection_listener, clear_data_device_selection, install_remote_selection, read_selection,
+        selection_mime_types, selection_snapshot, selections_equal, serve_bytes, SelectionReader,
+        SelectionSnapshot,
+    },
+    seat::Seat,
+};
+
+/// The mapping between X11 selection targets and mime types
+///
+/// X11 clients usually request text using targets that are not mime types, like `UTF8_STRING`
+/// or `STRING`. Each entry of the mapping associates such a target with the mime type it
+/// corresponds to. Targets containing a `/` are considered to be mime types and are never
+/// mapped.
+///
+/// Its [`Default`] implementation maps `UTF8_STRING`, `STRING` and `TEXT` to
+/// `text/plain;charset=utf-8`.
+#[derive(Debug, Clone)]
+pub struct TargetMapping {
+    entries: Vec<(String, String)>,
+}
+
+impl Default for TargetMapping {
+    fn default() -> TargetMapping {
+        let text = "text/plain;charset=utf-8";
+        TargetMapping {
+            entries: vec![
+                ("UTF8_STRING".into(), text.into()),
+                ("STRING".into(), text.into()),
+                ("TEXT".into(), text.into()),
+            ],
+        }
+    }
+}
+
+impl TargetMapping {
+    /// Map an X11 target to a mime type
+    ///
+    /// This replaces any previous mapping of this target.
+    pub fn insert(&mut self, target: String, mime_type: String) {
+        match self.entries.iter_mut().find(|entry| entry.0 == target) {
+            Some(entry) => entry.1 = mime_type,
+            None => self.entries.push((target, mime_type)),
+        }
+    }
+
+    /// The mime type corresponding to an X11 target
+    ///
+    /// Returns `None` if the target is neither mapped nor a mime type.
+    pub fn mime_type<'a>(&'a self, target: &'a str) -> Option<&'a str> {
+        if target.contains('/') {
+            return Some(target);
+        }
+        self.entries
+            .iter()
+            .find(|&&(ref t, _)| t == target)
+            .map(|&(_, ref mime_type)| &mime_type[..])
+    }
+
+    /// The X11 targets to advertise for a list of mime types, including `TARGETS`
+    ///
+    /// Each mime type is advertised as is, followed by the targets mapped to it.
+    pub fn targets(&self, mime_types: &[String]) -> Vec<String> {
+        let mut targets = vec!["TARGETS".to_string()];
+        for mime_type in mime_types {
+            let mapped = self
+                .entries
+                .iter()
+                .filter(|&&(_, ref m)| m == mime_type)
+                .map(|&(ref target, _)| target);
+            for target in ::std::iter::once(mime_type).chain(mapped) {
+                if !targets.contains(target) {
+                    targets.push(target.clone());
+                }
+            }
+        }
+        targets
+    }
+
+    /// The mime types to advertise for a list of X11 targets
+    ///
+    /// Targets that are neither mapped nor mime types, like `TARGETS` or `TIMESTAMP`, are
+    /// ignored.
+    pub fn mime_types(&self, targets: &[String]) -> Vec<String> {
+        let mut mime_types: Vec<String> = Vec::new();
+        for mime_type in targets.iter().filter_map(|target| self.mime_type(target)) {
+            if !mime_types.iter().any(|m| m == mime_type) {
+                mime_types.push(mime_type.into());
+            }
+        }
+        mime_types
+    }
+
+    // The target to request from an X11 selection offering these targets, for a mime type
+    fn target_for<'a>(&self, targets: &'a [String], mime_type: &str) -> Option<&'a String> {
+        targets.iter().find(|target| *target == mime_type).or_else(|| {
+            targets
+                .iter()
+                .find(|target| self.mime_type(target) == Some(mime_type))
+        })
+    }
+}
+
+// Whether the selection of a seat is being imported from X11, shared between the import and
+// the export of the seat
+#[derive(Default)]
+struct ImportMarker {
+    importing: Cell<bool>,
+    imported: RefCell<Option<SelectionSnapshot>>,
+}
+
+fn import_marker(seat: &Seat) -> Rc<ImportMarker> {
+    seat.user_data()
+        .insert_if_missing(|| Rc::new(ImportMarker::default()));
+    seat.user_data().get::<Rc<ImportMarker>>().unwrap().clone()
+}
+
+/// How to answer a `SelectionRequest` of an X11 client
+pub enum ClipboardReply {
+    /// Store these targets in the property, as an `ATOM` array
+    Targets(Vec<String>),
+    /// Store the data read from this reader in the property, with the requested target as type
+    ///
+    /// The reader provides the data as the source of the Wayland selection writes them, see
+    /// [`read_selection`](crate::wayland::data_device::read_selection). Data larger than the
+    /// maximum request size of the X server needs to be transferred using the `INCR` mechanism.
+    Data(SelectionReader),
+    /// Refuse the request, by answering with a `None` property
+    Refuse,
+}
+
+/// Exports the Wayland selection of a seat to the `CLIPBOARD` selection of X11 clients
+pub struct ClipboardExport {
+    seat: Seat,
+    mapping: TargetMapping,
+}
+
+impl ClipboardExport {
+    /// Start exporting the selection of this seat
+    ///
+    /// The `owner_changed` callback is invoked each time the selection of the seat changes. It
+    /// receives `true` when the selection was set, your window manager must then take
+    /// ownership of the `CLIPBOARD` selection, and `false` when it was cleared, your window
+    /// manager must then release it.
+    ///
+    /// The callback is not invoked for selections imported by a [`ClipboardImport`] of this
+    /// seat, and stays registered for the lifetime of the seat.
+    pub fn new<F>(seat: &Seat, mapping: TargetMapping, owner_changed: F) -> ClipboardExport
+    where
+        F: FnMut(bool) + 'static,
+    {
+        let owner_changed = Rc::new(RefCell::new(owner_changed));
+        let marker = import_marker(seat);
+        add_selection_listener(
+            seat,
+            Rc::new(move |selection: &SelectionSnapshot| {
+                // the selection comes from X11 in the first place
+                if marker.importing.get() {
+                    return;
+                }
+                (&mut *owner_changed.borrow_mut())(!selection.is_empty())
+            }),
+        );
+        ClipboardExport {
+            seat: seat.clone(),
+            mapping,
+        }
+    }
+
+    /// Answer a `SelectionRequest` for the `CLIPBOARD` selection, for this target
+    pub fn handle_request(&self, target: &str) -> ClipboardReply {
+        let mime_types = selection_mime_types(&self.seat);
+        if mime_types.is_empty() {
+            return ClipboardReply::Refuse;
+        }
+        if target == "TARGETS" {
+            return ClipboardReply::Targets(self.mapping.targets(&mime_types));
+        }
+        let mime_type = match self.mapping.mime_type(target) {
+            Some(mime_type) => mime_type,
+            None => return ClipboardReply::Refuse,
+        };
+        match read_selection(&self.seat, mime_type) {
+            Ok(reader) => ClipboardReply::Data(reader),
+            Err(_) => ClipboardReply::Refuse,
+        }
+    }
+}
+
+/// The identifier of a transfer of the X11 `CLIPBOARD` selection to a Wayland client
+///
+/// See [`ClipboardImport`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TransferId(u64);
+
+// The transfers waiting for the conversion of the selection by its X11 owner
+#[derive(Default)]
+struct PendingTransfers {
+    next_id: u64,
+    transfers: Vec<(TransferId, RawFd, Vec<u8>)>,
+}
+
+impl PendingTransfers {
+    fn cancel_all(&mut self) {
+        for (_, fd, _) in self.transfers.drain(..) {
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
+}
+
+/// Imports the `CLIPBOARD` selection of X11 clients into the Wayland selection of a seat
+///
+/// Each time a Wayland client requests the imported selection, your `convert` callback is given
+/// the X11 target to convert the selection to, along with an identifier for this transfer. Your
+/// window manager must then issue a `ConvertSelection` request, and provide the resulting
+/// property data with [`transfer_data`](ClipboardImport::transfer_data), as many times as needed
+/// for `INCR` transfers, followed by [`transfer_finished`](ClipboardImport::transfer_finished),
+/// or [`transfer_failed`](ClipboardImport::transfer_failed) if the conversion was refused.
+pub struct ClipboardImport {
+    seat: Seat,
+    mapping: TargetMapping,
+    convert: Rc<RefCell<dyn FnMut(TransferId, &str)>>,
+    pending: Rc<RefCell<PendingTransfers>>,
+    log: ::slog::Logger,
+}
+
+impl ClipboardImport {
+    /// Create the import of the `CLIPBOARD` selection for this seat
+    pub fn new<F, L>(seat: &Seat, mapping: TargetMapping, convert: F, logger: L) -> ClipboardImport
+    where
+        F: FnMut(TransferId, &str) + 'static,
+        L: Into<Option<::slog::Logger>>,
+    {
+        ClipboardImport {
+            seat: seat.clone(),
+            mapping,
+            convert: Rc::new(RefCell::new(convert)),
+            pending: Rc::new(RefCell::new(PendingTransfers::default())),
+            log: crate::slog_or_fallback(logger).new(o!("smithay_module" => "xwayland_clipboard")),
+        }
+    }
+
+    /// An X11 client took ownership of the `CLIPBOARD` selection, offering these targets
+    ///
+    /// The targets are the ones your window manager retrieved by converting the selection to
+    /// `TARGETS`. The mime types they map to become the Wayland selection of the seat, the
+    /// transfers still pending for the previous owner are cancelled.
+    pub fn owner_changed(&self, targets: Vec<String>) {
+        self.pending.borrow_mut().cancel_all();
+        let mime_types = self.mapping.mime_types(&targets);
+        if mime_types.is_empty() {
+            debug!(self.log, "Ignoring an X11 selection without any supported target");
+            return;
+        }
+        let mapping = self.mapping.clone();
+        let convert = self.convert.clone();
+        let pending = self.pending.clone();
+        let log = self.log.clone();
+        let marker = import_marker(&self.seat);
+        marker.importing.set(true);
+        install_remote_selection(&self.seat, mime_types, move |mime_type, fd| {
+            let target = match mapping.target_for(&targets, &mime_type) {
+                Some(target) => target,
+                None => {
+                    debug!(log, "No X11 target for the requested mime type"; "mime_type" => mime_type);
+                    let _ = ::nix::unistd::close(fd);
+                    return;
+                }
+            };
+            let id = {
+                let mut pending = pending.borrow_mut();
+                let id = TransferId(pending.next_id);
+                pending.next_id += 1;
+                pending.transfers.push((id, fd, Vec::new()));
+                id
+            };
+            (&mut *convert.borrow_mut())(id, target);
+        });
+        marker.importing.set(false);
+        *marker.imported.borrow_mut() = Some(selection_snapshot(&self.seat));
+    }
+
+    /// The X11 owner of the `CLIPBOARD` selection is gone
+    ///
+    /// If the Wayland selection of the seat is still the imported one it is cleared, and all the
+    /// pending transfers are cancelled: the Wayland clients waiting for them only receive the
+    /// data provided so far.
+    pub fn owner_gone(&self) {
+        self.pending.borrow_mut().cancel_all();
+        let marker = import_marker(&self.seat);
+        let imported = marker.imported.borrow_mut().take();
+        if let Some(imported) = imported {
+            if selections_equal(&imported, &selection_snapshot(&self.seat)) {
+                marker.importing.set(true);
+                clear_data_device_selection(&self.seat);
+                marker.importing.set(false);
+            }
+        }
+    }
+
+    /// Provide some of the data converted by the X11 owner for a transfer
+    ///
+    /// The data is only sent to the Wayland client once the transfer is finished.
+    pub fn transfer_data(&self, id: TransferId, data: &[u8]) {
+        let mut pending = self.pending.borrow_mut();
+        if let Some(&mut (_, _, ref mut buffer)) = pending.transfers.iter_mut().find(|t| t.0 == id) {
+            buffer.extend_from_slice(data);
+        }
+    }
+
+    /// The X11 owner finished converting the selection for a transfer
+    pub fn transfer_finished(&self, id: TransferId) {
+        let mut pending = self.pending.borrow_mut();
+        if let Some(index) = pending.transfers.iter().position(|t| t.0 == id) {
+            let (_, fd, data) = pending.transfers.remove(index);
+            serve_bytes(fd, data, &self.log);
+        }
+    }
+
+    /// The X11 owner refused to convert the selection for a transfer
+    ///
+    /// The Wayland client does not receive any data.
+    pub fn transfer_failed(&self, id: TransferId) {
+        let mut pending = self.pending.borrow_mut();
+        if let Some(index) = pending.transfers.iter().position(|t| t.0 == id) {
+            let (_, fd, _) = pending.transfers.remove(index);
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
+}
+
+impl Drop for ClipboardImport {
+    fn drop(&mut self) {
+        self.pending.borrow_mut().cancel_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use wayland_server::Display;
+
+    use crate::wayland::data_device::test_harness::create_seat;
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn text_targets() {
+        let mapping = TargetMapping::default();
+        assert_eq!(mapping.mime_type("UTF8_STRING"), Some("text/plain;charset=utf-8"));
+        assert_eq!(mapping.mime_type("image/png"), Some("image/png"));
+        assert_eq!(mapping.mime_type("PIXMAP"), None);
+        assert_eq!(
+            mapping.targets(&strings(&["text/plain;charset=utf-8", "image/png"])),
+            strings(&[
+                "TARGETS",
+                "text/plain;charset=utf-8",
+                "UTF8_STRING",
+                "STRING",
+                "TEXT",
+                "image/png"
+            ])
+        );
+    }
+
+    #[test]
+    fn extended_mapping() {
+        let mut mapping = TargetMapping::default();
+        mapping.insert("PNG".into(), "image/png".into());
+        mapping.insert("STRING".into(), "text/plain".into());
+        assert_eq!(
+            mapping.mime_types(&strings(&[
+                "TARGETS",
+                "UTF8_STRING",
+                "STRING",
+                "PNG",
+                "image/png"
+            ])),
+            strings(&["text/plain;charset=utf-8", "text/plain", "image/png"])
+        );
+        let targets = strings(&["TARGETS", "PNG"]);
+        assert_eq!(mapping.target_for(&targets, "image/png"), Some(&targets[1]));
+    }
+
+    #[test]
+    fn import_x11_selection() {
+        let mut display = Display::new();
+        let (seat, _) = create_seat(&mut display);
+        let exported = Rc::new(Cell::new(0));
+        let exported2 = exported.clone();
+        let _export = ClipboardExport::new(&seat, TargetMapping::default(), move |_| {
+            exported2.set(exported2.get() + 1)
+        });
+        let conversions = Rc::new(RefCell::new(Vec::new()));
+        let conversions2 = conversions.clone();
+        let import = ClipboardImport::new(
+            &seat,
+            TargetMapping::default(),
+            move |id, target: &str| conversions2.borrow_mut().push((id, target.to_string())),
+            None,
+        );
+
+        // an X11 client copies some text
+        import.owner_changed(strings(&["TARGETS", "TIMESTAMP", "UTF8_STRING", "STRING"]));
+        assert_eq!(
+            selection_mime_types(&seat),
+            strings(&["text/plain;charset=utf-8"])
+        );
+        // the imported selection is not exported back to X11
+        assert_eq!(exported.get(), 0);
+
+        // a Wayland client pastes it, the data is converted in two parts
+        let mut reader = read_selection(&seat, "text/plain;charset=utf-8").unwrap();
+        let (id, target) = conversions.borrow_mut().pop().unwrap();
+        assert_eq!(target, "UTF8_STRING");
+        import.transfer_data(id, b"Hello ");
+        import.transfer_data(id, b"X11");
+        import.transfer_finished(id);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"Hello X11");
+
+        // the X11 client disappears during a transfer
+        let mut reader = read_selection(&seat, "text/plain;charset=utf-8").unwrap();
+        import.owner_gone();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert!(data.is_empty());
+        assert!(selection_mime_types(&seat).is_empty());
+        assert_eq!(exported.get(), 0);
+    }
+}
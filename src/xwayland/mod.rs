@@ -11,8 +11,11 @@
 //! to treat XWayland (and all its X11 apps) as one special client, and play the role of
 //! an X11 Window Manager.
 //!
-//! Smithay does not provide any helper for doing that yet, but it is planned.
+//! Smithay does not provide any helper for doing that yet, but it is planned. The
+//! [`clipboard`](clipboard/index.html) module however helps you share the clipboard of your
+//! Wayland clients with X11 clients.
 
+pub mod clipboard;
 mod x11_sockets;
 mod xserver;
 